@@ -0,0 +1,300 @@
+use serialize::json::{Json, ToJson};
+use std::future::{self, Future};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use crate::credentials::Credentials;
+use crate::error::Result;
+use crate::paginator::{JsonPageSpec, PageStream, QueryPageSpec, RestPageSpec};
+use crate::protocol::query::{QueryResponse, ToQuery};
+use crate::protocol::rest::RestRequest;
+use crate::request::{self, ApiClient, ApiRequest};
+use crate::response::HttpResponse;
+use crate::runtime::{self, Task};
+use crate::xml::Element;
+
+pub use crate::runtime::BoxFuture;
+
+// Supplies credentials for each operation, so they can be fetched or
+// refreshed without blocking the caller.
+pub trait ProvideCredentials: Send + Sync {
+    fn provide(&self) -> BoxFuture<Result<Credentials>>;
+}
+
+impl ProvideCredentials for Credentials {
+    fn provide(&self) -> BoxFuture<Result<Credentials>> {
+        Box::pin(future::ready(Ok(self.clone())))
+    }
+}
+
+// Reads a profile from the credentials file off the calling thread, every
+// time it's asked, so edits to the file are picked up. A missing file,
+// profile or key is an error.
+pub struct ProfileProvider {
+    credentials: Credentials,
+}
+
+impl ProfileProvider {
+    pub fn new(credentials: Credentials) -> ProfileProvider {
+        ProfileProvider { credentials }
+    }
+}
+
+impl ProvideCredentials for ProfileProvider {
+    fn provide(&self) -> BoxFuture<Result<Credentials>> {
+        let creds = self.credentials.clone();
+        Box::pin(runtime::spawn_blocking(move || creds.try_load()))
+    }
+}
+
+// One operation, running on the shared runtime: credentials, then attempts
+// separated by rate limiting and retry backoff, then the protocol's response
+// handling. It can be awaited from any executor; dropping it cancels the
+// operation.
+#[derive(Debug)]
+pub struct Operation<T> {
+    task: Task<Result<T>>,
+}
+
+impl<T: Send + 'static> Operation<T> {
+    fn spawn<F>(future: F) -> Operation<T> where F: Future<Output = Result<T>> + Send + 'static {
+        Operation { task: runtime::spawn(future) }
+    }
+}
+
+impl<T> Future for Operation<T> {
+    type Output = Result<T>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<T>> {
+        Pin::new(&mut self.task).poll(cx)
+    }
+}
+
+// The non-blocking counterpart of ApiClient. Requests are built, signed and
+// parsed by the wrapped ApiClient; only the waiting is different.
+#[derive(Clone)]
+pub struct AsyncApiClient {
+    client: ApiClient,
+    credentials: Option<Arc<dyn ProvideCredentials>>,
+}
+
+impl AsyncApiClient {
+    pub fn new(client: ApiClient) -> AsyncApiClient {
+        AsyncApiClient { client, credentials: None }
+    }
+
+    // Without a provider, the credentials the ApiClient was built with are used.
+    pub fn credentials_provider<P: ProvideCredentials + 'static>(mut self, provider: P) -> AsyncApiClient {
+        self.credentials = Some(Arc::new(provider));
+        self
+    }
+
+    pub fn blocking(&self) -> &ApiClient {
+        &self.client
+    }
+
+    pub fn send(&self, req: ApiRequest) -> Operation<HttpResponse> {
+        self.operation(req, Ok)
+    }
+
+    pub fn query<T: ToQuery>(&self, action: &str, input: &T) -> Operation<QueryResponse> {
+        let action = action.to_string();
+        self.operation(self.client.query_request(&action, input), move |res| request::query_result(&action, res))
+    }
+
+    pub fn json<T: ToJson>(&self, operation: &str, input: &T) -> Operation<Json> {
+        match self.client.json_request(operation, input) {
+            Ok(req) => self.operation(req, request::json_result),
+            Err(e) => Operation::spawn(future::ready(Err(e))),
+        }
+    }
+
+    pub fn rest(&self, req: &RestRequest) -> Operation<HttpResponse> {
+        match self.client.rest_request(req) {
+            Ok((req, protocol)) => self.operation(req, move |res| request::rest_result(res, protocol)),
+            Err(e) => Operation::spawn(future::ready(Err(e))),
+        }
+    }
+
+    // The paginate methods are ApiClient's as streams, which fetch each page
+    // when the previous one has been taken.
+    pub fn paginate<T: ToQuery>(&self, action: &str, input: &T, spec: QueryPageSpec) -> PageStream<Element> {
+        let client = self.clone();
+        let action = action.to_string();
+        let params = self.client.query_params(&action, input);
+        PageStream::new(move |token: Option<String>| {
+            let req = ApiRequest::new("POST").operation(&action).form(&spec.params(&params, token.as_deref()));
+            let action = action.clone();
+            let page = client.operation(req, move |res| request::query_result(&action, res));
+            let spec = spec.clone();
+            Box::pin(async move { Ok(spec.page(&page.await?)) })
+        })
+    }
+
+    pub fn paginate_rest(&self, req: &RestRequest, spec: RestPageSpec) -> PageStream<Element> {
+        let client = self.clone();
+        let req = req.clone();
+        PageStream::new(move |token: Option<String>| {
+            let page = client.rest(&spec.request(&req, token.as_deref()));
+            let spec = spec.clone();
+            Box::pin(async move { spec.page(&page.await?) })
+        })
+    }
+
+    pub fn paginate_json(&self, operation: &str, input: &Json, spec: JsonPageSpec) -> PageStream<Json> {
+        let client = self.clone();
+        let operation = operation.to_string();
+        let input = input.clone();
+        PageStream::new(move |token: Option<String>| {
+            let page = spec.input(&input, token.as_deref()).map(|input| client.json(&operation, &input));
+            let spec = spec.clone();
+            Box::pin(async move { Ok(spec.page(&page?.await?)) })
+        })
+    }
+
+    fn operation<T, F>(&self, req: ApiRequest, parse: F) -> Operation<T>
+        where T: Send + 'static, F: FnOnce(HttpResponse) -> Result<T> + Send + 'static
+    {
+        let client = self.client.clone();
+        let provider = self.credentials.clone();
+        Operation::spawn(async move {
+            let client = match provider {
+                Some(provider) => client.credentials(provider.provide().await?),
+                None => client,
+            };
+            client.execute(req).await.and_then(parse)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AsyncApiClient, ProfileProvider, ProvideCredentials, BoxFuture};
+    use crate::credentials::Credentials;
+    use crate::error::{Error, Result};
+    use crate::paginator::{JsonPageSpec, QueryPageSpec};
+    use serialize::json::Json;
+    use crate::request::ApiClient;
+    use crate::retry::RetryPolicy;
+    use std::future;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+    use crate::test_server::TestServer;
+
+    fn client(url: &str) -> AsyncApiClient {
+        service_client("iam", url)
+    }
+
+    fn service_client(service: &str, url: &str) -> AsyncApiClient {
+        let cred = Credentials::new().path("fixtures/credentials.ini").load();
+        let client = ApiClient::new(cred, "eu-west-1", service).unwrap()
+            .endpoint(url).unwrap()
+            .retry_policy(RetryPolicy::new().base_delay(Duration::from_millis(1)));
+        AsyncApiClient::new(client)
+    }
+
+    struct Counting {
+        calls: Arc<AtomicUsize>,
+    }
+
+    impl ProvideCredentials for Counting {
+        fn provide(&self) -> BoxFuture<Result<Credentials>> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            Box::pin(future::ready(Ok(Credentials::new().path("fixtures/credentials.ini").profile("aws").load())))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_query_retries() {
+        let body = "<ListUsersResponse><ListUsersResult><Users/></ListUsersResult><ResponseMetadata><RequestId>abc</RequestId></ResponseMetadata></ListUsersResponse>";
+        let server = TestServer::new(vec!((500, ""), (200, body)));
+        let res = client(&server.url).query("ListUsers", &()).await.unwrap();
+        assert_eq!(res.request_id, Some("abc".to_string()));
+        assert_eq!(server.request_count(), 2)
+    }
+
+    // Both are in flight at once on a single-threaded executor.
+    #[tokio::test]
+    async fn test_concurrent_operations() {
+        let body = "<ListUsersResponse><ListUsersResult><Users/></ListUsersResult></ListUsersResponse>";
+        let server = TestServer::new(vec!((200, body), (200, body)));
+        let client = client(&server.url);
+        let (a, b) = tokio::join!(client.query("ListUsers", &()), client.query("ListUsers", &()));
+        assert!(a.is_ok() && b.is_ok());
+        assert_eq!(server.request_count(), 2)
+    }
+
+    #[tokio::test]
+    async fn test_service_error() {
+        let body = "<ErrorResponse><Error><Code>NoSuchEntity</Code><Message>nope</Message></Error></ErrorResponse>";
+        let server = TestServer::new(vec!((404, body)));
+        match client(&server.url).query("GetUser", &()).await {
+            Err(Error::Service(e)) => assert_eq!(e.code, "NoSuchEntity"),
+            other => panic!("expected a service error, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_credentials_provider() {
+        let body = "<ListUsersResponse><ListUsersResult><Users/></ListUsersResult></ListUsersResponse>";
+        let server = TestServer::new(vec!((200, body)));
+        let calls = Arc::new(AtomicUsize::new(0));
+        let client = client(&server.url).credentials_provider(Counting { calls: calls.clone() });
+
+        assert!(client.query("ListUsers", &()).await.is_ok());
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert!(server.requests.lock().unwrap()[0].contains("Credential=akid/"))
+    }
+
+    #[tokio::test]
+    async fn test_profile_provider_error() {
+        let server = TestServer::new(vec!());
+        let creds = Credentials::new().path("fixtures/credentials.ini").profile("missing");
+        let client = client(&server.url).credentials_provider(ProfileProvider::new(creds));
+        match client.query("ListUsers", &()).await {
+            Err(Error::Config(_)) => {},
+            other => panic!("expected a config error, got {:?}", other),
+        }
+        assert_eq!(server.request_count(), 0)
+    }
+
+    #[tokio::test]
+    async fn test_paginate() {
+        let first = "<ListUsersResponse><ListUsersResult><Users><member><UserName>a</UserName></member></Users><Marker>m1</Marker></ListUsersResult></ListUsersResponse>";
+        let second = "<ListUsersResponse><ListUsersResult><Users><member><UserName>b</UserName></member></Users></ListUsersResult></ListUsersResponse>";
+        let server = TestServer::new(vec!((200, first), (200, second)));
+        let mut pages = client(&server.url).paginate("ListUsers", &(), QueryPageSpec::new("Marker", "Marker", "Users/member"));
+
+        let mut users = Vec::new();
+        while let Some(page) = pages.next().await {
+            users.extend(page.unwrap().items.iter().map(|u| u.find_text("UserName").unwrap()));
+        }
+        assert_eq!(users, vec!("a".to_string(), "b".to_string()));
+        assert!(server.requests.lock().unwrap()[1].contains("Marker=m1"))
+    }
+
+    #[tokio::test]
+    async fn test_paginate_json() {
+        let first = r#"{"Items":[{"id":{"S":"1"}}],"LastEvaluatedKey":{"id":{"S":"1"}}}"#;
+        let second = r#"{"Items":[{"id":{"S":"2"}}]}"#;
+        let server = TestServer::new(vec!((200, first), (200, second)));
+        let client = service_client("dynamodb", &server.url);
+        let input = Json::from_str(r#"{"TableName":"t"}"#).unwrap();
+        let mut pages = client.paginate_json("Scan", &input, JsonPageSpec::new("ExclusiveStartKey", "LastEvaluatedKey", "Items"));
+
+        assert_eq!(pages.next().await.unwrap().unwrap().items.len(), 1);
+        assert_eq!(pages.next().await.unwrap().unwrap().next_token, None);
+        assert!(pages.next().await.is_none());
+        assert!(server.requests.lock().unwrap()[1].contains(r#""ExclusiveStartKey":{"id":{"S":"1"}}"#))
+    }
+
+    #[tokio::test]
+    async fn test_json_unsupported() {
+        match client("http://localhost/").json("ListTables", &()).await {
+            Err(Error::Unsupported(_)) => {},
+            other => panic!("expected unsupported, got {:?}", other),
+        }
+    }
+}
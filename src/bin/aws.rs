@@ -5,17 +5,19 @@ extern crate log;
 extern crate env_logger;
 use aws::request::ApiClient;
 use aws::credentials::Credentials;
-use std::io::Read;
 
 pub fn main() {
-    env_logger::init().unwrap();
+    env_logger::init();
     let cred = Credentials::new().load();
     let region = "eu-west-1";
     let service = "ec2";
 
-    let client = ApiClient::new(cred, region, service);
-    let res = client.get("DescribeInstances");
-    let mut output = String::new();
-    res.unwrap().read_to_string(&mut output);
-    info!("{:?}", output)
+    let client = match ApiClient::new(cred, region, service) {
+        Ok(client) => client,
+        Err(e) => return error!("{}", e),
+    };
+    match client.get("DescribeInstances") {
+        Ok(res) => info!("{}", res.result.to_xml()),
+        Err(e) => error!("{}", e),
+    }
 }
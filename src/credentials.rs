@@ -2,6 +2,9 @@ use ini::Ini;
 use std::path::PathBuf;
 use std::env;
 
+use crate::env::{self as config, process};
+use crate::error::{Error, Result};
+
 #[derive(Clone,Debug)]
 pub struct Credentials {
     pub key: Option<String>,
@@ -10,13 +13,19 @@ pub struct Credentials {
     profile: String,
 }
 
-impl<'a> Credentials {
+impl Default for Credentials {
+    fn default() -> Credentials {
+        Credentials::new()
+    }
+}
+
+impl Credentials {
     pub fn new() -> Credentials {
         Credentials{
             key: None,
             secret: None,
-            path: get_profile_path(),
-            profile: get_default_profile(),
+            path: config::aws_file(&process, "AWS_SHARED_CREDENTIALS_FILE", "credentials").to_string_lossy().into_owned(),
+            profile: config::profile(&process),
         }
     }
 
@@ -30,35 +39,26 @@ impl<'a> Credentials {
         self
     }
 
-    pub fn load(mut self) -> Credentials {
-        let conf = Ini::load_from_file(&self.path).unwrap();
-        let section = conf.section(Some(&self.profile)).unwrap();
-        let key = section.get("aws_access_key_id").unwrap();
-        let secret = section.get("aws_secret_access_key").unwrap();
-
-        self.key = Some(key.to_string());
-        self.secret = Some(secret.to_string());
-        self
+    // Panics if the file, profile or either key is missing; see try_load.
+    pub fn load(self) -> Credentials {
+        self.try_load().unwrap()
     }
-}
 
-fn get_default_profile() -> String {
-    match env::var("AWS_PROFILE") {
-        Err(_) => "default".to_string(),
-        Ok(s) => s.to_string(),
-    }
-}
+    pub fn try_load(mut self) -> Result<Credentials> {
+        let conf = Ini::load_from_file(&self.path).map_err(|e| Error::Config(format!("{}: {}", self.path, e)))?;
+        let section = match conf.section(Some(&self.profile)) {
+            Some(section) => section,
+            None => return Err(Error::Config(format!("{}: no profile {}", self.path, self.profile))),
+        };
+        let get = |name: &str| match section.get(name) {
+            Some(value) => Ok(value.to_string()),
+            None => Err(Error::Config(format!("{}: profile {} has no {}", self.path, self.profile, name))),
+        };
 
-fn get_profile_path() -> String {
-    let home = match env::var("HOME") {
-        // hell if i know what not having home set means
-        Err(_) => "/root".to_string(),
-        Ok(s) => s,
-    };
-    let mut p = PathBuf::from(&home);
-    p.push(".aws");
-    p.push("credentials");
-    p.to_str().unwrap().to_string()
+        self.key = Some(get("aws_access_key_id")?);
+        self.secret = Some(get("aws_secret_access_key")?);
+        Ok(self)
+    }
 }
 
 fn get_absolute_path(val: &str) -> String {
@@ -98,4 +98,10 @@ mod test {
         let cred = Credentials::new().path("fixtures/credentials.ini").profile("first").load();
         assert_eq!(cred.key.unwrap(), "zxspectrum")
     }
+
+    #[test]
+    fn test_try_load_errors() {
+        assert!(Credentials::new().path("fixtures/missing.ini").try_load().is_err());
+        assert!(Credentials::new().path("fixtures/credentials.ini").profile("missing").try_load().is_err())
+    }
 }
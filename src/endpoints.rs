@@ -0,0 +1,367 @@
+
+use crate::env::{self, Getenv};
+use crate::error::{Error, Result};
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Partition {
+    pub id: &'static str,
+    pub dns_suffix: &'static str,
+    pub dual_stack_dns_suffix: Option<&'static str>,
+    pub supports_fips: bool,
+    // where global services such as IAM are signed
+    pub global_region: &'static str,
+    region_prefixes: &'static [&'static str],
+}
+
+static PARTITIONS: &[Partition] = &[
+    Partition {
+        id: "aws-cn",
+        dns_suffix: "amazonaws.com.cn",
+        dual_stack_dns_suffix: Some("api.amazonwebservices.com.cn"),
+        supports_fips: true,
+        global_region: "cn-north-1",
+        region_prefixes: &["cn-"],
+    },
+    Partition {
+        id: "aws-us-gov",
+        dns_suffix: "amazonaws.com",
+        dual_stack_dns_suffix: Some("api.aws"),
+        supports_fips: true,
+        global_region: "us-gov-west-1",
+        region_prefixes: &["us-gov-"],
+    },
+    Partition {
+        id: "aws-iso-b",
+        dns_suffix: "sc2s.sgov.gov",
+        dual_stack_dns_suffix: None,
+        supports_fips: true,
+        global_region: "us-isob-east-1",
+        region_prefixes: &["us-isob-"],
+    },
+    Partition {
+        id: "aws-iso",
+        dns_suffix: "c2s.ic.gov",
+        dual_stack_dns_suffix: None,
+        supports_fips: true,
+        global_region: "us-iso-east-1",
+        region_prefixes: &["us-iso-"],
+    },
+    // the catch-all, so it has to come last
+    Partition {
+        id: "aws",
+        dns_suffix: "amazonaws.com",
+        dual_stack_dns_suffix: Some("api.aws"),
+        supports_fips: true,
+        global_region: "us-east-1",
+        region_prefixes: &[],
+    },
+];
+
+pub fn partition(region: &str) -> &'static Partition {
+    PARTITIONS.iter()
+        .find(|p| p.region_prefixes.iter().any(|prefix| region.starts_with(prefix)))
+        .unwrap_or(&PARTITIONS[PARTITIONS.len() - 1])
+}
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Endpoint {
+    pub url: String,
+    pub signing_region: String,
+}
+
+#[derive(Clone,Debug)]
+pub struct EndpointResolver {
+    fips: bool,
+    dual_stack: bool,
+    custom: Option<String>,
+    use_env: bool,
+}
+
+impl Default for EndpointResolver {
+    fn default() -> EndpointResolver {
+        EndpointResolver::new()
+    }
+}
+
+impl EndpointResolver {
+    pub fn new() -> EndpointResolver {
+        EndpointResolver {
+            fips: false,
+            dual_stack: false,
+            custom: None,
+            use_env: true,
+        }
+    }
+
+    pub fn fips(mut self, fips: bool) -> EndpointResolver {
+        self.fips = fips;
+        self
+    }
+
+    pub fn dual_stack(mut self, dual_stack: bool) -> EndpointResolver {
+        self.dual_stack = dual_stack;
+        self
+    }
+
+    // LocalStack, MinIO, VPC endpoints and the like.
+    pub fn custom(mut self, url: &str) -> EndpointResolver {
+        self.custom = Some(url.to_string());
+        self
+    }
+
+    // Ignore AWS_ENDPOINT_URL* and AWS_USE_*_ENDPOINT.
+    pub fn ignore_env(mut self) -> EndpointResolver {
+        self.use_env = false;
+        self
+    }
+
+    pub fn resolve(&self, service: &str, region: &str) -> Result<Endpoint> {
+        self.resolve_with(service, region, &env::process)
+    }
+
+    pub fn resolve_with(&self, service: &str, region: &str, getenv: Getenv) -> Result<Endpoint> {
+        let lookup = |name: &str| if self.use_env { env::lookup(getenv, name) } else { None };
+
+        let custom = self.custom.clone()
+            .or(lookup(&format!("AWS_ENDPOINT_URL_{}", env_service_name(service))))
+            .or(lookup("AWS_ENDPOINT_URL"));
+        if let Some(url) = custom {
+            return Ok(Endpoint {
+                url,
+                signing_region: region.to_string(),
+            });
+        }
+
+        let enabled = |name: &str| lookup(name).map(|v| v.eq_ignore_ascii_case("true")).unwrap_or(false);
+        let fips = self.fips || enabled("AWS_USE_FIPS_ENDPOINT");
+        let dual_stack = self.dual_stack || enabled("AWS_USE_DUALSTACK_ENDPOINT");
+        resolve_partition(service, region, fips, dual_stack)
+    }
+}
+
+// AWS_ENDPOINT_URL_<SERVICE> uses the upper-cased service id with dashes
+// and spaces as underscores, e.g. AWS_ENDPOINT_URL_SECRETS_MANAGER.
+pub fn env_service_name(service: &str) -> String {
+    service_id(service).to_uppercase().replace("-", "_").replace(" ", "_")
+}
+
+// The service id the SDKs know a service by, where it isn't just the signing
+// name. ELB's classic and v2 APIs share a signing name; v2 is the current one.
+fn service_id(service: &str) -> &str {
+    match service {
+        "apigateway" => "API Gateway",
+        "application-autoscaling" => "Application Auto Scaling",
+        "autoscaling" => "Auto Scaling",
+        "cognito-identity" => "Cognito Identity",
+        "cognito-idp" => "Cognito Identity Provider",
+        "config" => "Config Service",
+        "datapipeline" => "Data Pipeline",
+        "directconnect" => "Direct Connect",
+        "dms" => "Database Migration Service",
+        "ds" => "Directory Service",
+        "elasticbeanstalk" => "Elastic Beanstalk",
+        "elasticfilesystem" => "EFS",
+        "elasticloadbalancing" => "Elastic Load Balancing v2",
+        "elasticmapreduce" => "EMR",
+        "email" | "ses" => "SES",
+        "es" => "Elasticsearch Service",
+        "events" => "EventBridge",
+        "kinesisanalytics" => "Kinesis Analytics",
+        "logs" => "CloudWatch Logs",
+        "mobiletargeting" => "Pinpoint",
+        "monitoring" => "CloudWatch",
+        "route53" => "Route 53",
+        "route53domains" => "Route 53 Domains",
+        "sdb" => "SimpleDB",
+        "secretsmanager" => "Secrets Manager",
+        "states" => "SFN",
+        "storagegateway" => "Storage Gateway",
+        "tagging" => "Resource Groups Tagging API",
+        _ => service,
+    }
+}
+
+fn resolve_partition(service: &str, region: &str, fips: bool, dual_stack: bool) -> Result<Endpoint> {
+    let partition = partition(region);
+    if fips && !partition.supports_fips {
+        return Err(Error::Config(format!("{} does not support FIPS endpoints", partition.id)));
+    }
+    if dual_stack && partition.dual_stack_dns_suffix.is_none() {
+        return Err(Error::Config(format!("{} does not support dual-stack endpoints", partition.id)));
+    }
+
+    if let Some(endpoint) = global_endpoint(service, partition, fips, dual_stack) {
+        return Ok(endpoint);
+    }
+
+    let host = if service == "s3" {
+        s3_host(region, partition, fips, dual_stack)
+    } else {
+        let name = if fips { format!("{}-fips", service) } else { service.to_string() };
+        let suffix = if dual_stack { partition.dual_stack_dns_suffix.unwrap() } else { partition.dns_suffix };
+        format!("{}.{}.{}", name, region, suffix)
+    };
+    Ok(Endpoint {
+        url: format!("https://{}/", host),
+        signing_region: region.to_string(),
+    })
+}
+
+// S3 predates dual-stack api.aws hosts and keeps its own naming; us-east-1
+// still answers on the legacy global host.
+fn s3_host(region: &str, partition: &Partition, fips: bool, dual_stack: bool) -> String {
+    let name = if fips { "s3-fips" } else { "s3" };
+    if dual_stack {
+        format!("{}.dualstack.{}.{}", name, region, partition.dns_suffix)
+    } else if region == "us-east-1" && !fips {
+        "s3.amazonaws.com".to_string()
+    } else {
+        format!("{}.{}.{}", name, region, partition.dns_suffix)
+    }
+}
+
+// Services with a single endpoint per partition, signed in that partition's
+// global region whatever region the caller asked for.
+fn global_endpoint(service: &str, partition: &Partition, fips: bool, dual_stack: bool) -> Option<Endpoint> {
+    if dual_stack {
+        return None;
+    }
+    let host = match (service, partition.id, fips) {
+        ("iam", "aws", false) => "iam.amazonaws.com".to_string(),
+        ("iam", "aws", true) => "iam-fips.amazonaws.com".to_string(),
+        ("iam", "aws-cn", _) => "iam.cn-north-1.amazonaws.com.cn".to_string(),
+        ("iam", "aws-us-gov", _) => "iam.us-gov.amazonaws.com".to_string(),
+        ("iam", _, _) => format!("iam.{}.{}", partition.global_region, partition.dns_suffix),
+        ("route53", "aws", false) => "route53.amazonaws.com".to_string(),
+        ("route53", "aws", true) => "route53-fips.amazonaws.com".to_string(),
+        ("route53", "aws-cn", _) => "route53.amazonaws.com.cn".to_string(),
+        ("route53", "aws-us-gov", _) => "route53.us-gov.amazonaws.com".to_string(),
+        ("route53", _, _) => format!("route53.{}", partition.dns_suffix),
+        ("cloudfront", "aws", false) => "cloudfront.amazonaws.com".to_string(),
+        ("cloudfront", "aws", true) => "cloudfront-fips.amazonaws.com".to_string(),
+        ("cloudfront", "aws-cn", _) => "cloudfront.cn-northwest-1.amazonaws.com.cn".to_string(),
+        ("organizations", "aws", false) => "organizations.us-east-1.amazonaws.com".to_string(),
+        ("organizations", "aws", true) => "organizations-fips.us-east-1.amazonaws.com".to_string(),
+        ("shield", "aws", false) => "shield.us-east-1.amazonaws.com".to_string(),
+        _ => return None,
+    };
+    let signing_region = match (service, partition.id) {
+        ("cloudfront", "aws-cn") => "cn-northwest-1",
+        _ => partition.global_region,
+    };
+    Some(Endpoint {
+        url: format!("https://{}/", host),
+        signing_region: signing_region.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EndpointResolver, Endpoint, partition, env_service_name};
+    use crate::env::fixed;
+
+    fn no_env(_: &str) -> Option<String> {
+        None
+    }
+
+    fn resolve(resolver: EndpointResolver, service: &str, region: &str) -> Endpoint {
+        resolver.resolve_with(service, region, &no_env).unwrap()
+    }
+
+    fn url(service: &str, region: &str) -> String {
+        resolve(EndpointResolver::new(), service, region).url
+    }
+
+    #[test]
+    fn test_partitions() {
+        assert_eq!(partition("eu-west-1").id, "aws");
+        assert_eq!(partition("cn-north-1").id, "aws-cn");
+        assert_eq!(partition("us-gov-west-1").id, "aws-us-gov");
+        assert_eq!(partition("us-isob-east-1").id, "aws-iso-b");
+        assert_eq!(partition("us-iso-east-1").id, "aws-iso");
+        assert_eq!(partition("mars-north-1").id, "aws")
+    }
+
+    #[test]
+    fn test_regional() {
+        assert_eq!(url("ec2", "eu-west-1"), "https://ec2.eu-west-1.amazonaws.com/");
+        assert_eq!(url("ec2", "cn-north-1"), "https://ec2.cn-north-1.amazonaws.com.cn/");
+        assert_eq!(url("ec2", "us-iso-east-1"), "https://ec2.us-iso-east-1.c2s.ic.gov/")
+    }
+
+    #[test]
+    fn test_global_services() {
+        let iam = resolve(EndpointResolver::new(), "iam", "eu-west-1");
+        assert_eq!(iam.url, "https://iam.amazonaws.com/");
+        assert_eq!(iam.signing_region, "us-east-1");
+
+        let iam = resolve(EndpointResolver::new(), "iam", "cn-northwest-1");
+        assert_eq!(iam.url, "https://iam.cn-north-1.amazonaws.com.cn/");
+        assert_eq!(iam.signing_region, "cn-north-1");
+
+        assert_eq!(url("route53", "ap-southeast-2"), "https://route53.amazonaws.com/");
+        assert_eq!(url("iam", "us-gov-east-1"), "https://iam.us-gov.amazonaws.com/")
+    }
+
+    #[test]
+    fn test_s3() {
+        assert_eq!(url("s3", "us-east-1"), "https://s3.amazonaws.com/");
+        assert_eq!(url("s3", "eu-west-1"), "https://s3.eu-west-1.amazonaws.com/");
+        assert_eq!(resolve(EndpointResolver::new().dual_stack(true), "s3", "eu-west-1").url,
+                   "https://s3.dualstack.eu-west-1.amazonaws.com/");
+        assert_eq!(resolve(EndpointResolver::new().fips(true), "s3", "us-east-1").url,
+                   "https://s3-fips.us-east-1.amazonaws.com/")
+    }
+
+    #[test]
+    fn test_fips_and_dual_stack() {
+        assert_eq!(resolve(EndpointResolver::new().fips(true), "kms", "us-east-1").url,
+                   "https://kms-fips.us-east-1.amazonaws.com/");
+        assert_eq!(resolve(EndpointResolver::new().dual_stack(true), "ec2", "eu-west-1").url,
+                   "https://ec2.eu-west-1.api.aws/");
+        assert_eq!(resolve(EndpointResolver::new().fips(true).dual_stack(true), "ec2", "us-east-1").url,
+                   "https://ec2-fips.us-east-1.api.aws/");
+        assert!(EndpointResolver::new().dual_stack(true).resolve_with("ec2", "us-iso-east-1", &no_env).is_err())
+    }
+
+    #[test]
+    fn test_custom_endpoint() {
+        let e = resolve(EndpointResolver::new().custom("http://localhost:4566"), "sqs", "eu-west-1");
+        assert_eq!(e.url, "http://localhost:4566");
+        assert_eq!(e.signing_region, "eu-west-1")
+    }
+
+    #[test]
+    fn test_env_endpoints() {
+        let env = |name: &str| match name {
+            "AWS_ENDPOINT_URL_SECRETS_MANAGER" => Some("http://localhost:4566".to_string()),
+            "AWS_ENDPOINT_URL" => Some("http://minio:9000".to_string()),
+            _ => None,
+        };
+        let resolver = EndpointResolver::new();
+        assert_eq!(resolver.resolve_with("secretsmanager", "eu-west-1", &env).unwrap().url, "http://localhost:4566");
+        assert_eq!(resolver.resolve_with("s3", "eu-west-1", &env).unwrap().url, "http://minio:9000");
+        assert_eq!(resolver.clone().custom("http://vpce").resolve_with("s3", "eu-west-1", &env).unwrap().url, "http://vpce");
+        assert_eq!(resolver.ignore_env().resolve_with("s3", "eu-west-1", &env).unwrap().url, "https://s3.eu-west-1.amazonaws.com/")
+    }
+
+    #[test]
+    fn test_env_service_name() {
+        assert_eq!(env_service_name("secretsmanager"), "SECRETS_MANAGER");
+        assert_eq!(env_service_name("logs"), "CLOUDWATCH_LOGS");
+        assert_eq!(env_service_name("elasticloadbalancing"), "ELASTIC_LOAD_BALANCING_V2");
+        assert_eq!(env_service_name("apigateway"), "API_GATEWAY");
+        assert_eq!(env_service_name("dynamodb"), "DYNAMODB");
+        assert_eq!(env_service_name("execute-api"), "EXECUTE_API")
+    }
+
+    #[test]
+    fn test_env_flags_ignore_case() {
+        let env = fixed(&[("AWS_USE_FIPS_ENDPOINT", "TRUE"), ("AWS_USE_DUALSTACK_ENDPOINT", "True")]);
+        assert_eq!(EndpointResolver::new().resolve_with("ec2", "us-east-1", &env).unwrap().url,
+                   "https://ec2-fips.us-east-1.api.aws/");
+        let env = fixed(&[("AWS_USE_FIPS_ENDPOINT", "false")]);
+        assert_eq!(EndpointResolver::new().resolve_with("ec2", "us-east-1", &env).unwrap().url,
+                   "https://ec2.us-east-1.amazonaws.com/")
+    }
+}
@@ -0,0 +1,65 @@
+use std::env;
+use std::path::PathBuf;
+
+// Where settings read from environment variables come from. Configuration
+// takes one of these so tests can supply their own variables.
+pub type Getenv<'a> = &'a dyn Fn(&str) -> Option<String>;
+
+// The process environment.
+pub fn process(name: &str) -> Option<String> {
+    env::var(name).ok()
+}
+
+// A variable's value, trimmed; unset and blank are the same.
+pub fn lookup(getenv: Getenv, name: &str) -> Option<String> {
+    getenv(name).and_then(|v| {
+        let v = v.trim();
+        if !v.is_empty() { Some(v.to_string()) } else { None }
+    })
+}
+
+// AWS_PROFILE, or else the default profile.
+pub fn profile(getenv: Getenv) -> String {
+    lookup(getenv, "AWS_PROFILE").unwrap_or_else(|| "default".to_string())
+}
+
+// A file in ~/.aws, or wherever `var` says instead, as the CLI finds its
+// config and credentials files.
+pub fn aws_file(getenv: Getenv, var: &str, name: &str) -> PathBuf {
+    if let Some(path) = lookup(getenv, var) {
+        return PathBuf::from(path);
+    }
+    let mut path = lookup(getenv, "HOME").map(PathBuf::from).or_else(env::home_dir).unwrap_or_default();
+    path.push(".aws");
+    path.push(name);
+    path
+}
+
+// An environment of just `vars`.
+#[cfg(test)]
+pub fn fixed(vars: &'static [(&'static str, &'static str)]) -> impl Fn(&str) -> Option<String> {
+    move |name: &str| vars.iter().find(|&&(k, _)| k == name).map(|&(_, v)| v.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{aws_file, fixed, lookup, profile};
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_lookup() {
+        let env = fixed(&[("SET", " value "), ("BLANK", "  ")]);
+        assert_eq!(lookup(&env, "SET"), Some("value".to_string()));
+        assert_eq!(lookup(&env, "BLANK"), None);
+        assert_eq!(lookup(&env, "UNSET"), None)
+    }
+
+    #[test]
+    fn test_aws_files() {
+        let env = fixed(&[("HOME", "/home/me"), ("AWS_CONFIG_FILE", "/etc/aws.ini"), ("AWS_PROFILE", "dev")]);
+        assert_eq!(aws_file(&env, "AWS_CONFIG_FILE", "config"), PathBuf::from("/etc/aws.ini"));
+        assert_eq!(aws_file(&env, "AWS_SHARED_CREDENTIALS_FILE", "credentials"), PathBuf::from("/home/me/.aws/credentials"));
+        assert_eq!(profile(&env), "dev");
+        assert_eq!(profile(&fixed(&[])), "default")
+    }
+}
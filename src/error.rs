@@ -0,0 +1,101 @@
+use std::error;
+use std::fmt;
+use std::io;
+use std::result;
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct ServiceError {
+    pub code: String,
+    pub message: String,
+    pub request_id: Option<String>,
+    pub status: u16,
+}
+
+impl fmt::Display for ServiceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({}): {}", self.code, self.status, self.message)
+    }
+}
+
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum TimeoutKind {
+    Connect,
+    Read,
+    Attempt,
+    Operation,
+}
+
+impl fmt::Display for TimeoutKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            TimeoutKind::Connect => "connect",
+            TimeoutKind::Read => "read",
+            TimeoutKind::Attempt => "attempt",
+            TimeoutKind::Operation => "operation",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Config(String),
+    Http(Box<dyn error::Error + Send + Sync>),
+    Io(io::Error),
+    // A mock or replaying transport had nothing to answer a request with.
+    NoResponse(String),
+    Parse(String),
+    Service(ServiceError),
+    Timeout(TimeoutKind),
+    Unsupported(String),
+    WaiterFailure(String),
+    WaiterTimeout(String),
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Config(ref e) => write!(f, "invalid configuration: {}", e),
+            Error::Http(ref e) => write!(f, "http error: {}", e),
+            Error::Io(ref e) => write!(f, "io error: {}", e),
+            Error::NoResponse(ref e) => write!(f, "no response: {}", e),
+            Error::Parse(ref e) => write!(f, "could not parse response: {}", e),
+            Error::Service(ref e) => write!(f, "service error: {}", e),
+            Error::Timeout(ref kind) => write!(f, "{} timeout elapsed", kind),
+            Error::Unsupported(ref e) => write!(f, "unsupported: {}", e),
+            Error::WaiterFailure(ref e) => write!(f, "waiter failed: {}", e),
+            Error::WaiterTimeout(ref e) => write!(f, "timed out waiting for {}", e),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::Config(_) => "invalid configuration",
+            Error::Http(_) => "http error",
+            Error::Io(_) => "io error",
+            Error::NoResponse(_) => "no response",
+            Error::Parse(_) => "could not parse response",
+            Error::Service(_) => "service error",
+            Error::Timeout(_) => "timed out",
+            Error::Unsupported(_) => "unsupported",
+            Error::WaiterFailure(_) => "waiter failed",
+            Error::WaiterTimeout(_) => "waiter timed out",
+        }
+    }
+}
+
+impl From<hyper::Error> for Error {
+    fn from(err: hyper::Error) -> Error {
+        Error::Http(Box::new(err))
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::Io(err)
+    }
+}
@@ -0,0 +1,352 @@
+use std::fmt;
+use std::str;
+
+#[derive(Clone,Debug,PartialEq)]
+pub enum HeaderValue {
+    Bool(bool),
+    Byte(i8),
+    Short(i16),
+    Int(i32),
+    Long(i64),
+    Bytes(Vec<u8>),
+    String(String),
+    // milliseconds since the epoch
+    Timestamp(i64),
+    Uuid([u8; 16]),
+}
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Header {
+    pub name: String,
+    pub value: HeaderValue,
+}
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Message {
+    pub headers: Vec<Header>,
+    pub payload: Vec<u8>,
+}
+
+#[derive(Debug,PartialEq)]
+pub enum DecodeError {
+    Truncated,
+    PreludeChecksum(u32, u32),
+    MessageChecksum(u32, u32),
+    HeaderType(u8),
+    InvalidString,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DecodeError::Truncated => write!(f, "event stream message is truncated"),
+            DecodeError::PreludeChecksum(want, got) =>
+                write!(f, "prelude checksum mismatch: expected {:08x}, got {:08x}", want, got),
+            DecodeError::MessageChecksum(want, got) =>
+                write!(f, "message checksum mismatch: expected {:08x}, got {:08x}", want, got),
+            DecodeError::HeaderType(t) => write!(f, "unknown header value type {}", t),
+            DecodeError::InvalidString => write!(f, "header is not valid utf-8"),
+        }
+    }
+}
+
+// A header or message too large for its length field.
+#[derive(Debug,PartialEq)]
+pub enum EncodeError {
+    NameTooLong(String),
+    ValueTooLong(String, usize),
+    MessageTooLong(usize),
+}
+
+impl fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            EncodeError::NameTooLong(ref name) => write!(f, "header name {:?} is over 255 bytes", name),
+            EncodeError::ValueTooLong(ref name, len) => write!(f, "header {} value is {} bytes, over 65535", name, len),
+            EncodeError::MessageTooLong(len) => write!(f, "event stream message is {} bytes, too long to encode", len),
+        }
+    }
+}
+
+// prelude is total length, headers length and the prelude crc
+const PRELUDE_LENGTH: usize = 12;
+const MESSAGE_CRC_LENGTH: usize = 4;
+
+impl Header {
+    pub fn new(name: &str, value: HeaderValue) -> Header {
+        Header {
+            name: name.to_string(),
+            value,
+        }
+    }
+
+    pub fn encode(&self, buf: &mut Vec<u8>) -> Result<(), EncodeError> {
+        if self.name.len() > u8::MAX as usize {
+            return Err(EncodeError::NameTooLong(self.name.clone()));
+        }
+        let value_len = match self.value {
+            HeaderValue::Bytes(ref v) => v.len(),
+            HeaderValue::String(ref v) => v.len(),
+            _ => 0,
+        };
+        if value_len > u16::MAX as usize {
+            return Err(EncodeError::ValueTooLong(self.name.clone(), value_len));
+        }
+        buf.push(self.name.len() as u8);
+        buf.extend(self.name.as_bytes().iter().cloned());
+        match self.value {
+            HeaderValue::Bool(true) => buf.push(0),
+            HeaderValue::Bool(false) => buf.push(1),
+            HeaderValue::Byte(v) => {
+                buf.push(2);
+                buf.push(v as u8);
+            },
+            HeaderValue::Short(v) => {
+                buf.push(3);
+                push_be(buf, v as u64, 2);
+            },
+            HeaderValue::Int(v) => {
+                buf.push(4);
+                push_be(buf, v as u64, 4);
+            },
+            HeaderValue::Long(v) => {
+                buf.push(5);
+                push_be(buf, v as u64, 8);
+            },
+            HeaderValue::Bytes(ref v) => {
+                buf.push(6);
+                push_be(buf, v.len() as u64, 2);
+                buf.extend(v.iter().cloned());
+            },
+            HeaderValue::String(ref v) => {
+                buf.push(7);
+                push_be(buf, v.len() as u64, 2);
+                buf.extend(v.as_bytes().iter().cloned());
+            },
+            HeaderValue::Timestamp(v) => {
+                buf.push(8);
+                push_be(buf, v as u64, 8);
+            },
+            HeaderValue::Uuid(ref v) => {
+                buf.push(9);
+                buf.extend(v.iter().cloned());
+            },
+        }
+        Ok(())
+    }
+}
+
+impl Message {
+    pub fn new(headers: Vec<Header>, payload: Vec<u8>) -> Message {
+        Message {
+            headers,
+            payload,
+        }
+    }
+
+    pub fn header(&self, name: &str) -> Option<&HeaderValue> {
+        self.headers.iter().find(|h| h.name == name).map(|h| &h.value)
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>, EncodeError> {
+        let mut headers = Vec::new();
+        for h in self.headers.iter() {
+            h.encode(&mut headers)?;
+        }
+        let total = PRELUDE_LENGTH + headers.len() + self.payload.len() + MESSAGE_CRC_LENGTH;
+        if total > u32::MAX as usize {
+            return Err(EncodeError::MessageTooLong(total));
+        }
+
+        let mut buf = Vec::with_capacity(total);
+        push_be(&mut buf, total as u64, 4);
+        push_be(&mut buf, headers.len() as u64, 4);
+        let prelude_crc = crc32(&buf);
+        push_be(&mut buf, prelude_crc as u64, 4);
+        buf.extend(headers);
+        buf.extend(self.payload.iter().cloned());
+        let message_crc = crc32(&buf);
+        push_be(&mut buf, message_crc as u64, 4);
+        Ok(buf)
+    }
+
+    // Decodes one message from the front of `buf`, returning it and the
+    // number of bytes consumed.
+    pub fn decode(buf: &[u8]) -> Result<(Message, usize), DecodeError> {
+        if buf.len() < PRELUDE_LENGTH + MESSAGE_CRC_LENGTH {
+            return Err(DecodeError::Truncated);
+        }
+        let total = read_be(&buf[0..4]) as usize;
+        let headers_len = read_be(&buf[4..8]) as usize;
+        let prelude_crc = read_be(&buf[8..12]) as u32;
+        let actual = crc32(&buf[0..8]);
+        if prelude_crc != actual {
+            return Err(DecodeError::PreludeChecksum(prelude_crc, actual));
+        }
+        if buf.len() < total || total < PRELUDE_LENGTH + headers_len + MESSAGE_CRC_LENGTH {
+            return Err(DecodeError::Truncated);
+        }
+        let message_crc = read_be(&buf[total - 4..total]) as u32;
+        let actual = crc32(&buf[0..total - 4]);
+        if message_crc != actual {
+            return Err(DecodeError::MessageChecksum(message_crc, actual));
+        }
+
+        let headers_end = PRELUDE_LENGTH + headers_len;
+        let headers = decode_headers(&buf[PRELUDE_LENGTH..headers_end])?;
+        let payload = buf[headers_end..total - 4].to_vec();
+        Ok((Message::new(headers, payload), total))
+    }
+}
+
+fn decode_headers(mut buf: &[u8]) -> Result<Vec<Header>, DecodeError> {
+    fn take<'a>(buf: &mut &'a [u8], n: usize) -> Result<&'a [u8], DecodeError> {
+        if buf.len() < n {
+            return Err(DecodeError::Truncated);
+        }
+        let (head, tail) = buf.split_at(n);
+        *buf = tail;
+        Ok(head)
+    }
+
+    let mut headers = Vec::new();
+    while !buf.is_empty() {
+        let name_len = take(&mut buf, 1)?[0] as usize;
+        let name = match str::from_utf8(take(&mut buf, name_len)?) {
+            Ok(n) => n.to_string(),
+            Err(_) => return Err(DecodeError::InvalidString),
+        };
+        let value = match take(&mut buf, 1)?[0] {
+            0 => HeaderValue::Bool(true),
+            1 => HeaderValue::Bool(false),
+            2 => HeaderValue::Byte(take(&mut buf, 1)?[0] as i8),
+            3 => HeaderValue::Short(read_be(take(&mut buf, 2)?) as i16),
+            4 => HeaderValue::Int(read_be(take(&mut buf, 4)?) as i32),
+            5 => HeaderValue::Long(read_be(take(&mut buf, 8)?) as i64),
+            6 => {
+                let len = read_be(take(&mut buf, 2)?) as usize;
+                HeaderValue::Bytes(take(&mut buf, len)?.to_vec())
+            },
+            7 => {
+                let len = read_be(take(&mut buf, 2)?) as usize;
+                match str::from_utf8(take(&mut buf, len)?) {
+                    Ok(s) => HeaderValue::String(s.to_string()),
+                    Err(_) => return Err(DecodeError::InvalidString),
+                }
+            },
+            8 => HeaderValue::Timestamp(read_be(take(&mut buf, 8)?) as i64),
+            9 => {
+                let mut uuid = [0u8; 16];
+                uuid.copy_from_slice(take(&mut buf, 16)?);
+                HeaderValue::Uuid(uuid)
+            },
+            t => return Err(DecodeError::HeaderType(t)),
+        };
+        headers.push(Header { name, value });
+    }
+    Ok(headers)
+}
+
+fn push_be(buf: &mut Vec<u8>, val: u64, len: usize) {
+    for i in (0..len).rev() {
+        buf.push((val >> (i * 8)) as u8);
+    }
+}
+
+fn read_be(buf: &[u8]) -> u64 {
+    buf.iter().fold(0u64, |acc, &b| (acc << 8) | b as u64)
+}
+
+// CRC-32 (IEEE 802.3), as used for both the prelude and message checksums.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFFFFFFu32;
+    for &byte in data.iter() {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB88320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Message, Header, HeaderValue, DecodeError, EncodeError, crc32};
+    use serialize::hex::ToHex;
+
+    fn message() -> Message {
+        Message::new(vec!(Header::new(":message-type", HeaderValue::String("event".to_string())),
+                          Header::new(":content-type", HeaderValue::String("application/json".to_string()))),
+                     b"{\"foo\":\"bar\"}".to_vec())
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xcbf43926)
+    }
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(message().encode().unwrap().to_hex(), "0000005400000037100c136d0d3a6d6573736167652d747970650700056576656e740d3a636f6e74656e742d747970650700106170706c69636174696f6e2f6a736f6e7b22666f6f223a22626172227d76e2a7fc")
+    }
+
+    #[test]
+    fn test_encode_oversized() {
+        let name = "n".repeat(256);
+        let long_name = Message::new(vec!(Header::new(&name, HeaderValue::Bool(true))), Vec::new());
+        assert_eq!(long_name.encode().unwrap_err(), EncodeError::NameTooLong(name));
+
+        let long_value = Message::new(vec!(Header::new("v", HeaderValue::Bytes(vec!(0; 65536)))), Vec::new());
+        assert_eq!(long_value.encode().unwrap_err(), EncodeError::ValueTooLong("v".to_string(), 65536));
+
+        let longest = Message::new(vec!(Header::new("v", HeaderValue::String("s".repeat(65535)))), Vec::new());
+        assert!(longest.encode().is_ok())
+    }
+
+    #[test]
+    fn test_decode() {
+        let encoded = message().encode().unwrap();
+        let (decoded, used) = Message::decode(&encoded).unwrap();
+        assert_eq!(used, 84);
+        assert_eq!(decoded, message());
+        assert_eq!(decoded.header(":message-type"), Some(&HeaderValue::String("event".to_string())))
+    }
+
+    #[test]
+    fn test_round_trip_all_types() {
+        let msg = Message::new(vec!(Header::new("true", HeaderValue::Bool(true)),
+                                    Header::new("false", HeaderValue::Bool(false)),
+                                    Header::new("byte", HeaderValue::Byte(-1)),
+                                    Header::new("short", HeaderValue::Short(-300)),
+                                    Header::new("int", HeaderValue::Int(70000)),
+                                    Header::new("long", HeaderValue::Long(-5000000000)),
+                                    Header::new("bytes", HeaderValue::Bytes(vec!(1, 2, 3))),
+                                    Header::new("timestamp", HeaderValue::Timestamp(1369353600000)),
+                                    Header::new("uuid", HeaderValue::Uuid([7; 16]))),
+                               Vec::new());
+        let (decoded, _) = Message::decode(&msg.encode().unwrap()).unwrap();
+        assert_eq!(decoded, msg)
+    }
+
+    #[test]
+    fn test_decode_consumes_one_message() {
+        let mut stream = message().encode().unwrap();
+        stream.extend(message().encode().unwrap());
+        let (_, used) = Message::decode(&stream).unwrap();
+        let (second, _) = Message::decode(&stream[used..]).unwrap();
+        assert_eq!(second, message())
+    }
+
+    #[test]
+    fn test_decode_bad_checksum() {
+        let mut encoded = message().encode().unwrap();
+        encoded[20] ^= 0xff;
+        assert_eq!(Message::decode(&encoded).unwrap_err(), DecodeError::MessageChecksum(0x76e2a7fc, super::crc32(&encoded[..80])))
+    }
+
+    #[test]
+    fn test_decode_truncated() {
+        let encoded = message().encode().unwrap();
+        assert_eq!(Message::decode(&encoded[..40]).unwrap_err(), DecodeError::Truncated)
+    }
+}
@@ -0,0 +1,172 @@
+use std::fmt;
+
+use crate::request::ApiRequest;
+use crate::response::HttpResponse;
+use crate::transport::HttpRequest;
+
+// What an interceptor is told about the call in hand. `attempt` is 0 during
+// serialization and counts from 1 for each attempt after that.
+#[derive(Clone,Debug,PartialEq)]
+pub struct Context {
+    pub service: String,
+    pub operation: String,
+    pub region: String,
+    pub attempt: u32,
+}
+
+// Hooks around each stage of a call, run in the order the interceptors were
+// added. All do nothing by default. Any hook but after_response can
+// short-circuit by returning a response, which is then treated as though the
+// service had sent it: it goes through after_response and the retry policy,
+// except from serialization, where it is the operation's result.
+pub trait Interceptor: fmt::Debug + Send + Sync {
+    // Once per operation, with the serialized input.
+    fn serialization(&self, _ctx: &Context, _req: &mut ApiRequest) -> Option<HttpResponse> {
+        None
+    }
+
+    // Before each attempt is signed; changes here are covered by the signature.
+    fn before_signing(&self, _ctx: &Context, _req: &mut ApiRequest) -> Option<HttpResponse> {
+        None
+    }
+
+    // With the signed request. Changing signed parts will invalidate it.
+    fn after_signing(&self, _ctx: &Context, _req: &mut HttpRequest) -> Option<HttpResponse> {
+        None
+    }
+
+    // Once every after_signing hook has run, just before the transport is called.
+    fn before_transmit(&self, _ctx: &Context, _req: &mut HttpRequest) -> Option<HttpResponse> {
+        None
+    }
+
+    // With each attempt's response, before it's checked for errors and retries.
+    fn after_response(&self, _ctx: &Context, _res: &mut HttpResponse) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Context, Interceptor};
+    use crate::credentials::Credentials;
+    use crate::request::{ApiClient, ApiRequest};
+    use crate::response::HttpResponse;
+    use crate::retry::RetryPolicy;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use crate::transport::HttpRequest;
+    use crate::transport::mock::MockTransport;
+
+    #[derive(Debug)]
+    struct Recorder {
+        events: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Recorder {
+        fn log(&self, hook: &str, ctx: &Context) {
+            self.events.lock().unwrap().push(format!("{} {} {}", hook, ctx.operation, ctx.attempt));
+        }
+    }
+
+    impl Interceptor for Recorder {
+        fn serialization(&self, ctx: &Context, req: &mut ApiRequest) -> Option<HttpResponse> {
+            self.log("serialization", ctx);
+            req.headers_mut().push(("X-Amz-Custom".to_string(), "1".to_string()));
+            None
+        }
+
+        fn before_signing(&self, ctx: &Context, _req: &mut ApiRequest) -> Option<HttpResponse> {
+            self.log("before_signing", ctx);
+            None
+        }
+
+        fn after_signing(&self, ctx: &Context, req: &mut HttpRequest) -> Option<HttpResponse> {
+            assert!(req.header("authorization").unwrap().contains("x-amz-custom"));
+            self.log("after_signing", ctx);
+            None
+        }
+
+        fn before_transmit(&self, ctx: &Context, req: &mut HttpRequest) -> Option<HttpResponse> {
+            req.headers.push(("User-Agent".to_string(), "aws-rs-test".to_string()));
+            self.log("before_transmit", ctx);
+            None
+        }
+
+        fn after_response(&self, ctx: &Context, res: &mut HttpResponse) {
+            self.log(&format!("after_response {}", res.status), ctx);
+        }
+    }
+
+    #[derive(Debug)]
+    struct Canned;
+
+    impl Interceptor for Canned {
+        fn before_transmit(&self, _ctx: &Context, _req: &mut HttpRequest) -> Option<HttpResponse> {
+            Some(HttpResponse {
+                status: 200,
+                headers: Vec::new(),
+                body: b"<GetCallerIdentityResponse><GetCallerIdentityResult><Account>1</Account></GetCallerIdentityResult></GetCallerIdentityResponse>".to_vec(),
+            })
+        }
+    }
+
+    #[derive(Debug)]
+    struct Regions {
+        seen: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Interceptor for Regions {
+        fn after_signing(&self, ctx: &Context, req: &mut HttpRequest) -> Option<HttpResponse> {
+            self.seen.lock().unwrap().push(ctx.region.clone());
+            self.seen.lock().unwrap().push(req.header("authorization").unwrap().to_string());
+            None
+        }
+    }
+
+    fn client() -> ApiClient {
+        let cred = Credentials::new().path("fixtures/credentials.ini").load();
+        ApiClient::new(cred, "eu-west-1", "sts").unwrap().retry_policy(RetryPolicy::new().base_delay(Duration::from_millis(1)))
+    }
+
+    #[test]
+    fn test_hooks_in_order() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let mock = MockTransport::new().respond(503, "").respond(200, "<GetCallerIdentityResponse/>");
+        let client = client().transport(mock.clone()).interceptor(Recorder { events: events.clone() });
+        assert!(client.query("GetCallerIdentity", &()).is_ok());
+
+        assert_eq!(*events.lock().unwrap(), vec!(
+            "serialization GetCallerIdentity 0",
+            "before_signing GetCallerIdentity 1", "after_signing GetCallerIdentity 1",
+            "before_transmit GetCallerIdentity 1", "after_response 503 GetCallerIdentity 1",
+            "before_signing GetCallerIdentity 2", "after_signing GetCallerIdentity 2",
+            "before_transmit GetCallerIdentity 2", "after_response 200 GetCallerIdentity 2",
+        ));
+        let sent = mock.requests();
+        assert_eq!(sent[1].header("x-amz-custom"), Some("1"));
+        assert_eq!(sent[1].header("user-agent"), Some("aws-rs-test"))
+    }
+
+    #[test]
+    fn test_short_circuit() {
+        let mock = MockTransport::new();
+        let client = client().transport(mock.clone()).interceptor(Canned);
+        let res = client.query("GetCallerIdentity", &()).unwrap();
+        assert_eq!(res.result.find_text("Account"), Some("1".to_string()));
+        assert_eq!(mock.request_count(), 0)
+    }
+
+    // IAM is signed for us-east-1 whichever region the client is for.
+    #[test]
+    fn test_context_has_configured_region() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let cred = Credentials::new().path("fixtures/credentials.ini").load();
+        let client = ApiClient::new(cred, "eu-west-1", "iam").unwrap()
+            .transport(MockTransport::new().respond(200, "<ListUsersResponse/>"))
+            .interceptor(Regions { seen: seen.clone() });
+        assert!(client.query("ListUsers", &()).is_ok());
+
+        let seen = seen.lock().unwrap();
+        assert_eq!(seen[0], "eu-west-1");
+        assert!(seen[1].contains("/us-east-1/iam/"))
+    }
+}
@@ -1,20 +1,35 @@
 #![crate_name = "aws"]
 #![crate_type = "lib"]
 
-#![feature(convert)]
-
-#[macro_use]
-extern crate hyper;
-
-#[cfg(unix)] extern crate openssl;
 extern crate rustc_serialize as serialize;
-extern crate time;
-extern crate url;
-extern crate ini;
 
 #[macro_use]
-extern crate log;
+extern crate tracing;
 
+pub mod async_client;
 pub mod credentials;
+pub mod endpoints;
+pub mod env;
+pub mod error;
+pub mod event_stream;
+pub mod interceptor;
+pub mod metrics;
+pub mod paginator;
+pub mod pool;
+pub mod protocol;
+pub mod proxy;
+pub mod rate_limit;
 pub mod request;
+pub mod response;
+pub mod retry;
+pub mod runtime;
 pub mod signers;
+pub mod timeout;
+pub mod tls;
+pub mod transport;
+pub mod waiter;
+pub mod wire;
+pub mod xml;
+
+#[cfg(test)]
+mod test_server;
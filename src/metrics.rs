@@ -0,0 +1,208 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::error::{Error, Result, TimeoutKind};
+use crate::interceptor::Context;
+use crate::protocol::service_error;
+use crate::response::HttpResponse;
+use crate::retry::{self, RetryKind};
+
+// How an attempt or a whole operation turned out. `error_code` is the
+// service's code for error responses, or names the client-side failure.
+#[derive(Clone,Debug,PartialEq)]
+pub struct Outcome {
+    pub status: Option<u16>,
+    pub error_code: Option<String>,
+    pub kind: RetryKind,
+    pub latency: Duration,
+}
+
+impl Outcome {
+    pub fn new(result: &Result<HttpResponse>, latency: Duration) -> Outcome {
+        let (status, kind) = match *result {
+            Ok(ref res) => (Some(res.status), retry::classify_response(res)),
+            Err(ref e) => (None, retry::classify_error(e)),
+        };
+        Outcome {
+            status,
+            error_code: error_code(result),
+            kind,
+            latency,
+        }
+    }
+
+    pub fn is_throttled(&self) -> bool {
+        self.kind == RetryKind::Throttling
+    }
+}
+
+fn error_code(result: &Result<HttpResponse>) -> Option<String> {
+    match *result {
+        Ok(ref res) if res.status < 400 => None,
+        Ok(ref res) => Some(service_error(res).code),
+        Err(Error::Service(ref e)) => Some(e.code.clone()),
+        Err(Error::Timeout(kind)) => Some(match kind {
+            TimeoutKind::Connect => "ConnectTimeout",
+            TimeoutKind::Read => "ReadTimeout",
+            TimeoutKind::Attempt => "AttemptTimeout",
+            TimeoutKind::Operation => "OperationTimeout",
+        }.to_string()),
+        Err(Error::Http(_)) | Err(Error::Io(_)) => Some("TransportError".to_string()),
+        Err(_) => Some("ClientError".to_string()),
+    }
+}
+
+// Told about every attempt and every finished operation. The context's
+// `attempt` is the attempt's number, or on completion how many were made;
+// none are made when an interceptor answers during serialization. Both do
+// nothing by default.
+pub trait Metrics: fmt::Debug + Send + Sync {
+    fn attempt(&self, _ctx: &Context, _outcome: &Outcome) {}
+
+    fn completed(&self, _ctx: &Context, _outcome: &Outcome) {}
+}
+
+// How many latencies InMemoryMetrics keeps per operation; older ones are
+// dropped, so percentiles are of the most recent operations.
+pub const MAX_LATENCIES: usize = 1000;
+
+// What InMemoryMetrics has seen of one service's operation. Latencies are of
+// whole operations, retries included. Error codes are counted per attempt.
+#[derive(Clone,Debug,Default,PartialEq)]
+pub struct OperationStats {
+    pub requests: u32,
+    pub failures: u32,
+    pub attempts: u32,
+    pub retries: u32,
+    pub throttles: u32,
+    pub error_codes: BTreeMap<String, u32>,
+    pub latencies: VecDeque<Duration>,
+}
+
+impl OperationStats {
+    // The smallest latency at least `p` percent of operations took no longer than.
+    pub fn percentile(&self, p: f64) -> Option<Duration> {
+        if self.latencies.is_empty() {
+            return None;
+        }
+        let mut sorted: Vec<Duration> = self.latencies.iter().cloned().collect();
+        sorted.sort();
+        let rank = ((p / 100.0) * sorted.len() as f64).ceil() as usize;
+        Some(sorted[rank.max(1).min(sorted.len()) - 1])
+    }
+}
+
+// Keeps everything in memory, for tests and short-lived tools; only the
+// counts grow without bound. Clones share what's been recorded.
+#[derive(Clone,Debug,Default)]
+pub struct InMemoryMetrics {
+    stats: Arc<Mutex<HashMap<(String, String), OperationStats>>>,
+}
+
+impl InMemoryMetrics {
+    pub fn new() -> InMemoryMetrics {
+        InMemoryMetrics::default()
+    }
+
+    pub fn stats(&self, service: &str, operation: &str) -> OperationStats {
+        let key = (service.to_string(), operation.to_string());
+        self.stats.lock().unwrap().get(&key).cloned().unwrap_or_default()
+    }
+
+    fn update<F: FnOnce(&mut OperationStats)>(&self, ctx: &Context, f: F) {
+        let key = (ctx.service.clone(), ctx.operation.clone());
+        f(self.stats.lock().unwrap().entry(key).or_default())
+    }
+}
+
+impl Metrics for InMemoryMetrics {
+    fn attempt(&self, ctx: &Context, outcome: &Outcome) {
+        self.update(ctx, |stats| {
+            stats.attempts += 1;
+            if outcome.is_throttled() {
+                stats.throttles += 1;
+            }
+            if let Some(ref code) = outcome.error_code {
+                *stats.error_codes.entry(code.clone()).or_insert(0) += 1;
+            }
+        })
+    }
+
+    fn completed(&self, ctx: &Context, outcome: &Outcome) {
+        self.update(ctx, |stats| {
+            stats.requests += 1;
+            stats.retries += ctx.attempt.saturating_sub(1);
+            if outcome.error_code.is_some() {
+                stats.failures += 1;
+            }
+            if stats.latencies.len() == MAX_LATENCIES {
+                stats.latencies.pop_front();
+            }
+            stats.latencies.push_back(outcome.latency);
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{InMemoryMetrics, Metrics, Outcome, OperationStats, MAX_LATENCIES};
+    use crate::interceptor::Context;
+    use crate::error::{Error, TimeoutKind};
+    use crate::response::HttpResponse;
+    use crate::retry::RetryKind;
+    use std::time::Duration;
+
+    fn response(status: u16, body: &str) -> HttpResponse {
+        HttpResponse {
+            status,
+            headers: Vec::new(),
+            body: body.as_bytes().to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_outcomes() {
+        let ok = Outcome::new(&Ok(response(200, "")), Duration::from_millis(5));
+        assert_eq!(ok.status, Some(200));
+        assert_eq!(ok.error_code, None);
+
+        let throttled = Outcome::new(&Ok(response(400, r#"{"__type":"ThrottlingException"}"#)), Duration::from_millis(5));
+        assert_eq!(throttled.error_code, Some("ThrottlingException".to_string()));
+        assert!(throttled.is_throttled());
+
+        let timeout = Outcome::new(&Err(Error::Timeout(TimeoutKind::Read)), Duration::from_millis(5));
+        assert_eq!(timeout.status, None);
+        assert_eq!(timeout.error_code, Some("ReadTimeout".to_string()));
+        assert_eq!(timeout.kind, RetryKind::Timeout)
+    }
+
+    #[test]
+    fn test_percentile() {
+        let mut stats = OperationStats::default();
+        assert_eq!(stats.percentile(50.0), None);
+        stats.latencies = (1..11).rev().map(Duration::from_millis).collect();
+        assert_eq!(stats.percentile(50.0), Some(Duration::from_millis(5)));
+        assert_eq!(stats.percentile(99.0), Some(Duration::from_millis(10)));
+        assert_eq!(stats.percentile(0.0), Some(Duration::from_millis(1)))
+    }
+
+    #[test]
+    fn test_latencies_capped() {
+        let metrics = InMemoryMetrics::new();
+        let ctx = Context {
+            service: "iam".to_string(),
+            operation: "ListUsers".to_string(),
+            region: "us-east-1".to_string(),
+            attempt: 1,
+        };
+        for ms in 0..(MAX_LATENCIES as u64 + 10) {
+            metrics.completed(&ctx, &Outcome::new(&Ok(response(200, "")), Duration::from_millis(ms)));
+        }
+        let stats = metrics.stats("iam", "ListUsers");
+        assert_eq!(stats.requests, MAX_LATENCIES as u32 + 10);
+        assert_eq!(stats.latencies.len(), MAX_LATENCIES);
+        assert_eq!(stats.percentile(0.0), Some(Duration::from_millis(10)))
+    }
+}
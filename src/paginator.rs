@@ -0,0 +1,388 @@
+use futures_core::Stream;
+use serialize::json::Json;
+use std::future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use crate::error::{Error, Result};
+use crate::protocol::query::QueryResponse;
+use crate::protocol::rest::RestRequest;
+use crate::response::HttpResponse;
+use crate::runtime::BoxFuture;
+use crate::xml::Element;
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub next_token: Option<String>,
+}
+
+type Fetch<'a, T> = Box<dyn FnMut(Option<&str>) -> Result<Page<T>> + 'a>;
+
+type FetchAsync<T> = Box<dyn FnMut(Option<String>) -> BoxFuture<Result<Page<T>>> + Send>;
+
+// Where paging has got to, whether pages are fetched blocking or not.
+#[derive(Debug,Default)]
+struct Cursor {
+    next_token: Option<String>,
+    done: bool,
+    max_items: Option<usize>,
+    seen: usize,
+}
+
+impl Cursor {
+    // The token to fetch the next page with, or None when there are no more.
+    fn next(&self) -> Option<Option<String>> {
+        if self.done || self.max_items.map(|m| self.seen >= m).unwrap_or(false) {
+            return None;
+        }
+        Some(self.next_token.clone())
+    }
+
+    fn advance<T>(&mut self, page: Result<Page<T>>) -> Result<Page<T>> {
+        let mut page = match page {
+            Ok(page) => page,
+            Err(e) => {
+                self.done = true;
+                return Err(e);
+            }
+        };
+
+        if let Some(max) = self.max_items {
+            if self.seen + page.items.len() >= max {
+                page.items.truncate(max - self.seen);
+                self.done = true;
+            }
+        }
+        self.seen += page.items.len();
+
+        match page.next_token {
+            Some(ref token) if !token.is_empty() => {
+                // a service handing back the token it was given would loop forever
+                if self.next_token.as_ref() == Some(token) {
+                    self.done = true;
+                    return Err(Error::Parse(format!("pagination token {} was repeated", token)));
+                }
+                self.next_token = Some(token.clone());
+            },
+            _ => self.done = true,
+        }
+        Ok(page)
+    }
+}
+
+// Calls `fetch` with each page's token (None for the first page) until the
+// service stops returning one, or `max_items` items have been produced.
+pub struct Paginator<'a, T> {
+    fetch: Fetch<'a, T>,
+    cursor: Cursor,
+}
+
+impl<'a, T> Paginator<'a, T> {
+    pub fn new<F>(fetch: F) -> Paginator<'a, T> where F: FnMut(Option<&str>) -> Result<Page<T>> + 'a {
+        Paginator {
+            fetch: Box::new(fetch),
+            cursor: Cursor::default(),
+        }
+    }
+
+    pub fn max_items(mut self, max: usize) -> Paginator<'a, T> {
+        self.cursor.max_items = Some(max);
+        self
+    }
+
+    pub fn items(self) -> Items<'a, T> {
+        Items {
+            pages: self,
+            current: Vec::new().into_iter(),
+        }
+    }
+}
+
+impl<'a, T> Iterator for Paginator<'a, T> {
+    type Item = Result<Page<T>>;
+
+    fn next(&mut self) -> Option<Result<Page<T>>> {
+        let token = self.cursor.next()?;
+        let page = (self.fetch)(token.as_ref().map(|t| &t[..]));
+        Some(self.cursor.advance(page))
+    }
+}
+
+// The non-blocking Paginator: a stream of pages, each fetched once the
+// previous one has been taken.
+pub struct PageStream<T> {
+    fetch: FetchAsync<T>,
+    cursor: Cursor,
+    pending: Option<BoxFuture<Result<Page<T>>>>,
+}
+
+impl<T> PageStream<T> {
+    pub fn new<F>(fetch: F) -> PageStream<T> where F: FnMut(Option<String>) -> BoxFuture<Result<Page<T>>> + Send + 'static {
+        PageStream {
+            fetch: Box::new(fetch),
+            cursor: Cursor::default(),
+            pending: None,
+        }
+    }
+
+    pub fn max_items(mut self, max: usize) -> PageStream<T> {
+        self.cursor.max_items = Some(max);
+        self
+    }
+
+    pub async fn next(&mut self) -> Option<Result<Page<T>>> {
+        future::poll_fn(|cx| Pin::new(&mut *self).poll_next(cx)).await
+    }
+}
+
+impl<T> Stream for PageStream<T> {
+    type Item = Result<Page<T>>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Result<Page<T>>>> {
+        let this = self.get_mut();
+        if this.pending.is_none() {
+            match this.cursor.next() {
+                Some(token) => this.pending = Some((this.fetch)(token)),
+                None => return Poll::Ready(None),
+            }
+        }
+        let page = match this.pending.as_mut().map(|f| f.as_mut().poll(cx)) {
+            Some(Poll::Ready(page)) => page,
+            _ => return Poll::Pending,
+        };
+        this.pending = None;
+        Poll::Ready(Some(this.cursor.advance(page)))
+    }
+}
+
+pub struct Items<'a, T> {
+    pages: Paginator<'a, T>,
+    current: ::std::vec::IntoIter<T>,
+}
+
+impl<'a, T> Iterator for Items<'a, T> {
+    type Item = Result<T>;
+
+    fn next(&mut self) -> Option<Result<T>> {
+        loop {
+            if let Some(item) = self.current.next() {
+                return Some(Ok(item));
+            }
+            match self.pages.next() {
+                Some(Ok(page)) => self.current = page.items.into_iter(),
+                Some(Err(e)) => return Some(Err(e)),
+                None => return None,
+            }
+        }
+    }
+}
+
+// Where a Query/EC2 operation keeps its pagination token and items, e.g.
+// DescribeInstances uses NextToken in, nextToken out and reservationSet/item.
+#[derive(Clone,Debug)]
+pub struct QueryPageSpec {
+    pub input_token: String,
+    pub output_token: String,
+    pub items: String,
+}
+
+impl QueryPageSpec {
+    pub fn new(input_token: &str, output_token: &str, items: &str) -> QueryPageSpec {
+        QueryPageSpec {
+            input_token: input_token.to_string(),
+            output_token: output_token.to_string(),
+            items: items.to_string(),
+        }
+    }
+
+    // The action's parameters, with the token for pages after the first.
+    pub fn params(&self, params: &[(String, String)], token: Option<&str>) -> Vec<(String, String)> {
+        let mut params = params.to_vec();
+        if let Some(token) = token {
+            params.push((self.input_token.clone(), token.to_string()));
+        }
+        params
+    }
+
+    pub fn page(&self, res: &QueryResponse) -> Page<Element> {
+        Page {
+            items: res.result.find_all(&self.items).into_iter().cloned().collect(),
+            next_token: res.result.find_text(&self.output_token),
+        }
+    }
+}
+
+// The same for a REST-XML operation, whose token goes in the query string,
+// e.g. S3's ListObjectsV2 uses continuation-token in,
+// NextContinuationToken out and Contents.
+#[derive(Clone,Debug)]
+pub struct RestPageSpec {
+    pub input_token: String,
+    pub output_token: String,
+    pub items: String,
+}
+
+impl RestPageSpec {
+    pub fn new(input_token: &str, output_token: &str, items: &str) -> RestPageSpec {
+        RestPageSpec {
+            input_token: input_token.to_string(),
+            output_token: output_token.to_string(),
+            items: items.to_string(),
+        }
+    }
+
+    pub fn request(&self, req: &RestRequest, token: Option<&str>) -> RestRequest {
+        match token {
+            Some(token) => req.clone().query(&self.input_token, token),
+            None => req.clone(),
+        }
+    }
+
+    pub fn page(&self, res: &HttpResponse) -> Result<Page<Element>> {
+        let body = res.xml().map_err(|e| Error::Parse(e.to_string()))?;
+        Ok(Page {
+            items: body.find_all(&self.items).into_iter().cloned().collect(),
+            next_token: body.find_text(&self.output_token),
+        })
+    }
+}
+
+// The same for a JSON operation, whose token is a member of the input. A
+// token can be any JSON value, like DynamoDB's LastEvaluatedKey, so it's
+// carried between pages as JSON text. Scan uses ExclusiveStartKey in,
+// LastEvaluatedKey out and Items.
+#[derive(Clone,Debug)]
+pub struct JsonPageSpec {
+    pub input_token: String,
+    pub output_token: String,
+    pub items: String,
+}
+
+impl JsonPageSpec {
+    pub fn new(input_token: &str, output_token: &str, items: &str) -> JsonPageSpec {
+        JsonPageSpec {
+            input_token: input_token.to_string(),
+            output_token: output_token.to_string(),
+            items: items.to_string(),
+        }
+    }
+
+    pub fn input(&self, input: &Json, token: Option<&str>) -> Result<Json> {
+        let token = match token {
+            Some(token) => Json::from_str(token).map_err(|e| Error::Parse(e.to_string()))?,
+            None => return Ok(input.clone()),
+        };
+        match *input {
+            Json::Object(ref obj) => {
+                let mut obj = obj.clone();
+                obj.insert(self.input_token.clone(), token);
+                Ok(Json::Object(obj))
+            },
+            Json::Null => Ok(Json::Object(vec!((self.input_token.clone(), token)).into_iter().collect())),
+            _ => Err(Error::Unsupported("paginated JSON input must be an object".to_string())),
+        }
+    }
+
+    pub fn page(&self, output: &Json) -> Page<Json> {
+        let find = |path: &str| output.find_path(&path.split('/').collect::<Vec<_>>()).cloned();
+        Page {
+            items: find(&self.items).and_then(|v| v.as_array().cloned()).unwrap_or_default(),
+            next_token: find(&self.output_token).and_then(|v| if v.is_null() { None } else { Some(v.to_string()) }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{JsonPageSpec, Page, Paginator, QueryPageSpec};
+    use serialize::json::Json;
+    use crate::error::Error;
+    use crate::protocol::query::parse_response;
+
+    fn pages() -> Vec<Page<u32>> {
+        vec!(Page { items: vec!(1, 2), next_token: Some("a".to_string()) },
+             Page { items: vec!(3, 4), next_token: Some("b".to_string()) },
+             Page { items: vec!(5), next_token: None })
+    }
+
+    fn fetcher(tokens: &mut Vec<Option<String>>) -> Paginator<'_, u32> {
+        let pages = pages();
+        Paginator::new(move |token: Option<&str>| {
+            tokens.push(token.map(|t| t.to_string()));
+            let index = match token {
+                None => 0,
+                Some("a") => 1,
+                Some("b") => 2,
+                _ => panic!("unexpected token"),
+            };
+            Ok(pages[index].clone())
+        })
+    }
+
+    #[test]
+    fn test_threads_tokens() {
+        let mut tokens = Vec::new();
+        let pages: Vec<Page<u32>> = fetcher(&mut tokens).map(|p| p.unwrap()).collect();
+        assert_eq!(pages.len(), 3);
+        assert_eq!(tokens, vec!(None, Some("a".to_string()), Some("b".to_string())))
+    }
+
+    #[test]
+    fn test_items() {
+        let mut tokens = Vec::new();
+        let items: Vec<u32> = fetcher(&mut tokens).items().map(|i| i.unwrap()).collect();
+        assert_eq!(items, vec!(1, 2, 3, 4, 5))
+    }
+
+    #[test]
+    fn test_max_items() {
+        let mut tokens = Vec::new();
+        let items: Vec<u32> = fetcher(&mut tokens).max_items(3).items().map(|i| i.unwrap()).collect();
+        assert_eq!(items, vec!(1, 2, 3));
+        assert_eq!(tokens.len(), 2)
+    }
+
+    #[test]
+    fn test_repeated_token() {
+        let mut paginator = Paginator::new(|_: Option<&str>| Ok(Page { items: vec!(1), next_token: Some("same".to_string()) }));
+        assert!(paginator.next().unwrap().is_ok());
+        match paginator.next() {
+            Some(Err(Error::Parse(_))) => {},
+            other => panic!("expected an error, got {:?}", other),
+        }
+        assert!(paginator.next().is_none())
+    }
+
+    #[test]
+    fn test_error_stops_iteration() {
+        let mut paginator: Paginator<u32> = Paginator::new(|_: Option<&str>| Err(Error::Unsupported("boom".to_string())));
+        assert!(paginator.next().unwrap().is_err());
+        assert!(paginator.next().is_none())
+    }
+
+    #[test]
+    fn test_json_page_spec() {
+        let spec = JsonPageSpec::new("ExclusiveStartKey", "LastEvaluatedKey", "Items");
+        let output = Json::from_str(r#"{"Items":[1,2],"LastEvaluatedKey":{"id":{"S":"2"}}}"#).unwrap();
+        let page = spec.page(&output);
+        assert_eq!(page.items, vec!(Json::U64(1), Json::U64(2)));
+
+        let input = spec.input(&Json::from_str(r#"{"TableName":"t"}"#).unwrap(), page.next_token.as_ref().map(|t| &t[..])).unwrap();
+        assert_eq!(input.to_string(), r#"{"ExclusiveStartKey":{"id":{"S":"2"}},"TableName":"t"}"#);
+        assert_eq!(spec.page(&Json::from_str(r#"{"Items":[],"LastEvaluatedKey":null}"#).unwrap()).next_token, None)
+    }
+
+    #[test]
+    fn test_query_page_spec() {
+        let body = r#"<ListUsersResponse><ListUsersResult>
+  <Users><member><UserName>a</UserName></member><member><UserName>b</UserName></member></Users>
+  <IsTruncated>true</IsTruncated><Marker>m1</Marker>
+</ListUsersResult></ListUsersResponse>"#;
+        let res = parse_response("ListUsers", body).unwrap();
+        let page = QueryPageSpec::new("Marker", "Marker", "Users/member").page(&res);
+        assert_eq!(page.items.len(), 2);
+        assert_eq!(page.items[1].find_text("UserName"), Some("b".to_string()));
+        assert_eq!(page.next_token, Some("m1".to_string()))
+    }
+}
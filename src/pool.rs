@@ -0,0 +1,603 @@
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full};
+use hyper::rt::{Read, ReadBufCursor, Write};
+use hyper_util::client::legacy::connect::{Connected, Connection};
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::{TokioExecutor, TokioIo, TokioTimer};
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::result;
+use std::sync::{Arc, Mutex, OnceLock, Weak};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+use tokio::net::TcpStream;
+use tokio::sync::Semaphore;
+use tokio::time::{self, Sleep};
+use tokio_openssl::SslStream;
+use tower_service::Service;
+
+use crate::error::{Error, Result, TimeoutKind};
+use crate::proxy::{Proxy, ProxyConfig};
+use crate::response::HttpResponse;
+use crate::runtime::{self, BoxFuture};
+use crate::tls::{TlsConfig, TlsConnector};
+use crate::transport::{HttpRequest, HttpTransport};
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct PoolConfig {
+    max_idle: usize,
+    max_connections: Option<usize>,
+    idle_timeout: Option<Duration>,
+    keep_alive: bool,
+    connect_timeout: Option<Duration>,
+    read_timeout: Option<Duration>,
+    proxy: ProxyConfig,
+}
+
+impl Default for PoolConfig {
+    fn default() -> PoolConfig {
+        PoolConfig::new()
+    }
+}
+
+impl PoolConfig {
+    pub fn new() -> PoolConfig {
+        PoolConfig {
+            max_idle: 10,
+            max_connections: None,
+            idle_timeout: Some(Duration::from_secs(90)),
+            keep_alive: true,
+            connect_timeout: None,
+            read_timeout: None,
+            proxy: ProxyConfig::from_env(),
+        }
+    }
+
+    // Idle connections kept per host.
+    pub fn max_idle(mut self, max_idle: usize) -> PoolConfig {
+        self.max_idle = max_idle;
+        self
+    }
+
+    // Requests in flight to one host, and so connections open to it, at a
+    // time; others wait their turn. None, the default, doesn't limit them.
+    pub fn max_connections(mut self, max: Option<usize>) -> PoolConfig {
+        self.max_connections = max.map(|m| m.max(1));
+        self
+    }
+
+    // How long an idle connection is kept before being dropped; None keeps
+    // them until the server closes them.
+    pub fn idle_timeout(mut self, timeout: Option<Duration>) -> PoolConfig {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    // Without keep-alive every request asks the server to close the
+    // connection, so nothing is reused.
+    pub fn keep_alive(mut self, keep_alive: bool) -> PoolConfig {
+        self.keep_alive = keep_alive;
+        self
+    }
+
+    pub fn connect_timeout(mut self, timeout: Option<Duration>) -> PoolConfig {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    // Applies to each read from the socket, not to the whole response. A
+    // pooled connection left idle that long is closed as well.
+    pub fn read_timeout(mut self, timeout: Option<Duration>) -> PoolConfig {
+        self.read_timeout = timeout;
+        self
+    }
+
+    // Defaults to the proxy environment variables.
+    pub fn proxy(mut self, proxy: ProxyConfig) -> PoolConfig {
+        self.proxy = proxy;
+        self
+    }
+
+    pub fn proxy_config(&self) -> &ProxyConfig {
+        &self.proxy
+    }
+
+    pub fn is_keep_alive(&self) -> bool {
+        self.keep_alive
+    }
+
+    pub fn timeouts(&self) -> (Option<Duration>, Option<Duration>) {
+        (self.connect_timeout, self.read_timeout)
+    }
+}
+
+// The connections, and when they're limited the permits for each host's.
+struct Connections {
+    client: Client<Connector, Full<Bytes>>,
+    limits: Mutex<HashMap<String, Arc<Semaphore>>>,
+}
+
+// A live pool reconfigured from the same original, by its settings.
+struct Variant {
+    config: PoolConfig,
+    tls_config: TlsConfig,
+    tls: TlsConnector,
+    client: Weak<Connections>,
+}
+
+type Variants = Arc<Mutex<Vec<Variant>>>;
+
+// A pooled hyper client, and the transport ApiClients use by default.
+// Clones share the same connections, so one pool can serve every ApiClient
+// in the process.
+#[derive(Clone)]
+pub struct HttpPool {
+    client: Arc<Connections>,
+    config: PoolConfig,
+    tls_config: TlsConfig,
+    tls: TlsConnector,
+    variants: Variants,
+}
+
+impl HttpPool {
+    // TLS settings come from AWS_CA_BUNDLE or the profile, and it fails if
+    // they can't be used.
+    pub fn new(config: PoolConfig) -> Result<HttpPool> {
+        HttpPool::with_tls(config, &TlsConfig::from_env())
+    }
+
+    pub fn with_tls(config: PoolConfig, tls: &TlsConfig) -> Result<HttpPool> {
+        let pool = HttpPool::build(config, tls.clone(), tls.connector()?, Variants::default());
+        pool.variants.lock().unwrap().push(pool.variant());
+        Ok(pool)
+    }
+
+    // A pool with different connection settings but the same TLS setup.
+    // Pools reconfigured from the same one with equal settings share
+    // connections, for as long as any of them is in use.
+    pub fn reconfigure(&self, config: PoolConfig) -> HttpPool {
+        if config == self.config {
+            return self.clone();
+        }
+        let mut variants = self.variants.lock().unwrap();
+        self.find(&mut variants, &config, &self.tls_config).unwrap_or_else(|| {
+            let pool = HttpPool::build(config, self.tls_config.clone(), self.tls.clone(), self.variants.clone());
+            variants.push(pool.variant());
+            pool
+        })
+    }
+
+    // A pool with the same connection settings but different TLS setup,
+    // shared as reconfigure's are. Fails if `tls` can't be used.
+    pub fn reconfigure_tls(&self, tls: &TlsConfig) -> Result<HttpPool> {
+        if *tls == self.tls_config {
+            return Ok(self.clone());
+        }
+        let mut variants = self.variants.lock().unwrap();
+        if let Some(pool) = self.find(&mut variants, &self.config, tls) {
+            return Ok(pool);
+        }
+        let pool = HttpPool::build(self.config.clone(), tls.clone(), tls.connector()?, self.variants.clone());
+        variants.push(pool.variant());
+        Ok(pool)
+    }
+
+    fn find(&self, variants: &mut Vec<Variant>, config: &PoolConfig, tls_config: &TlsConfig) -> Option<HttpPool> {
+        variants.retain(|v| v.client.strong_count() > 0);
+        let variant = variants.iter().find(|v| v.config == *config && v.tls_config == *tls_config)?;
+        Some(HttpPool {
+            client: variant.client.upgrade()?,
+            config: config.clone(),
+            tls_config: tls_config.clone(),
+            tls: variant.tls.clone(),
+            variants: self.variants.clone(),
+        })
+    }
+
+    fn variant(&self) -> Variant {
+        Variant {
+            config: self.config.clone(),
+            tls_config: self.tls_config.clone(),
+            tls: self.tls.clone(),
+            client: Arc::downgrade(&self.client),
+        }
+    }
+
+    fn build(config: PoolConfig, tls_config: TlsConfig, tls: TlsConnector, variants: Variants) -> HttpPool {
+        let connector = Connector {
+            timeout: config.connect_timeout,
+            read_timeout: config.read_timeout,
+            proxy: config.proxy.clone(),
+            tls: tls.clone(),
+        };
+        let client = Client::builder(TokioExecutor::new())
+            .pool_max_idle_per_host(config.max_idle)
+            .pool_idle_timeout(config.idle_timeout)
+            .pool_timer(TokioTimer::new())
+            .build(connector);
+        HttpPool {
+            client: Arc::new(Connections { client, limits: Mutex::new(HashMap::new()) }),
+            config,
+            tls_config,
+            tls,
+            variants,
+        }
+    }
+
+    // The process-wide pool ApiClients use unless given their own. Every
+    // call fails the same way if the first couldn't set it up.
+    pub fn shared() -> Result<HttpPool> {
+        static SHARED: OnceLock<result::Result<HttpPool, String>> = OnceLock::new();
+        match *SHARED.get_or_init(|| HttpPool::new(PoolConfig::new()).map_err(|e| e.to_string())) {
+            Ok(ref pool) => Ok(pool.clone()),
+            Err(ref e) => Err(Error::Config(e.clone())),
+        }
+    }
+
+    pub fn config(&self) -> &PoolConfig {
+        &self.config
+    }
+
+    pub fn same_pool(&self, other: &HttpPool) -> bool {
+        Arc::ptr_eq(&self.client, &other.client)
+    }
+
+    fn host_limit(&self, url: &str) -> Option<Arc<Semaphore>> {
+        let max = self.config.max_connections?;
+        let host = url.parse::<http::Uri>().ok().and_then(|u| u.authority().map(|a| a.to_string())).unwrap_or_default();
+        let mut limits = self.client.limits.lock().unwrap();
+        Some(limits.entry(host).or_insert_with(|| Arc::new(Semaphore::new(max))).clone())
+    }
+}
+
+impl HttpTransport for HttpPool {
+    // Runs on the shared runtime, which the pool's connections belong to.
+    fn send(&self, req: HttpRequest) -> BoxFuture<Result<HttpResponse>> {
+        let client = self.client.clone();
+        let keep_alive = self.config.keep_alive;
+        let limit = self.host_limit(&req.url);
+        Box::pin(runtime::spawn(async move {
+            let _permit = match limit {
+                Some(limit) => Some(limit.acquire_owned().await.map_err(|e| Error::Http(Box::new(e)))?),
+                None => None,
+            };
+            let HttpRequest { method, url, headers, body } = req;
+            let mut builder = http::Request::builder().method(&method[..]).uri(&url[..]);
+            for (k, v) in headers.iter() {
+                builder = builder.header(&k[..], &v[..]);
+            }
+            if !keep_alive {
+                builder = builder.header("connection", "close");
+            }
+            let req = builder.body(Full::new(Bytes::from(body)))
+                .map_err(|e| Error::Config(format!("{} {}: {}", method, url, e)))?;
+
+            let res = client.client.request(req).await.map_err(transport_error)?;
+            let status = res.status().as_u16();
+            let headers = res.headers().iter()
+                .map(|(k, v)| (k.as_str().to_string(), String::from_utf8_lossy(v.as_bytes()).into_owned()))
+                .collect();
+            let body = res.into_body().collect().await.map_err(transport_error)?.to_bytes();
+            Ok(HttpResponse {
+                status,
+                headers,
+                body: body.to_vec(),
+            })
+        }))
+    }
+}
+
+impl fmt::Debug for HttpPool {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "HttpPool {{ config: {:?}, tls: {:?} }}", self.config, self.tls)
+    }
+}
+
+#[derive(Debug)]
+struct ConnectTimeout;
+
+impl fmt::Display for ConnectTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "connect timed out")
+    }
+}
+
+impl error::Error for ConnectTimeout {}
+
+// Connects directly or, when a proxy applies, through a CONNECT tunnel,
+// giving up after the connect timeout. Plain http is tunnelled too, so the
+// request hyper writes is exactly the one that was signed for the origin.
+#[derive(Clone,Debug)]
+struct Connector {
+    timeout: Option<Duration>,
+    read_timeout: Option<Duration>,
+    proxy: ProxyConfig,
+    tls: TlsConnector,
+}
+
+impl Connector {
+    async fn tcp(&self, host: &str, port: u16) -> io::Result<TcpStream> {
+        within(self.timeout, TcpStream::connect((host, port))).await
+    }
+
+    async fn connect(self, uri: http::Uri) -> io::Result<Stream> {
+        let scheme = uri.scheme_str().unwrap_or("http");
+        let host = match uri.host() {
+            Some(host) => host.trim_start_matches('[').trim_end_matches(']'),
+            None => return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("no host in {}", uri))),
+        };
+        let port = uri.port_u16().unwrap_or(if scheme == "https" { 443 } else { 80 });
+
+        let stream = match self.proxy.proxy_for(scheme, host, port) {
+            None => self.tcp(host, port).await?,
+            Some(proxy) => {
+                let mut stream = self.tcp(proxy.host(), proxy.port()).await?;
+                within(self.timeout, tunnel(&mut stream, host, port, proxy)).await?;
+                stream
+            }
+        };
+        stream.set_nodelay(true)?;
+        let io = if scheme == "https" {
+            Io::Tls(self.tls.connect(host, stream).await?)
+        } else {
+            Io::Plain(stream)
+        };
+        Ok(Stream {
+            io: TokioIo::new(io),
+            read_timeout: self.read_timeout,
+            timer: None,
+        })
+    }
+}
+
+impl Service<http::Uri> for Connector {
+    type Response = Stream;
+    type Error = io::Error;
+    type Future = BoxFuture<io::Result<Stream>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, uri: http::Uri) -> Self::Future {
+        Box::pin(self.clone().connect(uri))
+    }
+}
+
+// Runs a step of connecting, failing with ConnectTimeout if it takes too long.
+async fn within<T, F: Future<Output = io::Result<T>>>(timeout: Option<Duration>, f: F) -> io::Result<T> {
+    match timeout {
+        None => f.await,
+        Some(t) => match time::timeout(t, f).await {
+            Ok(res) => res,
+            Err(_) => Err(io::Error::new(io::ErrorKind::TimedOut, ConnectTimeout)),
+        },
+    }
+}
+
+async fn tunnel(stream: &mut TcpStream, host: &str, port: u16, proxy: &Proxy) -> io::Result<()> {
+    let mut req = format!("CONNECT {0}:{1} HTTP/1.1\r\nHost: {0}:{1}\r\n", host, port);
+    if let Some(auth) = proxy.authorization() {
+        req.push_str(&format!("Proxy-Authorization: {}\r\n", auth));
+    }
+    req.push_str("\r\n");
+    stream.write_all(req.as_bytes()).await?;
+
+    // A byte at a time, so nothing after the proxy's reply is consumed.
+    let mut head = Vec::new();
+    let mut byte = [0u8; 1];
+    while !head.ends_with(b"\r\n\r\n") {
+        if stream.read(&mut byte).await? == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "proxy closed the connection"));
+        }
+        head.push(byte[0]);
+        if head.len() > 8192 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "proxy response too long"));
+        }
+    }
+    let head = String::from_utf8_lossy(&head);
+    let status = head.lines().next().unwrap_or("");
+    if status.split_whitespace().nth(1).is_some_and(|code| code.starts_with('2')) {
+        Ok(())
+    } else {
+        Err(io::Error::other(format!("proxy refused tunnel to {}:{}: {}", host, port, status)))
+    }
+}
+
+enum Io {
+    Plain(TcpStream),
+    Tls(SslStream<TcpStream>),
+}
+
+impl AsyncRead for Io {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context, buf: &mut ReadBuf) -> Poll<io::Result<()>> {
+        match *self.get_mut() {
+            Io::Plain(ref mut s) => Pin::new(s).poll_read(cx, buf),
+            Io::Tls(ref mut s) => Pin::new(s).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Io {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+        match *self.get_mut() {
+            Io::Plain(ref mut s) => Pin::new(s).poll_write(cx, buf),
+            Io::Tls(ref mut s) => Pin::new(s).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        match *self.get_mut() {
+            Io::Plain(ref mut s) => Pin::new(s).poll_flush(cx),
+            Io::Tls(ref mut s) => Pin::new(s).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        match *self.get_mut() {
+            Io::Plain(ref mut s) => Pin::new(s).poll_shutdown(cx),
+            Io::Tls(ref mut s) => Pin::new(s).poll_shutdown(cx),
+        }
+    }
+}
+
+// A connection whose reads give up after the read timeout.
+struct Stream {
+    io: TokioIo<Io>,
+    read_timeout: Option<Duration>,
+    timer: Option<Pin<Box<Sleep>>>,
+}
+
+impl Read for Stream {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context, buf: ReadBufCursor) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if let Poll::Ready(res) = Pin::new(&mut this.io).poll_read(cx, buf) {
+            this.timer = None;
+            return Poll::Ready(res);
+        }
+        if let Some(timeout) = this.read_timeout {
+            let timer = this.timer.get_or_insert_with(|| Box::pin(time::sleep(timeout)));
+            if timer.as_mut().poll(cx).is_ready() {
+                this.timer = None;
+                return Poll::Ready(Err(io::Error::new(io::ErrorKind::TimedOut, "read timed out")));
+            }
+        }
+        Poll::Pending
+    }
+}
+
+impl Write for Stream {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().io).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().io).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().io).poll_shutdown(cx)
+    }
+}
+
+impl Connection for Stream {
+    fn connected(&self) -> Connected {
+        Connected::new()
+    }
+}
+
+// Turns socket timeouts into Error::Timeout, telling connect and read apart,
+// and other socket errors into Error::Io so they can be retried by kind.
+pub fn transport_error<E: error::Error + Send + Sync + 'static>(err: E) -> Error {
+    let mut source: Option<&(dyn error::Error + 'static)> = Some(&err);
+    while let Some(e) = source {
+        if let Some(io) = e.downcast_ref::<io::Error>() {
+            return io_error(io::Error::new(io.kind(), err.to_string()), is_connect_timeout(io));
+        }
+        source = e.source();
+    }
+    Error::Http(Box::new(err))
+}
+
+fn is_connect_timeout(err: &io::Error) -> bool {
+    err.get_ref().is_some_and(|e| e.is::<ConnectTimeout>())
+}
+
+fn io_error(err: io::Error, connect: bool) -> Error {
+    match err.kind() {
+        io::ErrorKind::TimedOut if connect => Error::Timeout(TimeoutKind::Connect),
+        io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => Error::Timeout(TimeoutKind::Read),
+        _ => Error::Io(err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{HttpPool, PoolConfig, ConnectTimeout, transport_error};
+    use crate::error::{Error, TimeoutKind};
+    use crate::runtime;
+    use crate::test_server::TestServer;
+    use crate::tls::TlsConfig;
+    use crate::transport::{HttpRequest, HttpTransport};
+    use std::sync::Arc;
+    use std::io;
+    use std::time::Duration;
+
+    #[test]
+    fn test_shared_pool() {
+        assert!(HttpPool::shared().unwrap().same_pool(&HttpPool::shared().unwrap()));
+        assert!(!HttpPool::shared().unwrap().same_pool(&HttpPool::new(PoolConfig::new()).unwrap()))
+    }
+
+    #[test]
+    fn test_reconfigured_pools_are_shared() {
+        let base = HttpPool::new(PoolConfig::new()).unwrap();
+        let short = PoolConfig::new().connect_timeout(Some(Duration::from_secs(1)));
+        let a = base.reconfigure(short.clone());
+        assert!(a.same_pool(&base.reconfigure(short.clone())));
+        assert!(a.same_pool(&a.reconfigure(short)));
+        assert!(!a.same_pool(&base.reconfigure(PoolConfig::new().max_idle(1))));
+        assert!(base.same_pool(&a.reconfigure(PoolConfig::new())))
+    }
+
+    #[test]
+    fn test_reconfigured_tls_pools_are_shared() {
+        let base = HttpPool::with_tls(PoolConfig::new(), &TlsConfig::new()).unwrap();
+        let tls = TlsConfig::new().ca_bundle("fixtures/tls/ca.pem");
+        let a = base.reconfigure_tls(&tls).unwrap();
+        assert!(!a.same_pool(&base));
+        assert!(a.same_pool(&base.reconfigure_tls(&tls).unwrap()));
+        assert!(base.same_pool(&a.reconfigure_tls(&TlsConfig::new()).unwrap()));
+
+        let short = PoolConfig::new().connect_timeout(Some(Duration::from_secs(1)));
+        assert!(!a.reconfigure(short.clone()).same_pool(&base.reconfigure(short)));
+        assert!(base.reconfigure_tls(&TlsConfig::new().ca_bundle("fixtures/tls/missing.pem")).is_err())
+    }
+
+    #[test]
+    fn test_max_connections() {
+        let pool = HttpPool::new(PoolConfig::new().max_connections(Some(1))).unwrap();
+        let limit = pool.host_limit("https://s3.amazonaws.com/a").unwrap();
+        assert!(Arc::ptr_eq(&limit, &pool.clone().host_limit("https://s3.amazonaws.com/b").unwrap()));
+        assert!(!Arc::ptr_eq(&limit, &pool.host_limit("https://iam.amazonaws.com/").unwrap()));
+        assert_eq!(limit.available_permits(), 1);
+        assert!(HttpPool::new(PoolConfig::new()).unwrap().host_limit("https://s3.amazonaws.com/").is_none());
+
+        let server = TestServer::new(vec!((200, "one"), (200, "two")));
+        let request = || HttpRequest { method: "GET".to_string(), url: server.url.clone(), headers: Vec::new(), body: Vec::new() };
+        let (one, two) = (pool.send(request()), pool.send(request()));
+        let (one, two) = runtime::block_on(async move { tokio::join!(one, two) });
+        assert!(one.is_ok() && two.is_ok());
+        assert_eq!(limit.available_permits(), 1)
+    }
+
+    #[test]
+    fn test_config() {
+        let config = PoolConfig::new().max_idle(2).idle_timeout(Some(Duration::from_secs(5))).keep_alive(false);
+        let pool = HttpPool::new(config.clone()).unwrap();
+        assert!(HttpPool::with_tls(config.clone(), &TlsConfig::new().ca_bundle("fixtures/tls/missing.pem")).is_err());
+        assert_eq!(pool.config(), &config);
+        assert!(!pool.config().is_keep_alive())
+    }
+
+    #[test]
+    fn test_timeout_errors() {
+        match transport_error(io::Error::new(io::ErrorKind::TimedOut, ConnectTimeout)) {
+            Error::Timeout(TimeoutKind::Connect) => {},
+            other => panic!("expected a connect timeout, got {:?}", other),
+        }
+        match transport_error(io::Error::new(io::ErrorKind::TimedOut, "read timed out")) {
+            Error::Timeout(TimeoutKind::Read) => {},
+            other => panic!("expected a read timeout, got {:?}", other),
+        }
+        match transport_error(io::Error::new(io::ErrorKind::ConnectionReset, "reset")) {
+            Error::Io(ref e) if e.kind() == io::ErrorKind::ConnectionReset => {},
+            other => panic!("expected an io error, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,103 @@
+use serialize::json::Json;
+
+use crate::error::ServiceError;
+
+// The X-Amz-Target prefix and application/x-amz-json version for each JSON
+// protocol service.
+pub fn service_metadata(service: &str) -> Option<(&'static str, &'static str)> {
+    let meta = match service {
+        "dynamodb" => ("DynamoDB_20120810", "1.0"),
+        "ecr" => ("AmazonEC2ContainerRegistry_V20150921", "1.1"),
+        "kinesis" => ("Kinesis_20131202", "1.1"),
+        "kms" => ("TrentService", "1.1"),
+        "logs" => ("Logs_20140328", "1.1"),
+        "secretsmanager" => ("secretsmanager", "1.1"),
+        "ssm" => ("AmazonSSM", "1.1"),
+        _ => return None,
+    };
+    Some(meta)
+}
+
+pub fn content_type(version: &str) -> String {
+    format!("application/x-amz-json-{}", version)
+}
+
+pub fn target(prefix: &str, operation: &str) -> String {
+    format!("{}.{}", prefix, operation)
+}
+
+pub fn parse_body(body: &str) -> Result<Json, String> {
+    if body.trim().is_empty() {
+        return Ok(Json::from_str("{}").unwrap());
+    }
+    Json::from_str(body).map_err(|e| e.to_string())
+}
+
+// Error codes arrive as `__type` (or the x-amzn-errortype header) and may be
+// namespaced (`com.amazonaws.dynamodb.v20120810#ResourceNotFoundException`)
+// or carry a trailing `:http://...` uri.
+pub fn parse_error(status: u16, request_id: Option<String>, error_type: Option<String>, body: &str) -> ServiceError {
+    fn field(json: &Option<Json>, names: &[&str]) -> Option<String> {
+        match *json {
+            Some(ref j) => names.iter()
+                .filter_map(|n| j.find(n).and_then(|v| v.as_string()))
+                .next()
+                .map(|v| v.to_string()),
+            None => None,
+        }
+    }
+
+    let json = Json::from_str(body).ok();
+    let code = error_type.or(field(&json, &["__type", "code"])).unwrap_or_default();
+    let code = code.rsplit('#').next().unwrap_or("").split(':').next().unwrap_or("").to_string();
+    let code = if !code.is_empty() { code } else { format!("Http{}", status) };
+
+    ServiceError {
+        code,
+        message: field(&json, &["message", "Message", "errorMessage"]).unwrap_or_default(),
+        request_id,
+        status,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_error, parse_body, service_metadata, target};
+
+    #[test]
+    fn test_target() {
+        let (prefix, version) = service_metadata("dynamodb").unwrap();
+        assert_eq!(target(prefix, "ListTables"), "DynamoDB_20120810.ListTables");
+        assert_eq!(version, "1.0")
+    }
+
+    #[test]
+    fn test_parse_namespaced_error() {
+        let body = r#"{"__type":"com.amazonaws.dynamodb.v20120810#ResourceNotFoundException","message":"Requested resource not found"}"#;
+        let err = parse_error(400, Some("abc".to_string()), None, body);
+        assert_eq!(err.code, "ResourceNotFoundException");
+        assert_eq!(err.message, "Requested resource not found");
+        assert_eq!(err.request_id, Some("abc".to_string()));
+        assert_eq!(err.status, 400)
+    }
+
+    #[test]
+    fn test_parse_error_header_wins() {
+        let body = r#"{"__type":"Other","Message":"bad"}"#;
+        let err = parse_error(400, None, Some("ValidationException:http://internal.amazon.com/coral/".to_string()), body);
+        assert_eq!(err.code, "ValidationException");
+        assert_eq!(err.message, "bad")
+    }
+
+    #[test]
+    fn test_parse_error_empty_body() {
+        let err = parse_error(503, None, None, "");
+        assert_eq!(err.code, "Http503");
+        assert_eq!(err.message, "")
+    }
+
+    #[test]
+    fn test_parse_empty_body() {
+        assert!(parse_body("").unwrap().is_object())
+    }
+}
@@ -0,0 +1,94 @@
+use crate::error::ServiceError;
+use crate::response::HttpResponse;
+use crate::xml::Element;
+
+pub mod json;
+pub mod query;
+pub mod rest;
+
+// Parses the XML error bodies: S3's bare <Error>, the <ErrorResponse><Error>
+// used by Query and the other XML services, and EC2's <Response><Errors><Error>.
+pub fn xml_error(status: u16, request_id: Option<String>, body: &str) -> ServiceError {
+    let root = Element::parse(body).ok();
+    let error = root.as_ref().and_then(|r| match &r.name[..] {
+        "Error" => Some(r),
+        "ErrorResponse" => r.get("Error"),
+        "Response" => r.find("Errors/Error"),
+        _ => None,
+    });
+
+    let text = |name: &str| error.and_then(|e| e.find_text(name)).unwrap_or_default();
+    let code = text("Code");
+    let body_request_id = root.as_ref().and_then(|r| {
+        r.find_text("RequestId")
+            .or(r.find_text("RequestID"))
+            .or(r.find_text("Error/RequestId"))
+    });
+
+    ServiceError {
+        code: if !code.is_empty() { code } else { format!("Http{}", status) },
+        message: text("Message"),
+        request_id: request_id.or(body_request_id),
+        status,
+    }
+}
+
+// For when the protocol isn't known, e.g. when deciding whether to retry.
+pub fn service_error(res: &HttpResponse) -> ServiceError {
+    let body = res.body_string();
+    if body.trim_start().starts_with("<") {
+        xml_error(res.status, res.request_id(), &body)
+    } else {
+        let error_type = res.header("x-amzn-ErrorType").map(|v| v.to_string());
+        json::parse_error(res.status, res.request_id(), error_type, &body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::xml_error;
+
+    #[test]
+    fn test_s3_error() {
+        let err = xml_error(403, None, "<Error><Code>AccessDenied</Code><Message>Access Denied</Message><RequestId>ABC</RequestId></Error>");
+        assert_eq!(err.code, "AccessDenied");
+        assert_eq!(err.message, "Access Denied");
+        assert_eq!(err.request_id, Some("ABC".to_string()))
+    }
+
+    #[test]
+    fn test_error_response() {
+        let body = r#"<ErrorResponse xmlns="https://route53.amazonaws.com/doc/2013-04-01/">
+  <Error><Type>Sender</Type><Code>NoSuchHostedZone</Code><Message>No hosted zone found</Message></Error>
+  <RequestId>d6a5b7f1</RequestId>
+</ErrorResponse>"#;
+        let err = xml_error(404, None, body);
+        assert_eq!(err.code, "NoSuchHostedZone");
+        assert_eq!(err.request_id, Some("d6a5b7f1".to_string()))
+    }
+
+    #[test]
+    fn test_ec2_error() {
+        let body = r#"<?xml version="1.0" encoding="UTF-8"?>
+<Response><Errors><Error><Code>InvalidInstanceID.NotFound</Code><Message>The instance ID 'i-1a2b3c4d' does not exist</Message></Error></Errors><RequestID>ea966190-f9aa-478e-9ede-example</RequestID></Response>"#;
+        let err = xml_error(400, None, body);
+        assert_eq!(err.code, "InvalidInstanceID.NotFound");
+        assert_eq!(err.message, "The instance ID 'i-1a2b3c4d' does not exist");
+        assert_eq!(err.request_id, Some("ea966190-f9aa-478e-9ede-example".to_string()))
+    }
+
+    #[test]
+    fn test_query_throttling() {
+        let body = r#"<ErrorResponse xmlns="https://iam.amazonaws.com/doc/2010-05-08/"><Error><Type>Sender</Type><Code>Throttling</Code><Message>Rate exceeded</Message></Error><RequestId>7a62c49f</RequestId></ErrorResponse>"#;
+        let err = xml_error(400, None, body);
+        assert_eq!(err.code, "Throttling");
+        assert_eq!(err.status, 400)
+    }
+
+    #[test]
+    fn test_empty_error_body() {
+        let err = xml_error(404, Some("hdr".to_string()), "");
+        assert_eq!(err.code, "Http404");
+        assert_eq!(err.request_id, Some("hdr".to_string()))
+    }
+}
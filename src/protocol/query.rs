@@ -0,0 +1,320 @@
+use std::collections::BTreeMap;
+
+use crate::xml::{Element, ParseError};
+
+// EC2 speaks a dialect of the Query protocol where lists are always flattened
+// (`Filter.1.Value.1` rather than `Filter.member.1.Value.member.1`).
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum Flavor {
+    Query,
+    Ec2,
+}
+
+pub fn api_version(service: &str) -> Option<&'static str> {
+    let version = match service {
+        "autoscaling" => "2011-01-01",
+        "cloudformation" => "2010-05-15",
+        "ec2" => "2016-11-15",
+        "elasticache" => "2015-02-02",
+        "elasticbeanstalk" => "2010-12-01",
+        "elasticloadbalancing" => "2015-12-01",
+        "email" => "2010-12-01",
+        "iam" => "2010-05-08",
+        "monitoring" => "2010-08-01",
+        "rds" => "2014-10-31",
+        "redshift" => "2012-12-01",
+        "sdb" => "2009-04-15",
+        "sns" => "2010-03-31",
+        "sqs" => "2012-11-05",
+        "sts" => "2011-06-15",
+        _ => return None,
+    };
+    Some(version)
+}
+
+pub fn flavor(service: &str) -> Flavor {
+    match service {
+        "ec2" => Flavor::Ec2,
+        _ => Flavor::Query,
+    }
+}
+
+#[derive(Clone,Debug)]
+pub struct QueryResponse {
+    pub request_id: Option<String>,
+    pub result: Element,
+}
+
+// Query responses wrap the result in <ActionResult> beside <ResponseMetadata>;
+// EC2 puts the members and a <requestId> directly under the root.
+pub fn parse_response(action: &str, body: &str) -> Result<QueryResponse, ParseError> {
+    let root = Element::parse(body)?;
+    let request_id = root.find_text("ResponseMetadata/RequestId").or(root.find_text("requestId"));
+    let wrapped = root.get(&format!("{}Result", action)).cloned();
+    Ok(QueryResponse {
+        request_id,
+        result: wrapped.unwrap_or(root),
+    })
+}
+
+pub trait ToQuery {
+    fn to_query(&self, prefix: &str, s: &mut QuerySerializer);
+}
+
+#[derive(Clone,Debug)]
+pub struct QuerySerializer {
+    flavor: Flavor,
+    params: Vec<(String, String)>,
+}
+
+impl QuerySerializer {
+    pub fn new(flavor: Flavor) -> QuerySerializer {
+        QuerySerializer {
+            flavor,
+            params: Vec::new(),
+        }
+    }
+
+    // Starts a request body with Action and the service's API version.
+    pub fn for_action(service: &str, action: &str) -> QuerySerializer {
+        let mut s = QuerySerializer::new(flavor(service));
+        s.param("Action", action);
+        if let Some(version) = api_version(service) {
+            s.param("Version", version);
+        }
+        s
+    }
+
+    pub fn param(&mut self, key: &str, value: &str) {
+        self.params.push((key.to_string(), value.to_string()));
+    }
+
+    pub fn field<T: ToQuery + ?Sized>(&mut self, prefix: &str, name: &str, value: &T) {
+        value.to_query(&join(prefix, name), self);
+    }
+
+    pub fn list<T: ToQuery>(&mut self, prefix: &str, items: &[T]) {
+        match self.flavor {
+            Flavor::Ec2 => self.flat_list(prefix, items),
+            Flavor::Query => {
+                // an empty list is sent as a bare key so the service clears it
+                if items.is_empty() {
+                    self.param(prefix, "");
+                }
+                for (i, item) in items.iter().enumerate() {
+                    item.to_query(&format!("{}.member.{}", prefix, i + 1), self);
+                }
+            },
+        }
+    }
+
+    pub fn flat_list<T: ToQuery>(&mut self, prefix: &str, items: &[T]) {
+        for (i, item) in items.iter().enumerate() {
+            item.to_query(&format!("{}.{}", prefix, i + 1), self);
+        }
+    }
+
+    pub fn map<V: ToQuery>(&mut self, prefix: &str, entries: &BTreeMap<String, V>) {
+        for (i, (key, value)) in entries.iter().enumerate() {
+            let entry = format!("{}.entry.{}", prefix, i + 1);
+            self.param(&join(&entry, "key"), key);
+            value.to_query(&join(&entry, "value"), self);
+        }
+    }
+
+    pub fn params(self) -> Vec<(String, String)> {
+        self.params
+    }
+}
+
+pub fn join(prefix: &str, name: &str) -> String {
+    if prefix.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}.{}", prefix, name)
+    }
+}
+
+impl ToQuery for () {
+    fn to_query(&self, _: &str, _: &mut QuerySerializer) {}
+}
+
+impl ToQuery for str {
+    fn to_query(&self, prefix: &str, s: &mut QuerySerializer) {
+        s.param(prefix, self);
+    }
+}
+
+impl ToQuery for String {
+    fn to_query(&self, prefix: &str, s: &mut QuerySerializer) {
+        s.param(prefix, self);
+    }
+}
+
+impl<T: ToQuery + ?Sized> ToQuery for &T {
+    fn to_query(&self, prefix: &str, s: &mut QuerySerializer) {
+        (**self).to_query(prefix, s);
+    }
+}
+
+macro_rules! display_to_query {
+    ($($t:ty),*) => (
+        $(impl ToQuery for $t {
+            fn to_query(&self, prefix: &str, s: &mut QuerySerializer) {
+                s.param(prefix, &self.to_string());
+            }
+        })*
+    )
+}
+
+display_to_query!(bool, i32, i64, u32, u64, f64);
+
+impl<T: ToQuery> ToQuery for Option<T> {
+    fn to_query(&self, prefix: &str, s: &mut QuerySerializer) {
+        if let Some(ref v) = *self {
+            v.to_query(prefix, s);
+        }
+    }
+}
+
+impl<T: ToQuery> ToQuery for Vec<T> {
+    fn to_query(&self, prefix: &str, s: &mut QuerySerializer) {
+        s.list(prefix, self);
+    }
+}
+
+impl<V: ToQuery> ToQuery for BTreeMap<String, V> {
+    fn to_query(&self, prefix: &str, s: &mut QuerySerializer) {
+        s.map(prefix, self);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ToQuery, QuerySerializer, Flavor, api_version, parse_response};
+    use std::collections::BTreeMap;
+
+    struct Filter {
+        name: String,
+        values: Vec<String>,
+    }
+
+    impl ToQuery for Filter {
+        fn to_query(&self, prefix: &str, s: &mut QuerySerializer) {
+            s.field(prefix, "Name", &self.name);
+            s.field(prefix, "Value", &self.values);
+        }
+    }
+
+    struct DescribeInstances {
+        filters: Vec<Filter>,
+        max_results: Option<i32>,
+    }
+
+    impl ToQuery for DescribeInstances {
+        fn to_query(&self, prefix: &str, s: &mut QuerySerializer) {
+            s.field(prefix, "Filter", &self.filters);
+            s.field(prefix, "MaxResults", &self.max_results);
+        }
+    }
+
+    fn serialize<T: ToQuery>(flavor: Flavor, input: &T) -> Vec<(String, String)> {
+        let mut s = QuerySerializer::new(flavor);
+        input.to_query("", &mut s);
+        s.params()
+    }
+
+    fn pair(k: &str, v: &str) -> (String, String) {
+        (k.to_string(), v.to_string())
+    }
+
+    #[test]
+    fn test_api_versions() {
+        assert_eq!(api_version("iam"), Some("2010-05-08"));
+        assert_eq!(api_version("sqs"), Some("2012-11-05"));
+        assert_eq!(api_version("nope"), None)
+    }
+
+    #[test]
+    fn test_for_action() {
+        let s = QuerySerializer::for_action("sts", "GetCallerIdentity");
+        assert_eq!(s.params(), vec!(pair("Action", "GetCallerIdentity"), pair("Version", "2011-06-15")))
+    }
+
+    #[test]
+    fn test_ec2_lists() {
+        let input = DescribeInstances {
+            filters: vec!(Filter { name: "tag:Name".to_string(), values: vec!("web".to_string(), "db".to_string()) }),
+            max_results: Some(5),
+        };
+        assert_eq!(serialize(Flavor::Ec2, &input),
+                   vec!(pair("Filter.1.Name", "tag:Name"),
+                        pair("Filter.1.Value.1", "web"),
+                        pair("Filter.1.Value.2", "db"),
+                        pair("MaxResults", "5")))
+    }
+
+    #[test]
+    fn test_query_lists() {
+        let input = DescribeInstances {
+            filters: vec!(Filter { name: "a".to_string(), values: vec!("b".to_string()) }),
+            max_results: None,
+        };
+        assert_eq!(serialize(Flavor::Query, &input),
+                   vec!(pair("Filter.member.1.Name", "a"),
+                        pair("Filter.member.1.Value.member.1", "b")))
+    }
+
+    #[test]
+    fn test_query_empty_list() {
+        let v: Vec<String> = Vec::new();
+        let mut s = QuerySerializer::new(Flavor::Query);
+        s.field("", "AttributeNames", &v);
+        assert_eq!(s.params(), vec!(pair("AttributeNames", "")))
+    }
+
+    #[test]
+    fn test_flattened_list() {
+        let mut s = QuerySerializer::new(Flavor::Query);
+        s.flat_list("AttributeName", &["All", "Policy"]);
+        assert_eq!(s.params(), vec!(pair("AttributeName.1", "All"), pair("AttributeName.2", "Policy")))
+    }
+
+    #[test]
+    fn test_map() {
+        let mut attrs = BTreeMap::new();
+        attrs.insert("DelaySeconds".to_string(), 10);
+        attrs.insert("VisibilityTimeout".to_string(), 30);
+        let mut s = QuerySerializer::new(Flavor::Query);
+        s.field("", "Attributes", &attrs);
+        assert_eq!(s.params(), vec!(pair("Attributes.entry.1.key", "DelaySeconds"),
+                                    pair("Attributes.entry.1.value", "10"),
+                                    pair("Attributes.entry.2.key", "VisibilityTimeout"),
+                                    pair("Attributes.entry.2.value", "30")))
+    }
+
+    #[test]
+    fn test_parse_query_response() {
+        let body = r#"<GetCallerIdentityResponse xmlns="https://sts.amazonaws.com/doc/2011-06-15/">
+  <GetCallerIdentityResult>
+    <Arn>arn:aws:iam::123456789012:user/thom</Arn>
+    <Account>123456789012</Account>
+  </GetCallerIdentityResult>
+  <ResponseMetadata><RequestId>01234567-89ab-cdef</RequestId></ResponseMetadata>
+</GetCallerIdentityResponse>"#;
+        let res = parse_response("GetCallerIdentity", body).unwrap();
+        assert_eq!(res.request_id, Some("01234567-89ab-cdef".to_string()));
+        assert_eq!(res.result.find_text("Account"), Some("123456789012".to_string()))
+    }
+
+    #[test]
+    fn test_parse_ec2_response() {
+        let body = r#"<DescribeInstancesResponse xmlns="http://ec2.amazonaws.com/doc/2016-11-15/">
+  <requestId>8f7724cf-496f-496e-8fe3-example</requestId>
+  <reservationSet><item><instancesSet><item><instanceId>i-1234567890abcdef0</instanceId></item></instancesSet></item></reservationSet>
+</DescribeInstancesResponse>"#;
+        let res = parse_response("DescribeInstances", body).unwrap();
+        assert_eq!(res.request_id, Some("8f7724cf-496f-496e-8fe3-example".to_string()));
+        assert_eq!(res.result.find_text("reservationSet/item/instancesSet/item/instanceId"), Some("i-1234567890abcdef0".to_string()))
+    }
+}
@@ -0,0 +1,271 @@
+use serialize::json::Json;
+use std::collections::BTreeMap;
+
+use crate::error::ServiceError;
+use crate::protocol::json;
+use crate::protocol::xml_error;
+use crate::request::ApiRequest;
+use crate::response::HttpResponse;
+use crate::xml::Element;
+
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum RestProtocol {
+    Xml,
+    Json,
+}
+
+pub fn rest_protocol(service: &str) -> Option<RestProtocol> {
+    match service {
+        "s3" | "route53" | "cloudfront" => Some(RestProtocol::Xml),
+        "lambda" | "apigateway" | "execute-api" | "glacier" | "efs" | "elasticfilesystem" => Some(RestProtocol::Json),
+        _ => None,
+    }
+}
+
+#[derive(Clone,Debug)]
+pub enum Body {
+    Empty,
+    Xml(Element),
+    Json(Json),
+    Raw(Vec<u8>),
+}
+
+// A REST operation: a uri template such as `/{Bucket}/{Key+}?uploads` with
+// members bound to labels, query strings, headers and the body.
+#[derive(Clone,Debug)]
+pub struct RestRequest {
+    operation: String,
+    method: String,
+    template: String,
+    labels: BTreeMap<String, String>,
+    query: Vec<(String, String)>,
+    headers: Vec<(String, String)>,
+    body: Body,
+}
+
+impl RestRequest {
+    pub fn new(method: &str, template: &str) -> RestRequest {
+        RestRequest {
+            operation: String::new(),
+            method: method.to_string(),
+            template: template.to_string(),
+            labels: BTreeMap::new(),
+            query: Vec::new(),
+            headers: Vec::new(),
+            body: Body::Empty,
+        }
+    }
+
+    pub fn label(mut self, name: &str, value: &str) -> RestRequest {
+        self.labels.insert(name.to_string(), value.to_string());
+        self
+    }
+
+    pub fn query(mut self, key: &str, value: &str) -> RestRequest {
+        self.query.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn header(mut self, key: &str, value: &str) -> RestRequest {
+        self.headers.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    // Members bound to a header prefix, like S3's x-amz-meta-*.
+    pub fn prefixed_headers(mut self, prefix: &str, values: &BTreeMap<String, String>) -> RestRequest {
+        for (k, v) in values.iter() {
+            self.headers.push((format!("{}{}", prefix, k), v.clone()));
+        }
+        self
+    }
+
+    pub fn xml(mut self, body: Element) -> RestRequest {
+        self.body = Body::Xml(body);
+        self
+    }
+
+    pub fn json(mut self, body: Json) -> RestRequest {
+        self.body = Body::Json(body);
+        self
+    }
+
+    pub fn raw(mut self, body: &[u8]) -> RestRequest {
+        self.body = Body::Raw(body.to_vec());
+        self
+    }
+
+    // Names the operation, e.g. "GetObject", for interceptors and logs.
+    // Defaults to the method and URI template.
+    pub fn operation(mut self, name: &str) -> RestRequest {
+        self.operation = name.to_string();
+        self
+    }
+
+    pub fn to_api_request(&self) -> Result<ApiRequest, String> {
+        let (path, template_query) = expand_uri(&self.template, &self.labels)?;
+        let operation = if !self.operation.is_empty() {
+            self.operation.clone()
+        } else {
+            format!("{} {}", self.method, self.template)
+        };
+
+        let mut req = ApiRequest::new(&self.method).operation(&operation).path(&path);
+        for (k, v) in template_query.iter().chain(self.query.iter()) {
+            req = req.query(k, v);
+        }
+        for (k, v) in self.headers.iter() {
+            req = req.header(k, v);
+        }
+        let has_content_type = self.headers.iter().any(|(k, _)| k.to_lowercase() == "content-type");
+        req = match self.body {
+            Body::Empty => req,
+            Body::Raw(ref bytes) => req.body(bytes),
+            Body::Xml(ref e) => {
+                let req = if has_content_type { req } else { req.header("Content-Type", "application/xml") };
+                req.body(e.to_xml().as_bytes())
+            },
+            Body::Json(ref j) => {
+                let req = if has_content_type { req } else { req.header("Content-Type", "application/json") };
+                req.body(j.to_string().as_bytes())
+            },
+        };
+        Ok(req)
+    }
+}
+
+// Returns the unencoded path and any query parameters fixed by the template.
+// `{Name}` must not contain a slash; `{Name+}` is greedy and may.
+pub fn expand_uri(template: &str, labels: &BTreeMap<String, String>) -> Result<(String, Vec<(String, String)>), String> {
+    let (path_template, query_template) = match template.find('?') {
+        Some(i) => (&template[..i], Some(&template[i + 1..])),
+        None => (template, None),
+    };
+
+    let mut path = String::new();
+    let mut rest = path_template;
+    while let Some(start) = rest.find('{') {
+        path.push_str(&rest[..start]);
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => return Err(format!("unterminated label in {}", template)),
+        };
+        let label = &rest[start + 1..end];
+        let (name, greedy) = match label.strip_suffix('+') {
+            Some(name) => (name, true),
+            None => (label, false),
+        };
+        let value = match labels.get(name) {
+            Some(v) => v,
+            None => return Err(format!("missing uri label {}", name)),
+        };
+        if value.is_empty() {
+            return Err(format!("uri label {} is empty", name));
+        }
+        if !greedy && value.contains('/') {
+            return Err(format!("uri label {} may not contain '/'", name));
+        }
+        path.push_str(value);
+        rest = &rest[end + 1..];
+    }
+    path.push_str(rest);
+
+    let mut query = Vec::new();
+    if let Some(q) = query_template {
+        for pair in q.split('&').filter(|p| !p.is_empty()) {
+            match pair.find('=') {
+                Some(i) => query.push((pair[..i].to_string(), pair[i + 1..].to_string())),
+                None => query.push((pair.to_string(), String::new())),
+            }
+        }
+    }
+    Ok((path, query))
+}
+
+pub fn parse_error(res: &HttpResponse, protocol: RestProtocol) -> ServiceError {
+    let body = res.body_string();
+    match protocol {
+        RestProtocol::Xml => xml_error(res.status, res.request_id(), &body),
+        RestProtocol::Json => {
+            let error_type = res.header("x-amzn-ErrorType").map(|v| v.to_string());
+            json::parse_error(res.status, res.request_id(), error_type, &body)
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{expand_uri, parse_error, RestRequest, RestProtocol};
+    use crate::response::HttpResponse;
+    use std::collections::BTreeMap;
+    use crate::xml::Element;
+
+    fn labels(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs.iter().map(|&(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn test_expand_greedy() {
+        let (path, query) = expand_uri("/{Bucket}/{Key+}", &labels(&[("Bucket", "b"), ("Key", "photos/2015 summer/a.jpg")])).unwrap();
+        assert_eq!(path, "/b/photos/2015 summer/a.jpg");
+        assert_eq!(query.len(), 0)
+    }
+
+    #[test]
+    fn test_expand_template_query() {
+        let (path, query) = expand_uri("/{Bucket}/{Key+}?uploads&x-id=CreateMultipartUpload", &labels(&[("Bucket", "b"), ("Key", "k")])).unwrap();
+        assert_eq!(path, "/b/k");
+        assert_eq!(query, vec!(("uploads".to_string(), "".to_string()),
+                               ("x-id".to_string(), "CreateMultipartUpload".to_string())))
+    }
+
+    #[test]
+    fn test_expand_errors() {
+        assert!(expand_uri("/{Bucket}", &labels(&[])).is_err());
+        assert!(expand_uri("/{Bucket}", &labels(&[("Bucket", "a/b")])).is_err());
+        assert!(expand_uri("/{Bucket", &labels(&[("Bucket", "a")])).is_err())
+    }
+
+    #[test]
+    fn test_xml_body_sets_content_type() {
+        let req = RestRequest::new("POST", "/2013-04-01/hostedzone/{Id}/rrset")
+            .label("Id", "Z123")
+            .xml(Element::new("ChangeResourceRecordSetsRequest"))
+            .to_api_request()
+            .unwrap();
+        assert_eq!(req.request_path(), "/2013-04-01/hostedzone/Z123/rrset");
+        let content_type = req.headers().iter().find(|h| h.0.eq_ignore_ascii_case("content-type")).map(|h| &h.1[..]);
+        assert_eq!(content_type, Some("application/xml"))
+    }
+
+    #[test]
+    fn test_operation_name() {
+        let req = RestRequest::new("GET", "/{Bucket}").label("Bucket", "b");
+        assert_eq!(req.to_api_request().unwrap().operation_name(), "GET /{Bucket}");
+        assert_eq!(req.operation("ListObjects").to_api_request().unwrap().operation_name(), "ListObjects")
+    }
+
+    #[test]
+    fn test_rest_xml_error() {
+        let res = HttpResponse {
+            status: 404,
+            headers: Vec::new(),
+            body: b"<Error><Code>NoSuchKey</Code><Message>The specified key does not exist.</Message><RequestId>4442587FB7D0A2F9</RequestId></Error>".to_vec(),
+        };
+        let err = parse_error(&res, RestProtocol::Xml);
+        assert_eq!(err.code, "NoSuchKey");
+        assert_eq!(err.request_id, Some("4442587FB7D0A2F9".to_string()));
+        assert_eq!(err.status, 404)
+    }
+
+    #[test]
+    fn test_rest_json_error() {
+        let res = HttpResponse {
+            status: 404,
+            headers: vec!(("x-amzn-ErrorType".to_string(), "ResourceNotFoundException".to_string())),
+            body: br#"{"Message":"Function not found"}"#.to_vec(),
+        };
+        let err = parse_error(&res, RestProtocol::Json);
+        assert_eq!(err.code, "ResourceNotFoundException");
+        assert_eq!(err.message, "Function not found")
+    }
+}
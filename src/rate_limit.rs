@@ -0,0 +1,237 @@
+use std::cmp::Ordering;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use time::precise_time_s;
+
+const SMOOTH: f64 = 0.8;
+const BETA: f64 = 0.7;
+const SCALE_CONSTANT: f64 = 0.4;
+const MIN_FILL_RATE: f64 = 0.5;
+const MIN_CAPACITY: f64 = 1.0;
+
+// The client side of adaptive retries: a token bucket whose fill rate follows
+// CUBIC congestion control, backing off multiplicatively on each throttle and
+// growing back along a cubic curve. Times are in seconds.
+#[derive(Clone,Debug)]
+pub struct RateLimiterState {
+    enabled: bool,
+    fill_rate: f64,
+    max_capacity: f64,
+    current_capacity: f64,
+    last_timestamp: Option<f64>,
+    measured_tx_rate: f64,
+    last_tx_rate_bucket: f64,
+    request_count: u64,
+    last_max_rate: f64,
+    last_throttle_time: f64,
+    time_window: f64,
+}
+
+impl RateLimiterState {
+    pub fn new(now: f64) -> RateLimiterState {
+        RateLimiterState {
+            enabled: false,
+            fill_rate: 0.0,
+            max_capacity: 0.0,
+            current_capacity: 0.0,
+            last_timestamp: None,
+            measured_tx_rate: 0.0,
+            last_tx_rate_bucket: (now * 2.0).floor() / 2.0,
+            request_count: 0,
+            last_max_rate: 0.0,
+            last_throttle_time: now,
+            time_window: 0.0,
+        }
+    }
+
+    pub fn fill_rate(&self) -> f64 {
+        self.fill_rate
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    // Takes a token, returning how long the caller must wait before sending.
+    // The bucket isn't used until the first throttle.
+    pub fn acquire(&mut self, now: f64) -> f64 {
+        if !self.enabled {
+            return 0.0;
+        }
+        self.refill(now);
+        let delay = if self.current_capacity < 1.0 {
+            (1.0 - self.current_capacity) / self.fill_rate
+        } else {
+            0.0
+        };
+        self.current_capacity -= 1.0;
+        delay
+    }
+
+    pub fn update(&mut self, throttled: bool, now: f64) {
+        self.update_measured_rate(now);
+
+        let calculated = if throttled {
+            let rate = if self.enabled {
+                partial_min(self.measured_tx_rate, self.fill_rate)
+            } else {
+                self.measured_tx_rate
+            };
+            self.last_max_rate = rate;
+            self.calculate_time_window();
+            self.last_throttle_time = now;
+            self.enabled = true;
+            rate * BETA
+        } else {
+            self.calculate_time_window();
+            let dt = now - self.last_throttle_time;
+            SCALE_CONSTANT * (dt - self.time_window).powi(3) + self.last_max_rate
+        };
+
+        let new_rate = partial_min(calculated, 2.0 * self.measured_tx_rate);
+        self.update_bucket_rate(new_rate, now);
+    }
+
+    fn refill(&mut self, now: f64) {
+        if let Some(last) = self.last_timestamp {
+            let fill = (now - last) * self.fill_rate;
+            self.current_capacity = partial_min(self.max_capacity, self.current_capacity + fill);
+        }
+        self.last_timestamp = Some(now);
+    }
+
+    fn calculate_time_window(&mut self) {
+        self.time_window = (self.last_max_rate * (1.0 - BETA) / SCALE_CONSTANT).cbrt();
+    }
+
+    fn update_measured_rate(&mut self, now: f64) {
+        let bucket = (now * 2.0).floor() / 2.0;
+        self.request_count += 1;
+        if bucket > self.last_tx_rate_bucket {
+            let current = self.request_count as f64 / (bucket - self.last_tx_rate_bucket);
+            self.measured_tx_rate = current * SMOOTH + self.measured_tx_rate * (1.0 - SMOOTH);
+            self.request_count = 0;
+            self.last_tx_rate_bucket = bucket;
+        }
+    }
+
+    fn update_bucket_rate(&mut self, rate: f64, now: f64) {
+        self.refill(now);
+        self.fill_rate = partial_max(rate, MIN_FILL_RATE);
+        self.max_capacity = partial_max(rate, MIN_CAPACITY);
+        self.current_capacity = partial_min(self.current_capacity, self.max_capacity);
+    }
+}
+
+fn partial_min(a: f64, b: f64) -> f64 {
+    match a.partial_cmp(&b) {
+        Some(Ordering::Greater) => b,
+        _ => a,
+    }
+}
+
+fn partial_max(a: f64, b: f64) -> f64 {
+    match a.partial_cmp(&b) {
+        Some(Ordering::Less) => b,
+        _ => a,
+    }
+}
+
+// Shared by every clone, so all copies of a client throttle together.
+#[derive(Clone,Debug)]
+pub struct ClientRateLimiter {
+    state: Arc<Mutex<RateLimiterState>>,
+}
+
+impl Default for ClientRateLimiter {
+    fn default() -> ClientRateLimiter {
+        ClientRateLimiter::new()
+    }
+}
+
+impl ClientRateLimiter {
+    pub fn new() -> ClientRateLimiter {
+        ClientRateLimiter {
+            state: Arc::new(Mutex::new(RateLimiterState::new(precise_time_s()))),
+        }
+    }
+
+    // Takes a token and says how long to wait before sending; the waiting is
+    // left to the caller, so no lock is held meanwhile.
+    pub fn delay(&self) -> Duration {
+        let delay = self.state.lock().unwrap().acquire(precise_time_s());
+        Duration::from_millis((delay.max(0.0) * 1000.0).ceil() as u64)
+    }
+
+    pub fn update(&self, throttled: bool) {
+        self.state.lock().unwrap().update(throttled, precise_time_s());
+    }
+
+    pub fn state(&self) -> RateLimiterState {
+        self.state.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RateLimiterState, ClientRateLimiter};
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 0.0001
+    }
+
+    #[test]
+    fn test_disabled_until_throttled() {
+        let mut state = RateLimiterState::new(0.0);
+        for i in 0..10 {
+            assert_eq!(state.acquire(i as f64 * 0.1), 0.0);
+            state.update(false, i as f64 * 0.1);
+        }
+        assert!(!state.enabled())
+    }
+
+    #[test]
+    fn test_throttle_reduces_rate() {
+        let mut state = RateLimiterState::new(0.0);
+        // ten requests a second for a couple of seconds
+        for i in 0..20 {
+            state.update(false, i as f64 * 0.1);
+        }
+        state.update(true, 2.0);
+        assert!(state.enabled());
+        assert!(state.measured_tx_rate > 9.0);
+        assert!(close(state.fill_rate(), state.measured_tx_rate * 0.7))
+    }
+
+    #[test]
+    fn test_rate_recovers_after_throttle() {
+        let mut state = RateLimiterState::new(0.0);
+        for i in 0..20 {
+            state.update(false, i as f64 * 0.1);
+        }
+        state.update(true, 2.0);
+        let throttled = state.fill_rate();
+        for i in 0..40 {
+            state.update(false, 2.0 + i as f64 * 0.1);
+        }
+        assert!(state.fill_rate() > throttled)
+    }
+
+    #[test]
+    fn test_acquire_waits_when_empty() {
+        let mut state = RateLimiterState::new(0.0);
+        state.update(true, 0.0);
+        // fill rate is at the minimum of 0.5/s with an empty bucket
+        assert!(close(state.fill_rate(), 0.5));
+        assert!(close(state.acquire(0.0), 2.0));
+        assert!(close(state.acquire(0.0), 4.0))
+    }
+
+    #[test]
+    fn test_shared_across_clones() {
+        let limiter = ClientRateLimiter::new();
+        let clone = limiter.clone();
+        clone.update(true);
+        assert!(limiter.state().enabled())
+    }
+}
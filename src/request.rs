@@ -1,50 +1,690 @@
-use hyper::Client;
-use hyper::client::Response;
-use hyper::Result as HyperResult;
-use signers::sigv4::SigV4;
-use credentials::Credentials;
+use http::uri::Uri;
+use serialize::json::{Json, ToJson};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use time::{now_utc, Tm};
+use crate::signers::sigv4::{SigV4, PathMode, host_header};
+use crate::credentials::Credentials;
+use crate::endpoints::EndpointResolver;
+use crate::interceptor::{Context, Interceptor};
+use crate::metrics::{Metrics, Outcome};
+use crate::paginator::{JsonPageSpec, Paginator, QueryPageSpec, RestPageSpec};
+use crate::pool::HttpPool;
+use crate::proxy::ProxyConfig;
+use crate::tls::TlsConfig;
+use crate::error::{Error, Result, TimeoutKind};
+use crate::protocol::{json, rest, xml_error};
+use crate::protocol::query::{self, ToQuery, QuerySerializer, QueryResponse};
+use crate::protocol::rest::{RestProtocol, RestRequest, rest_protocol};
+use crate::response::HttpResponse;
+use crate::xml::Element;
+use crate::rate_limit::ClientRateLimiter;
+use crate::retry::{self, RetryPolicy, RetryKind, RetryState};
+use crate::runtime;
+use crate::timeout::{self, Timeouts};
+use crate::transport::{HttpRequest, HttpTransport};
+use crate::waiter::{Observation, Waiter};
+use tracing::{field, Instrument, Span};
+use crate::wire;
 
-#[derive(Debug)]
-pub struct ApiClient {
+#[derive(Clone,Debug)]
+pub struct ApiRequest {
+    operation: String,
+    method: String,
+    path: String,
+    query: Vec<(String, String)>,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl ApiRequest {
+    pub fn new(method: &str) -> ApiRequest {
+        ApiRequest {
+            operation: String::new(),
+            method: method.to_string(),
+            path: "/".to_string(),
+            query: Vec::new(),
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    pub fn operation(mut self, name: &str) -> ApiRequest {
+        self.operation = name.to_string();
+        self
+    }
+
+    // Unencoded; the signer encodes it for the request line.
+    pub fn path(mut self, path: &str) -> ApiRequest {
+        self.path = path.to_string();
+        self
+    }
+
+    pub fn query(mut self, key: &str, value: &str) -> ApiRequest {
+        self.query.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn header(mut self, key: &str, value: &str) -> ApiRequest {
+        self.headers.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn body(mut self, body: &[u8]) -> ApiRequest {
+        self.body = body.to_vec();
+        self
+    }
+
+    // Form-encodes the parameters into the body, as the Query APIs expect for POST.
+    pub fn form(self, params: &[(String, String)]) -> ApiRequest {
+        let body = form_urlencoded::Serializer::new(String::new()).extend_pairs(params).finish();
+        self.header("Content-Type", "application/x-www-form-urlencoded; charset=utf-8")
+            .body(body.as_bytes())
+    }
+
+    pub fn operation_name(&self) -> &str {
+        &self.operation
+    }
+
+    pub fn method(&self) -> &str {
+        &self.method
+    }
+
+    pub fn request_path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn query_params(&self) -> &[(String, String)] {
+        &self.query
+    }
+
+    pub fn headers(&self) -> &[(String, String)] {
+        &self.headers
+    }
+
+    pub fn payload(&self) -> &[u8] {
+        &self.body
+    }
+
+    // For interceptors, which get the request by reference.
+    pub fn query_mut(&mut self) -> &mut Vec<(String, String)> {
+        &mut self.query
+    }
+
+    pub fn headers_mut(&mut self) -> &mut Vec<(String, String)> {
+        &mut self.headers
+    }
+
+    pub fn set_body(&mut self, body: &[u8]) {
+        self.body = body.to_vec();
+    }
+}
+
+#[derive(Clone,Debug)]
+struct Config {
     signer: SigV4,
-    endpoint: String
+    region: String,
+    endpoint: String,
+    host: String,
+    service: String,
+    retry: RetryPolicy,
+    rate_limiter: Option<ClientRateLimiter>,
+    timeouts: Timeouts,
+    interceptors: Vec<Arc<dyn Interceptor>>,
+    wire_logging: bool,
+    metrics: Option<Arc<dyn Metrics>>,
+    // Dates each attempt's signature.
+    clock: fn() -> Tm,
+}
+
+// Cheap to clone and safe to share between threads: clones share the
+// configuration until one of them is changed, and always share the pool.
+#[derive(Clone,Debug)]
+pub struct ApiClient {
+    config: Arc<Config>,
+    pool: HttpPool,
+    transport: Option<Arc<dyn HttpTransport>>,
 }
 
 impl ApiClient {
-    pub fn new(creds: Credentials, region: &str, service: &str) -> ApiClient{
+    // Fails when the endpoint or TLS settings configured in the environment
+    // or config file can't be used, or the region is malformed.
+    pub fn new(creds: Credentials, region: &str, service: &str) -> Result<ApiClient> {
+        ApiClient::with_resolver(creds, region, service, &EndpointResolver::new())
+    }
+
+    pub fn with_resolver(creds: Credentials, region: &str, service: &str, resolver: &EndpointResolver) -> Result<ApiClient> {
+        let endpoint = resolver.resolve(service, region)?;
+
         let sig = SigV4::new();
         let sig = sig.credentials(creds);
-        let sig = sig.region(region);
+        let sig = sig.region(&endpoint.signing_region);
         let sig = sig.service(service);
+        let sig = if service == "s3" { sig.path_mode(PathMode::S3) } else { sig };
+
+        let client = ApiClient {
+            config: Arc::new(Config {
+                signer: sig,
+                region: region.to_string(),
+                endpoint: String::new(),
+                host: String::new(),
+                service: service.to_string(),
+                retry: RetryPolicy::new(),
+                rate_limiter: None,
+                timeouts: Timeouts::new(),
+                interceptors: Vec::new(),
+                wire_logging: false,
+                metrics: None,
+                clock: now_utc,
+            }),
+            pool: HttpPool::shared()?,
+            transport: None,
+        };
+        client.endpoint(&endpoint.url)
+    }
+
+    // Sends requests to `url` instead, while still signing for the service and region.
+    pub fn endpoint(mut self, url: &str) -> Result<ApiClient> {
+        let uri = url.parse::<Uri>().map_err(|e| Error::Config(format!("{}: {}", url, e)))?;
+        let host = match host_header(&uri) {
+            Some(host) => host,
+            None => return Err(Error::Config(format!("{}: endpoint has no host", url))),
+        };
+        {
+            let config = Arc::make_mut(&mut self.config);
+            config.host = host;
+            config.endpoint = url.to_string();
+        }
+        Ok(self)
+    }
+
+    pub fn credentials(mut self, creds: Credentials) -> ApiClient {
+        {
+            let config = Arc::make_mut(&mut self.config);
+            config.signer = config.signer.clone().credentials(creds);
+        }
+        self
+    }
+
+    // Shares `pool` instead of the process-wide default, e.g. to give one
+    // service different limits.
+    pub fn pool(mut self, pool: HttpPool) -> ApiClient {
+        self.pool = pool;
+        self
+    }
+
+    // Connect and read timeouts belong to the connection pool, so changing
+    // them moves this client to a pool with those timeouts, shared with the
+    // clients that set the same ones.
+    pub fn timeouts(mut self, timeouts: Timeouts) -> ApiClient {
+        if self.pool.config().timeouts() != (timeouts.connect, timeouts.read) {
+            let config = self.pool.config().clone().connect_timeout(timeouts.connect).read_timeout(timeouts.read);
+            self.pool = self.pool.reconfigure(config);
+        }
+        Arc::make_mut(&mut self.config).timeouts = timeouts;
+        self
+    }
+
+    // Overrides the proxy environment variables for this client, which moves
+    // to a pool using that proxy, as timeouts does.
+    pub fn proxy(mut self, proxy: ProxyConfig) -> ApiClient {
+        self.pool = self.pool.reconfigure(self.pool.config().clone().proxy(proxy));
+        self
+    }
+
+    // Overrides AWS_CA_BUNDLE and the profile's ca_bundle for this client,
+    // which moves to a pool using those settings, as timeouts does.
+    pub fn tls(mut self, tls: TlsConfig) -> Result<ApiClient> {
+        self.pool = self.pool.reconfigure_tls(&tls)?;
+        Ok(self)
+    }
+
+    // Sends through `transport` rather than the connection pool, e.g. a
+    // MockTransport in tests.
+    pub fn transport<T: HttpTransport + 'static>(mut self, transport: T) -> ApiClient {
+        self.transport = Some(Arc::new(transport));
+        self
+    }
+
+    // Adds to the end of the interceptor chain.
+    pub fn interceptor<I: Interceptor + 'static>(mut self, interceptor: I) -> ApiClient {
+        Arc::make_mut(&mut self.config).interceptors.push(Arc::new(interceptor));
+        self
+    }
+
+    // Logs each canonical request, string to sign and request headers at debug
+    // level under the "aws::wire" target, with credentials and signatures redacted.
+    pub fn wire_logging(mut self, enabled: bool) -> ApiClient {
+        Arc::make_mut(&mut self.config).wire_logging = enabled;
+        self
+    }
+
+    // Reports every attempt and finished operation to `metrics`.
+    pub fn metrics<M: Metrics + 'static>(mut self, metrics: M) -> ApiClient {
+        Arc::make_mut(&mut self.config).metrics = Some(Arc::new(metrics));
+        self
+    }
+
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> ApiClient {
+        Arc::make_mut(&mut self.config).retry = policy;
+        self
+    }
+
+    // Slows this client, and every clone of it, down when the service starts
+    // throttling, rather than relying on retries alone.
+    pub fn adaptive(mut self) -> ApiClient {
+        Arc::make_mut(&mut self.config).rate_limiter = Some(ClientRateLimiter::new());
+        self
+    }
+
+    pub fn rate_limiter(mut self, limiter: ClientRateLimiter) -> ApiClient {
+        Arc::make_mut(&mut self.config).rate_limiter = Some(limiter);
+        self
+    }
+
+    pub fn get(&self, action: &str) -> Result<QueryResponse> {
+        let mut req = ApiRequest::new("GET").operation(action);
+        for (k, v) in QuerySerializer::for_action(&self.config.service, action).params() {
+            req = req.query(&k, &v);
+        }
+        self.query_response(action, req)
+    }
+
+    // Calls a Query protocol action, POSTing the serialized input as a form.
+    pub fn query<T: ToQuery>(&self, action: &str, input: &T) -> Result<QueryResponse> {
+        self.query_response(action, self.query_request(action, input))
+    }
 
-        let host = format!("{}.{}.amazonaws.com", service, region);
-        let sig = sig.header(("Host", &host));
+    pub(crate) fn query_request<T: ToQuery>(&self, action: &str, input: &T) -> ApiRequest {
+        ApiRequest::new("POST").operation(action).form(&self.query_params(action, input))
+    }
+
+    pub(crate) fn query_params<T: ToQuery>(&self, action: &str, input: &T) -> Vec<(String, String)> {
+        let mut s = QuerySerializer::for_action(&self.config.service, action);
+        input.to_query("", &mut s);
+        s.params()
+    }
+
+    // Pages through a Query/EC2 list operation, e.g.
+    // client.paginate("ListUsers", &(), QueryPageSpec::new("Marker", "Marker", "Users/member")).items()
+    pub fn paginate<'a, T: ToQuery>(&'a self, action: &'a str, input: &T, spec: QueryPageSpec) -> Paginator<'a, Element> {
+        let params = self.query_params(action, input);
+        Paginator::new(move |token: Option<&str>| {
+            let req = ApiRequest::new("POST").operation(action).form(&spec.params(&params, token));
+            Ok(spec.page(&self.query_response(action, req)?))
+        })
+    }
+
+    // Pages through a REST-XML list operation, e.g.
+    // client.paginate_rest(&list_objects, RestPageSpec::new("continuation-token", "NextContinuationToken", "Contents"))
+    pub fn paginate_rest<'a>(&'a self, req: &RestRequest, spec: RestPageSpec) -> Paginator<'a, Element> {
+        let req = req.clone();
+        Paginator::new(move |token: Option<&str>| spec.page(&self.rest(&spec.request(&req, token))?))
+    }
+
+    // Pages through a JSON list operation, e.g.
+    // client.paginate_json("Scan", &input, JsonPageSpec::new("ExclusiveStartKey", "LastEvaluatedKey", "Items"))
+    pub fn paginate_json<'a>(&'a self, operation: &'a str, input: &Json, spec: JsonPageSpec) -> Paginator<'a, Json> {
+        let input = input.clone();
+        Paginator::new(move |token: Option<&str>| Ok(spec.page(&self.json(operation, &spec.input(&input, token)?)?)))
+    }
+
+    fn query_response(&self, action: &str, req: ApiRequest) -> Result<QueryResponse> {
+        query_result(action, self.send(req)?)
+    }
+
+    // Calls a JSON protocol operation, e.g. DynamoDB's ListTables.
+    pub fn json<T: ToJson>(&self, operation: &str, input: &T) -> Result<Json> {
+        let req = self.json_request(operation, input)?;
+        json_result(self.send(req)?)
+    }
+
+    pub(crate) fn json_request<T: ToJson>(&self, operation: &str, input: &T) -> Result<ApiRequest> {
+        let (prefix, version) = match json::service_metadata(&self.config.service) {
+            Some(meta) => meta,
+            None => return Err(Error::Unsupported(format!("{} does not use the JSON protocol", self.config.service))),
+        };
+        Ok(ApiRequest::new("POST")
+            .operation(operation)
+            .header("X-Amz-Target", &json::target(prefix, operation))
+            .header("Content-Type", &json::content_type(version))
+            .body(input.to_json().to_string().as_bytes()))
+    }
+
+    // Calls a REST-XML or REST-JSON operation; non-2xx responses become service errors.
+    pub fn rest(&self, req: &RestRequest) -> Result<HttpResponse> {
+        let (api_req, protocol) = self.rest_request(req)?;
+        rest_result(self.send(api_req)?, protocol)
+    }
+
+    pub(crate) fn rest_request(&self, req: &RestRequest) -> Result<(ApiRequest, RestProtocol)> {
+        let protocol = match rest_protocol(&self.config.service) {
+            Some(p) => p,
+            None => return Err(Error::Unsupported(format!("{} is not a REST service", self.config.service))),
+        };
+        let api_req = req.to_api_request().map_err(Error::Unsupported)?;
+        Ok((api_req, protocol))
+    }
+
+    // Polls a Query/EC2 operation until the waiter's acceptors say it's done,
+    // e.g. client.wait(&waiter::instance_running(), "DescribeInstances", &params).
+    pub fn wait<T: ToQuery>(&self, waiter: &Waiter, action: &str, input: &T) -> Result<()> {
+        waiter.wait(|| self.query(action, input).map(|res| Observation { status: 200, body: Some(res.result) }))
+    }
+
+    pub fn wait_rest(&self, waiter: &Waiter, req: &RestRequest) -> Result<()> {
+        waiter.wait(|| self.rest(req).map(|res| Observation { status: res.status, body: res.xml().ok() }))
+    }
+
+    // Sends a signed request and reads the whole response, whatever its status.
+    // Transient failures and throttling are retried according to the retry policy.
+    pub fn send(&self, req: ApiRequest) -> Result<HttpResponse> {
+        let client = self.clone();
+        runtime::block_on(async move { client.execute(req).await })
+    }
+
+    // The operation both send and AsyncApiClient run.
+    pub(crate) async fn execute(&self, req: ApiRequest) -> Result<HttpResponse> {
+        let span = self.operation_span(&req);
+        let start = Instant::now();
+        let run = async {
+            let (req, intercepted) = self.serialized(req);
+            let (result, attempts) = match intercepted {
+                Some(res) => (Ok(res), 0),
+                None => self.send_attempts(&req).await,
+            };
+            self.completed(&span, &req, attempts, &result, start);
+            result
+        };
+        run.instrument(span.clone()).await
+    }
+
+    async fn send_attempts(&self, req: &ApiRequest) -> (Result<HttpResponse>, u32) {
+        let deadline = self.deadline();
+        let mut state = self.config.retry.start();
+        loop {
+            let delay = self.rate_limiter_delay();
+            if delay > Duration::from_millis(0) {
+                tokio::time::sleep(delay).await;
+            }
+            let attempt = state.attempt();
+            let result = self.attempt(req, attempt, deadline).await;
+            match self.retry_delay(&mut state, &result) {
+                Some(delay) if timeout::exceeds(deadline, delay) => return (Err(Error::Timeout(TimeoutKind::Operation)), attempt),
+                Some(delay) => tokio::time::sleep(delay).await,
+                None => return (result, attempt),
+            }
+        }
+    }
 
-        ApiClient {
-            signer: sig,
-            endpoint: format!("https://{}/", host)
+    pub(crate) fn operation_span(&self, req: &ApiRequest) -> Span {
+        info_span!("operation",
+                   service = %self.config.service,
+                   operation = %req.operation,
+                   region = %self.config.region,
+                   attempts = field::Empty,
+                   status = field::Empty,
+                   request_id = field::Empty,
+                   error = field::Empty,
+                   latency_ms = field::Empty)
+    }
+
+    // Records how the operation went on its span and with the metrics.
+    pub(crate) fn completed(&self, span: &Span, req: &ApiRequest, attempts: u32, result: &Result<HttpResponse>, start: Instant) {
+        let latency = start.elapsed();
+        span.record("attempts", attempts);
+        record_outcome(span, result, latency);
+        span.in_scope(|| debug!("operation finished"));
+        if let Some(ref metrics) = self.config.metrics {
+            metrics.completed(&self.context(req, attempts), &Outcome::new(result, latency));
+        }
+    }
+
+    // Runs the serialization hooks, returning the request to send or the
+    // response an interceptor answered with instead.
+    pub(crate) fn serialized(&self, mut req: ApiRequest) -> (ApiRequest, Option<HttpResponse>) {
+        let ctx = self.context(&req, 0);
+        for interceptor in self.config.interceptors.iter() {
+            if let Some(res) = interceptor.serialization(&ctx, &mut req) {
+                let res = self.after_response(&ctx, res);
+                return (req, Some(res));
+            }
         }
+        (req, None)
     }
 
-    pub fn get(self, action: &str) -> HyperResult<Response>{
-        let sig = self.signer.clone();
-        let sig = sig.method("GET");
-        let sig = sig.path("/");
-        let query = format!("Action={}&Version=2015-04-15", action);
-        let sig = sig.query(&query);
-        let url = format!("{}?{}", self.endpoint, query);
+    fn context(&self, req: &ApiRequest, attempt: u32) -> Context {
+        Context {
+            service: self.config.service.clone(),
+            operation: req.operation.clone(),
+            region: self.config.region.clone(),
+            attempt,
+        }
+    }
 
-        let headers = sig.as_headers();
-        let mut client = Client::new();
-        let res = client.get(&url).headers(headers).send();
+    fn after_response(&self, ctx: &Context, mut res: HttpResponse) -> HttpResponse {
+        for interceptor in self.config.interceptors.iter() {
+            interceptor.after_response(ctx, &mut res);
+        }
         res
     }
+
+    pub(crate) fn deadline(&self) -> Option<Instant> {
+        self.config.timeouts.deadline()
+    }
+
+    pub(crate) fn rate_limiter_delay(&self) -> Duration {
+        match self.config.rate_limiter {
+            Some(ref limiter) => limiter.delay(),
+            None => Duration::from_millis(0),
+        }
+    }
+
+    // Feeds an attempt's outcome to the rate limiter and retry policy, returning
+    // how long to wait before trying again, or None if the result should stand.
+    pub(crate) fn retry_delay(&self, state: &mut RetryState, result: &Result<HttpResponse>) -> Option<Duration> {
+        let kind = match *result {
+            Ok(ref res) => retry::classify_response(res),
+            Err(ref e) => retry::classify_error(e),
+        };
+        if let Some(ref limiter) = self.config.rate_limiter {
+            limiter.update(kind == RetryKind::Throttling);
+        }
+        if kind == RetryKind::NotRetryable {
+            // Only a successful response earns the retry quota back.
+            if let Ok(ref res) = *result {
+                if res.is_success() {
+                    self.config.retry.succeeded(state);
+                }
+            }
+            return None;
+        }
+        self.config.retry.should_retry(state, kind)
+    }
+
+    // Each attempt is signed afresh so x-amz-date stays current.
+    async fn attempt(&self, req: &ApiRequest, attempt: u32, deadline: Option<Instant>) -> Result<HttpResponse> {
+        let span = debug_span!("attempt",
+                               attempt = attempt,
+                               status = field::Empty,
+                               request_id = field::Empty,
+                               error = field::Empty,
+                               latency_ms = field::Empty);
+        let start = Instant::now();
+        let result = self.attempt_within_limit(req, attempt, deadline).instrument(span.clone()).await;
+        let latency = start.elapsed();
+        record_outcome(&span, &result, latency);
+        span.in_scope(|| debug!("attempt finished"));
+        if let Some(ref metrics) = self.config.metrics {
+            metrics.attempt(&self.context(req, attempt), &Outcome::new(&result, latency));
+        }
+        result
+    }
+
+    // An attempt that runs out of time is dropped, which closes its connection.
+    async fn attempt_within_limit(&self, req: &ApiRequest, attempt: u32, deadline: Option<Instant>) -> Result<HttpResponse> {
+        let (limit, kind) = match self.config.timeouts.attempt_limit(deadline) {
+            Some(limit) => limit,
+            None => return self.transmit(req, attempt).await,
+        };
+        if limit == Duration::from_millis(0) {
+            return Err(Error::Timeout(kind));
+        }
+        match tokio::time::timeout(limit, self.transmit(req, attempt)).await {
+            Ok(result) => result,
+            Err(_) => Err(Error::Timeout(kind)),
+        }
+    }
+
+    async fn transmit(&self, req: &ApiRequest, attempt: u32) -> Result<HttpResponse> {
+        let ctx = self.context(req, attempt);
+        let interceptors = &self.config.interceptors;
+
+        let mut req = req.clone();
+        for interceptor in interceptors.iter() {
+            if let Some(res) = interceptor.before_signing(&ctx, &mut req) {
+                return Ok(self.after_response(&ctx, res));
+            }
+        }
+
+        let (url, sig) = self.sign(&req);
+        if self.config.wire_logging {
+            wire::log_signing(&sig);
+        }
+        let mut request = HttpRequest {
+            method: req.method.clone(),
+            url,
+            headers: sig.as_headers()?,
+            body: req.body,
+        };
+        for interceptor in interceptors.iter() {
+            if let Some(res) = interceptor.after_signing(&ctx, &mut request) {
+                return Ok(self.after_response(&ctx, res));
+            }
+        }
+        for interceptor in interceptors.iter() {
+            if let Some(res) = interceptor.before_transmit(&ctx, &mut request) {
+                return Ok(self.after_response(&ctx, res));
+            }
+        }
+
+        if self.config.wire_logging {
+            wire::log_request(&request);
+        }
+        let res = match self.transport {
+            Some(ref transport) => transport.send(request),
+            None => self.pool.send(request),
+        }.await?;
+        Ok(self.after_response(&ctx, res))
+    }
+
+    // Returns the url to send to and a signer primed with everything in the request.
+    fn sign(&self, req: &ApiRequest) -> (String, SigV4) {
+        let has_header = |name: &str| req.headers.iter().any(|h| h.0.eq_ignore_ascii_case(name));
+        let mut sig = self.config.signer.clone()
+            .timestamp((self.config.clock)())
+            .method(&req.method)
+            .path(&req.path)
+            .payload_bytes(&req.body);
+        if !has_header("host") {
+            sig = sig.header(("Host", &self.config.host));
+        }
+        for (k, v) in req.query.iter() {
+            sig = sig.query_param(k, v);
+        }
+        for (k, v) in req.headers.iter() {
+            // A hash the request brings, e.g. UNSIGNED-PAYLOAD, is signed as is.
+            if k.eq_ignore_ascii_case("x-amz-content-sha256") {
+                sig = sig.payload_hash(v);
+            } else {
+                sig = sig.header((k, v));
+            }
+        }
+        // S3 won't accept a request without the payload hash as a header.
+        if self.config.service == "s3" && !has_header("x-amz-content-sha256") {
+            let hash = sig.hashed_payload();
+            sig = sig.header(("x-amz-content-sha256", &hash));
+        }
+
+        let mut url = format!("{}{}", self.config.endpoint.trim_end_matches('/'), sig.encoded_path());
+        if !req.query.is_empty() {
+            url.push('?');
+            url.push_str(&sig.encoded_query());
+        }
+        (url, sig)
+    }
+}
+
+fn record_outcome(span: &Span, result: &Result<HttpResponse>, latency: Duration) {
+    match *result {
+        Ok(ref res) => {
+            span.record("status", res.status);
+            if let Some(id) = res.request_id() {
+                span.record("request_id", field::display(id));
+            }
+        },
+        Err(ref e) => {
+            span.record("error", field::display(e));
+        },
+    }
+    span.record("latency_ms", retry::duration_millis(latency));
+}
+
+pub(crate) fn query_result(action: &str, res: HttpResponse) -> Result<QueryResponse> {
+    if !res.is_success() {
+        return Err(Error::Service(xml_error(res.status, res.request_id(), &res.body_string())));
+    }
+    query::parse_response(action, &res.body_string()).map_err(|e| Error::Parse(e.to_string()))
+}
+
+pub(crate) fn json_result(res: HttpResponse) -> Result<Json> {
+    if !res.is_success() {
+        let error_type = res.header("x-amzn-ErrorType").map(|v| v.to_string());
+        return Err(Error::Service(json::parse_error(res.status, res.request_id(), error_type, &res.body_string())));
+    }
+    res.json().map_err(Error::Parse)
+}
+
+pub(crate) fn rest_result(res: HttpResponse, protocol: RestProtocol) -> Result<HttpResponse> {
+    if !res.is_success() {
+        return Err(Error::Service(rest::parse_error(&res, protocol)));
+    }
+    Ok(res)
 }
 
 #[cfg(test)]
 mod tests {
-    use super::ApiClient;
-    use credentials::Credentials;
+    use super::{ApiClient, ApiRequest};
+    use crate::credentials::Credentials;
+    use crate::endpoints::EndpointResolver;
+    use crate::error::{Error, TimeoutKind};
+    use crate::metrics::InMemoryMetrics;
+    use crate::paginator::{QueryPageSpec, RestPageSpec};
+    use crate::protocol::rest::RestRequest;
+    use crate::pool::{HttpPool, PoolConfig};
+    use crate::proxy::{Proxy, ProxyConfig};
+    use crate::tls::TlsConfig;
+    use crate::transport::mock::MockTransport;
+    use crate::transport::vcr::VcrTransport;
+    use crate::retry::RetryPolicy;
+    use crate::timeout::Timeouts;
+    use serialize::json::Json;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicI64, Ordering};
+    use std::time::{Duration, Instant};
+    use crate::test_server::TestServer;
+    use time::{at_utc, Timespec, Tm};
+    use tracing::{self, Event, Id, Metadata, Subscriber};
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Record};
+    use std::fmt;
+    use std::sync::Mutex;
+    use crate::waiter;
+
+    fn client(service: &str) -> ApiClient {
+        let cred = Credentials::new().path("fixtures/credentials.ini").load();
+        ApiClient::new(cred, "eu-west-1", service).unwrap()
+    }
 
     #[test]
     fn test_new_apiclient() {
@@ -52,7 +692,462 @@ mod tests {
         let region = "eu-west-1";
         let service = "ec2";
 
-        let client = ApiClient::new(cred, region, service);
-        assert_eq!(client.endpoint, "https://ec2.eu-west-1.amazonaws.com/")
+        let client = ApiClient::new(cred, region, service).unwrap();
+        assert_eq!(client.config.endpoint, "https://ec2.eu-west-1.amazonaws.com/")
+    }
+
+    #[test]
+    fn test_new_apiclient_bad_region() {
+        let cred = Credentials::new().path("fixtures/credentials.ini").load();
+        assert!(ApiClient::new(cred, "eu west 1", "ec2").is_err())
+    }
+
+    #[test]
+    fn test_global_service_signing_region() {
+        let cred = Credentials::new().path("fixtures/credentials.ini").load();
+        let resolver = EndpointResolver::new().ignore_env();
+        let client = ApiClient::with_resolver(cred, "eu-west-1", "iam", &resolver).unwrap();
+        assert_eq!(client.config.endpoint, "https://iam.amazonaws.com/");
+        assert_eq!(client.config.host, "iam.amazonaws.com");
+        assert!(client.config.signer.credential_scope().contains("/us-east-1/iam/"))
+    }
+
+    #[test]
+    fn test_custom_resolver_endpoint() {
+        let cred = Credentials::new().path("fixtures/credentials.ini").load();
+        let resolver = EndpointResolver::new().custom("http://localhost:9000");
+        let client = ApiClient::with_resolver(cred, "eu-west-1", "s3", &resolver).unwrap();
+        let (url, _) = client.sign(&ApiRequest::new("GET").path("/bucket/key"));
+        assert_eq!(url, "http://localhost:9000/bucket/key")
+    }
+
+    #[test]
+    fn test_sign_query_url() {
+        let client = client("ec2");
+        let mut req = ApiRequest::new("GET");
+        for (k, v) in client.query_params("DescribeInstances", &()) {
+            req = req.query(&k, &v);
+        }
+        let (url, _) = client.sign(&req);
+        assert_eq!(url, "https://ec2.eu-west-1.amazonaws.com/?Action=DescribeInstances&Version=2016-11-15")
+    }
+
+    #[test]
+    fn test_sign_rest_path() {
+        let req = ApiRequest::new("PUT").path("/bucket/photos/2015 summer/a.jpg").body(b"data");
+        let (url, sig) = client("s3").sign(&req);
+        assert_eq!(url, "https://s3.eu-west-1.amazonaws.com/bucket/photos/2015%20summer/a.jpg");
+        assert!(sig.as_headers().unwrap().iter().any(|h| h.0 == "x-amz-content-sha256"))
+    }
+
+    #[test]
+    fn test_sign_request_host() {
+        let req = ApiRequest::new("GET").path("/key").header("Host", "bucket.s3.eu-west-1.amazonaws.com");
+        let (_, sig) = client("s3").sign(&req);
+        let hosts: Vec<_> = sig.as_headers().unwrap().into_iter().filter(|h| h.0 == "host").collect();
+        assert_eq!(hosts, vec!(("host".to_string(), "bucket.s3.eu-west-1.amazonaws.com".to_string())))
+    }
+
+    #[test]
+    fn test_sign_request_payload_hash() {
+        let req = ApiRequest::new("PUT").path("/bucket/key").header("X-Amz-Content-Sha256", "UNSIGNED-PAYLOAD").body(b"data");
+        let (_, sig) = client("s3").sign(&req);
+        let hashes: Vec<_> = sig.clone().as_headers().unwrap().into_iter().filter(|h| h.0 == "x-amz-content-sha256").collect();
+        assert_eq!(hashes, vec!(("x-amz-content-sha256".to_string(), "UNSIGNED-PAYLOAD".to_string())));
+        assert_eq!(sig.hashed_payload(), "UNSIGNED-PAYLOAD")
+    }
+
+    #[test]
+    fn test_json_unsupported_service() {
+        let input = Json::from_str("{}").unwrap();
+        match client("ec2").json("DescribeInstances", &input) {
+            Err(Error::Unsupported(_)) => {},
+            other => panic!("expected unsupported, got {:?}", other),
+        }
+    }
+
+    fn fast_retries() -> RetryPolicy {
+        RetryPolicy::new().base_delay(Duration::from_millis(1))
+    }
+
+    #[test]
+    fn test_clones_share_pool() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<ApiClient>();
+
+        let ec2 = client("ec2");
+        let iam = client("iam");
+        assert!(ec2.pool.same_pool(&iam.pool));
+        assert!(Arc::ptr_eq(&ec2.config, &ec2.clone().config));
+
+        let custom = ec2.clone().pool(HttpPool::new(PoolConfig::new().max_idle(1)).unwrap());
+        assert!(!custom.pool.same_pool(&iam.pool))
+    }
+
+    #[test]
+    fn test_no_keep_alive() {
+        let server = TestServer::new(vec!((200, "<ListUsersResponse><ListUsersResult/></ListUsersResponse>")));
+        let client = client("iam").endpoint(&server.url).unwrap()
+            .pool(HttpPool::new(PoolConfig::new().keep_alive(false)).unwrap());
+        assert!(client.query("ListUsers", &()).is_ok());
+        assert!(server.requests.lock().unwrap()[0].to_lowercase().contains("connection: close"))
+    }
+
+    #[test]
+    fn test_attempt_timeout() {
+        let server = TestServer::silent();
+        let client = client("iam").endpoint(&server.url).unwrap()
+            .retry_policy(RetryPolicy::none())
+            .timeouts(Timeouts::new().attempt(Duration::from_millis(50)));
+        match client.query("ListUsers", &()) {
+            Err(Error::Timeout(TimeoutKind::Attempt)) => {},
+            other => panic!("expected an attempt timeout, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_operation_deadline_spans_retries() {
+        let server = TestServer::silent();
+        let client = client("iam").endpoint(&server.url).unwrap()
+            .retry_policy(fast_retries().max_attempts(10))
+            .timeouts(Timeouts::new().attempt(Duration::from_millis(30)).operation(Duration::from_millis(100)));
+        let start = Instant::now();
+        match client.query("ListUsers", &()) {
+            Err(Error::Timeout(TimeoutKind::Operation)) => {},
+            other => panic!("expected an operation timeout, got {:?}", other),
+        }
+        assert!(start.elapsed() < Duration::from_millis(500))
+    }
+
+    #[test]
+    fn test_read_timeout() {
+        let server = TestServer::silent();
+        let client = client("iam").endpoint(&server.url).unwrap()
+            .retry_policy(RetryPolicy::none())
+            .timeouts(Timeouts::new().read(Duration::from_millis(50)));
+        match client.query("ListUsers", &()) {
+            Err(Error::Timeout(TimeoutKind::Read)) => {},
+            other => panic!("expected a read timeout, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_proxy_tunnel_signs_origin() {
+        let proxy = TestServer::proxy(vec!((200, "<ListUsersResponse><ListUsersResult/></ListUsersResponse>")));
+        let port = proxy.url.trim_end_matches('/').rsplit(':').next().unwrap().parse::<u16>().unwrap();
+        let client = client("iam").endpoint("http://iam.internal.test:8080/").unwrap()
+            .proxy(ProxyConfig::new().all(Proxy::new("127.0.0.1", port).basic_auth("bob", "secret")));
+        assert!(client.query("ListUsers", &()).is_ok());
+
+        let requests = proxy.requests.lock().unwrap();
+        assert!(requests[0].starts_with("CONNECT iam.internal.test:8080 HTTP/1.1"));
+        assert!(requests[0].contains("Proxy-Authorization: Basic Ym9iOnNlY3JldA=="));
+
+        let tunnelled = requests[1].to_lowercase();
+        assert!(tunnelled.contains("host: iam.internal.test:8080"));
+        assert!(tunnelled.contains("signedheaders=content-type;host;x-amz-date"));
+        assert!(!tunnelled.contains("proxy-authorization"))
+    }
+
+    #[test]
+    fn test_tls_config() {
+        let tls = TlsConfig::new().ca_bundle("fixtures/tls/ca.pem");
+        let s3 = client("s3").tls(tls.clone()).unwrap();
+        let ec2 = client("ec2").tls(tls).unwrap();
+        assert!(s3.pool.same_pool(&ec2.pool));
+        assert!(!s3.pool.same_pool(&client("s3").pool));
+        match client("s3").tls(TlsConfig::new().ca_bundle("fixtures/tls/missing.pem")) {
+            Err(Error::Config(_)) => {},
+            other => panic!("expected a config error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_tls_handshake() {
+        let ok = "<GetCallerIdentityResponse><GetCallerIdentityResult><Account>1</Account></GetCallerIdentityResult></GetCallerIdentityResponse>";
+        let server = TestServer::tls(vec!((200, ok)));
+        let verified = client("sts").endpoint(&server.url).unwrap()
+            .tls(TlsConfig::new().ca_bundle("fixtures/tls/ca.pem")).unwrap()
+            .retry_policy(RetryPolicy::none());
+        assert!(verified.query("GetCallerIdentity", &()).is_err());
+
+        let insecure = client("sts").endpoint(&server.url).unwrap().tls(TlsConfig::new().insecure()).unwrap();
+        let res = insecure.query("GetCallerIdentity", &()).unwrap();
+        assert_eq!(res.result.find_text("Account"), Some("1".to_string()));
+        assert_eq!(server.request_count(), 1)
+    }
+
+    #[test]
+    fn test_mock_transport() {
+        let body = "<GetCallerIdentityResponse><GetCallerIdentityResult><Account>123456789012</Account></GetCallerIdentityResult></GetCallerIdentityResponse>";
+        let mock = MockTransport::new().respond(503, "").respond(200, body);
+        let client = client("sts").retry_policy(fast_retries()).transport(mock.clone());
+
+        let res = client.query("GetCallerIdentity", &()).unwrap();
+        assert_eq!(res.result.find_text("Account"), Some("123456789012".to_string()));
+
+        let requests = mock.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[1].method, "POST");
+        assert_eq!(requests[1].url, "https://sts.eu-west-1.amazonaws.com/");
+        assert_eq!(requests[1].header("host"), Some("sts.eu-west-1.amazonaws.com"));
+        assert!(requests[1].header("authorization").unwrap().starts_with("AWS4-HMAC-SHA256 Credential="));
+        assert_eq!(requests[1].body, b"Action=GetCallerIdentity&Version=2011-06-15".to_vec())
+    }
+
+    #[test]
+    fn test_deeply_nested_response() {
+        let body = "<a>".repeat(100000);
+        let client = client("sts").transport(MockTransport::new().respond(200, &body));
+        match client.query("GetCallerIdentity", &()) {
+            Err(Error::Parse(_)) => {},
+            other => panic!("expected a parse error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_replay_fixture() {
+        let vcr = VcrTransport::replay("fixtures/vcr/sts_get_caller_identity.json").unwrap();
+        let client = client("sts").transport(vcr);
+        let res = client.query("GetCallerIdentity", &()).unwrap();
+        assert_eq!(res.result.find_text("Arn"), Some("arn:aws:iam::123456789012:user/Alice".to_string()));
+        assert_eq!(res.request_id, Some("01234567-89ab-cdef-0123-456789abcdef".to_string()))
+    }
+
+    #[test]
+    fn test_endpoint_override() {
+        let custom = client("sqs").endpoint("http://localhost:4566/").unwrap();
+        assert_eq!(custom.config.host, "localhost:4566");
+        assert!(client("sqs").endpoint("not a url").is_err())
+    }
+
+    #[test]
+    fn test_retries_transient_failures() {
+        let ok = "<GetCallerIdentityResponse><GetCallerIdentityResult><Account>1</Account></GetCallerIdentityResult></GetCallerIdentityResponse>";
+        let server = TestServer::new(vec!((503, ""), (500, ""), (200, ok)));
+        let client = client("sts").endpoint(&server.url).unwrap().retry_policy(fast_retries());
+
+        let res = client.get("GetCallerIdentity").unwrap();
+        assert_eq!(res.result.find_text("Account"), Some("1".to_string()));
+        assert_eq!(server.request_count(), 3)
+    }
+
+    // A clock that moves on a second every time it's read.
+    fn ticking() -> Tm {
+        static SECONDS: AtomicI64 = AtomicI64::new(1440000000);
+        at_utc(Timespec::new(SECONDS.fetch_add(1, Ordering::SeqCst), 0))
+    }
+
+    #[test]
+    fn test_resigns_each_attempt() {
+        let server = TestServer::new(vec!((503, ""), (200, "<R/>")));
+        let mut client = client("sts").endpoint(&server.url).unwrap().retry_policy(fast_retries());
+        Arc::make_mut(&mut client.config).clock = ticking;
+        client.get("GetCallerIdentity").unwrap();
+
+        let requests = server.requests.lock().unwrap();
+        let header = |req: &str, name: &str| {
+            req.lines().find(|l| l.to_lowercase().starts_with(name)).map(|l| l.to_string()).unwrap()
+        };
+        assert_eq!(requests.len(), 2);
+        assert!(header(&requests[0], "x-amz-date:") != header(&requests[1], "x-amz-date:"));
+        assert!(header(&requests[0], "authorization:") != header(&requests[1], "authorization:"))
+    }
+
+    #[test]
+    fn test_refunds_quota_only_on_success() {
+        let server = TestServer::new(vec!((503, ""), (400, "<ErrorResponse><Error><Code>ValidationError</Code></Error></ErrorResponse>")));
+        let retry = fast_retries().quota(10);
+        let client = client("sts").endpoint(&server.url).unwrap().retry_policy(retry.clone());
+        assert!(client.get("GetCallerIdentity").is_err());
+        assert_eq!(retry.available_quota(), 5)
+    }
+
+    #[test]
+    fn test_gives_up_after_max_attempts() {
+        let throttled = "<ErrorResponse><Error><Code>Throttling</Code><Message>Rate exceeded</Message></Error></ErrorResponse>";
+        let server = TestServer::new(vec!((400, throttled), (400, throttled), (400, throttled)));
+        let client = client("sts").endpoint(&server.url).unwrap().retry_policy(fast_retries().max_attempts(2));
+
+        match client.get("GetCallerIdentity") {
+            Err(Error::Service(ref e)) if e.code == "Throttling" => {},
+            other => panic!("expected throttling error, got {:?}", other),
+        }
+        assert_eq!(server.request_count(), 2)
+    }
+
+    #[test]
+    fn test_does_not_retry_client_errors() {
+        let denied = "<ErrorResponse><Error><Code>AccessDenied</Code><Message>no</Message></Error></ErrorResponse>";
+        let server = TestServer::new(vec!((403, denied), (200, "<R/>")));
+        let client = client("sts").endpoint(&server.url).unwrap().retry_policy(fast_retries());
+
+        match client.get("GetCallerIdentity") {
+            Err(Error::Service(ref e)) => assert_eq!(e.code, "AccessDenied"),
+            other => panic!("expected access denied, got {:?}", other),
+        }
+        assert_eq!(server.request_count(), 1)
+    }
+
+    #[test]
+    fn test_adaptive_client_learns_from_throttles() {
+        let throttled = "<Response><Errors><Error><Code>RequestLimitExceeded</Code></Error></Errors></Response>";
+        let server = TestServer::new(vec!((503, throttled), (200, "<R/>")));
+        let client = client("ec2").endpoint(&server.url).unwrap().retry_policy(fast_retries()).adaptive();
+        let clone = client.clone();
+
+        clone.get("DescribeInstances").unwrap();
+        let state = client.config.rate_limiter.as_ref().unwrap().state();
+        assert!(state.enabled())
+    }
+
+    #[test]
+    fn test_paginate() {
+        let first = "<ListUsersResponse><ListUsersResult><Users><member><UserName>a</UserName></member></Users><IsTruncated>true</IsTruncated><Marker>m1</Marker></ListUsersResult></ListUsersResponse>";
+        let second = "<ListUsersResponse><ListUsersResult><Users><member><UserName>b</UserName></member></Users><IsTruncated>false</IsTruncated></ListUsersResult></ListUsersResponse>";
+        let server = TestServer::new(vec!((200, first), (200, second)));
+        let client = client("iam").endpoint(&server.url).unwrap();
+
+        let users: Vec<String> = client.paginate("ListUsers", &(), QueryPageSpec::new("Marker", "Marker", "Users/member"))
+            .items()
+            .map(|u| u.unwrap().find_text("UserName").unwrap())
+            .collect();
+        assert_eq!(users, vec!("a".to_string(), "b".to_string()));
+        assert!(server.requests.lock().unwrap()[1].contains("Marker=m1"))
+    }
+
+    #[test]
+    fn test_paginate_rest() {
+        let first = "<ListBucketResult><Contents><Key>a</Key></Contents><NextContinuationToken>t1</NextContinuationToken></ListBucketResult>";
+        let second = "<ListBucketResult><Contents><Key>b</Key></Contents></ListBucketResult>";
+        let server = TestServer::new(vec!((200, first), (200, second)));
+        let client = client("s3").endpoint(&server.url).unwrap();
+        let req = RestRequest::new("GET", "/{Bucket}").label("Bucket", "b").query("list-type", "2");
+
+        let keys: Vec<String> = client.paginate_rest(&req, RestPageSpec::new("continuation-token", "NextContinuationToken", "Contents"))
+            .items()
+            .map(|o| o.unwrap().find_text("Key").unwrap())
+            .collect();
+        assert_eq!(keys, vec!("a".to_string(), "b".to_string()));
+        assert!(server.requests.lock().unwrap()[1].contains("continuation-token=t1"))
+    }
+
+    #[test]
+    fn test_wait_for_instance() {
+        let pending = "<DescribeInstancesResponse><reservationSet><item><instancesSet><item><instanceState><name>pending</name></instanceState></item></instancesSet></item></reservationSet></DescribeInstancesResponse>";
+        let running = "<DescribeInstancesResponse><reservationSet><item><instancesSet><item><instanceState><name>running</name></instanceState></item></instancesSet></item></reservationSet></DescribeInstancesResponse>";
+        let server = TestServer::new(vec!((200, pending), (200, running)));
+        let client = client("ec2").endpoint(&server.url).unwrap();
+
+        let waiter = waiter::instance_running().min_delay(Duration::from_millis(1));
+        assert!(client.wait(&waiter, "DescribeInstances", &()).is_ok());
+        assert_eq!(server.request_count(), 2)
+    }
+
+    #[test]
+    fn test_form_body() {
+        let params = vec!(("Action".to_string(), "ListUsers".to_string()),
+                          ("Version".to_string(), "2010-05-08".to_string()));
+        let req = ApiRequest::new("POST").form(&params);
+        assert_eq!(req.body, b"Action=ListUsers&Version=2010-05-08".to_vec());
+        assert_eq!(req.headers[0].0, "Content-Type")
+    }
+
+    #[test]
+    fn test_metrics() {
+        let mock = MockTransport::new()
+            .respond(400, r#"{"__type":"ThrottlingException"}"#)
+            .respond(503, "")
+            .respond(200, "<GetCallerIdentityResponse/>")
+            .respond(403, "<ErrorResponse><Error><Code>AccessDenied</Code></Error></ErrorResponse>");
+        let metrics = InMemoryMetrics::new();
+        let client = client("sts").retry_policy(fast_retries()).transport(mock).metrics(metrics.clone());
+
+        client.send(ApiRequest::new("POST").operation("GetCallerIdentity")).unwrap();
+        client.send(ApiRequest::new("POST").operation("GetCallerIdentity")).unwrap();
+
+        let stats = metrics.stats("sts", "GetCallerIdentity");
+        assert_eq!(stats.requests, 2);
+        assert_eq!(stats.failures, 1);
+        assert_eq!(stats.attempts, 4);
+        assert_eq!(stats.retries, 2);
+        assert_eq!(stats.throttles, 1);
+        assert_eq!(stats.error_codes.get("ThrottlingException"), Some(&1));
+        assert_eq!(stats.error_codes.get("Http503"), Some(&1));
+        assert_eq!(stats.error_codes.get("AccessDenied"), Some(&1));
+        assert_eq!(stats.latencies.len(), 2);
+        assert_eq!(metrics.stats("sts", "AssumeRole"), Default::default())
+    }
+
+    // Keeps each span's name and fields as text, indexed by span id.
+    type Recorded = Vec<(String, Vec<(String, String)>)>;
+
+    #[derive(Clone,Default)]
+    struct Spans(Arc<Mutex<Recorded>>);
+
+    struct Fields<'a>(&'a mut Vec<(String, String)>);
+
+    impl<'a> Visit for Fields<'a> {
+        fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+            self.0.push((field.name().to_string(), format!("{:?}", value)));
+        }
+    }
+
+    impl Spans {
+        fn named(&self, name: &str) -> Vec<Vec<(String, String)>> {
+            self.0.lock().unwrap().iter().filter(|s| s.0 == name).map(|s| s.1.clone()).collect()
+        }
+    }
+
+    impl Subscriber for Spans {
+        fn enabled(&self, _: &Metadata) -> bool { true }
+
+        fn new_span(&self, attrs: &Attributes) -> Id {
+            let mut fields = Vec::new();
+            attrs.record(&mut Fields(&mut fields));
+            let mut spans = self.0.lock().unwrap();
+            spans.push((attrs.metadata().name().to_string(), fields));
+            Id::from_u64(spans.len() as u64)
+        }
+
+        fn record(&self, id: &Id, values: &Record) {
+            let mut spans = self.0.lock().unwrap();
+            values.record(&mut Fields(&mut spans[id.into_u64() as usize - 1].1));
+        }
+
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+        fn event(&self, _: &Event) {}
+        fn enter(&self, _: &Id) {}
+        fn exit(&self, _: &Id) {}
+    }
+
+    fn field<'a>(fields: &'a [(String, String)], name: &str) -> Option<&'a str> {
+        fields.iter().find(|f| f.0 == name).map(|f| &f.1[..])
+    }
+
+    #[test]
+    fn test_operation_and_attempt_spans() {
+        let mock = MockTransport::new().respond(503, "").respond(200, "<GetCallerIdentityResponse/>");
+        let client = client("sts").retry_policy(fast_retries()).transport(mock);
+        let spans = Spans::default();
+        tracing::subscriber::with_default(spans.clone(), || {
+            client.send(ApiRequest::new("POST").operation("GetCallerIdentity")).unwrap();
+        });
+
+        let operations = spans.named("operation");
+        assert_eq!(operations.len(), 1);
+        let op = &operations[0];
+        assert_eq!(field(op, "service"), Some("sts"));
+        assert_eq!(field(op, "operation"), Some("GetCallerIdentity"));
+        assert_eq!(field(op, "region"), Some("eu-west-1"));
+        assert_eq!(field(op, "attempts"), Some("2"));
+        assert_eq!(field(op, "status"), Some("200"));
+        assert_eq!(field(op, "request_id"), Some("mock-200"));
+        assert!(field(op, "latency_ms").is_some());
+
+        let attempts = spans.named("attempt");
+        assert_eq!(attempts.len(), 2);
+        assert_eq!(field(&attempts[0], "status"), Some("503"));
+        assert_eq!(field(&attempts[1], "attempt"), Some("2"))
     }
 }
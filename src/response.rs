@@ -0,0 +1,66 @@
+use serialize::json::Json;
+use std::collections::BTreeMap;
+
+use crate::protocol::json;
+use crate::xml::{Element, ParseError};
+
+#[derive(Clone,Debug)]
+pub struct HttpResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl HttpResponse {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        let name = name.to_lowercase();
+        self.headers.iter().find(|&(k, _)| k.to_lowercase() == name).map(|(_, v)| &v[..])
+    }
+
+    // Members bound to a header prefix, with the prefix removed.
+    pub fn prefixed_headers(&self, prefix: &str) -> BTreeMap<String, String> {
+        let prefix = prefix.to_lowercase();
+        self.headers.iter()
+            .filter(|&(k, _)| k.to_lowercase().starts_with(&prefix))
+            .map(|(k, v)| (k[prefix.len()..].to_string(), v.clone()))
+            .collect()
+    }
+
+    pub fn request_id(&self) -> Option<String> {
+        self.header("x-amzn-RequestId").or(self.header("x-amz-request-id")).map(|v| v.to_string())
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.status >= 200 && self.status < 300
+    }
+
+    pub fn body_string(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+
+    pub fn xml(&self) -> Result<Element, ParseError> {
+        Element::parse(&self.body_string())
+    }
+
+    pub fn json(&self) -> Result<Json, String> {
+        json::parse_body(&self.body_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HttpResponse;
+
+    #[test]
+    fn test_headers() {
+        let res = HttpResponse {
+            status: 200,
+            headers: vec!(("x-amz-meta-owner".to_string(), "thom".to_string()),
+                          ("X-Amz-Request-Id".to_string(), "req-1".to_string())),
+            body: Vec::new(),
+        };
+        assert_eq!(res.prefixed_headers("x-amz-meta-").get("owner"), Some(&"thom".to_string()));
+        assert_eq!(res.request_id(), Some("req-1".to_string()));
+        assert!(res.is_success())
+    }
+}
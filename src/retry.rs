@@ -0,0 +1,310 @@
+use std::cmp;
+use std::error;
+use std::io::ErrorKind;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::error::{Error, ServiceError, TimeoutKind};
+use crate::protocol::service_error;
+use crate::response::HttpResponse;
+
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum RetryKind {
+    Throttling,
+    Transient,
+    Timeout,
+    NotRetryable,
+}
+
+const THROTTLING_CODES: &[&str] = &[
+    "Throttling", "ThrottlingException", "ThrottledException", "RequestThrottledException",
+    "TooManyRequestsException", "ProvisionedThroughputExceededException",
+    "TransactionInProgressException", "RequestLimitExceeded", "BandwidthLimitExceeded",
+    "LimitExceededException", "RequestThrottled", "SlowDown", "PriorRequestNotComplete",
+    "EC2ThrottledException",
+];
+
+const TRANSIENT_CODES: &[&str] = &[
+    "RequestTimeout", "RequestTimeoutException", "InternalError", "InternalFailure",
+    "ServiceUnavailable", "IDPCommunicationError",
+];
+
+const TRANSIENT_STATUSES: &[u16] = &[500, 502, 503, 504];
+
+pub fn classify_service_error(err: &ServiceError) -> RetryKind {
+    if THROTTLING_CODES.contains(&&err.code[..]) || err.status == 429 {
+        RetryKind::Throttling
+    } else if TRANSIENT_CODES.contains(&&err.code[..]) || TRANSIENT_STATUSES.contains(&err.status) {
+        RetryKind::Transient
+    } else {
+        RetryKind::NotRetryable
+    }
+}
+
+pub fn classify_response(res: &HttpResponse) -> RetryKind {
+    if res.is_success() || (res.status >= 300 && res.status < 400) {
+        return RetryKind::NotRetryable;
+    }
+    classify_service_error(&service_error(res))
+}
+
+pub fn classify_error(err: &Error) -> RetryKind {
+    let io = match *err {
+        Error::Io(ref e) => e,
+        Error::Http(ref e) => return classify_http_error(&**e),
+        Error::Service(ref e) => return classify_service_error(e),
+        Error::Timeout(TimeoutKind::Operation) => return RetryKind::NotRetryable,
+        Error::Timeout(_) => return RetryKind::Timeout,
+        _ => return RetryKind::NotRetryable,
+    };
+    match io.kind() {
+        ErrorKind::TimedOut | ErrorKind::WouldBlock => RetryKind::Timeout,
+        ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted | ErrorKind::ConnectionRefused |
+        ErrorKind::BrokenPipe | ErrorKind::UnexpectedEof => RetryKind::Transient,
+        _ => RetryKind::NotRetryable,
+    }
+}
+
+// hyper's errors for a connection the server closed before answering, which
+// is often a pooled connection that went stale, so worth another go.
+fn classify_http_error(err: &(dyn error::Error + 'static)) -> RetryKind {
+    let mut source = Some(err);
+    while let Some(e) = source {
+        match e.downcast_ref::<hyper::Error>() {
+            Some(e) if e.is_incomplete_message() || e.is_closed() || e.is_canceled() => {
+                return RetryKind::Transient
+            },
+            _ => source = e.source(),
+        }
+    }
+    RetryKind::NotRetryable
+}
+
+// A token bucket that stops retries once too many have failed, so a struggling
+// service isn't hit by a retry storm. Shared by every clone of a policy.
+#[derive(Debug)]
+pub struct RetryQuota {
+    capacity: u32,
+    available: u32,
+}
+
+const RETRY_COST: u32 = 5;
+const TIMEOUT_RETRY_COST: u32 = 10;
+const NO_RETRY_INCREMENT: u32 = 1;
+
+impl RetryQuota {
+    pub fn new(capacity: u32) -> RetryQuota {
+        RetryQuota {
+            capacity,
+            available: capacity,
+        }
+    }
+
+    pub fn available(&self) -> u32 {
+        self.available
+    }
+
+    fn acquire(&mut self, kind: RetryKind) -> Option<u32> {
+        let cost = if kind == RetryKind::Timeout { TIMEOUT_RETRY_COST } else { RETRY_COST };
+        if self.available < cost {
+            return None;
+        }
+        self.available -= cost;
+        Some(cost)
+    }
+
+    fn release(&mut self, amount: u32) {
+        self.available = cmp::min(self.capacity, self.available + amount);
+    }
+}
+
+#[derive(Clone,Debug)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    base_delay: Duration,
+    max_backoff: Duration,
+    quota: Arc<Mutex<RetryQuota>>,
+}
+
+// Tracks the retries of a single operation, so their cost can be refunded
+// to the quota when it eventually succeeds.
+#[derive(Debug)]
+pub struct RetryState {
+    attempt: u32,
+    cost: u32,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy::new()
+    }
+}
+
+impl RetryPolicy {
+    pub fn new() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(20),
+            quota: Arc::new(Mutex::new(RetryQuota::new(500))),
+        }
+    }
+
+    pub fn none() -> RetryPolicy {
+        RetryPolicy::new().max_attempts(1)
+    }
+
+    pub fn max_attempts(mut self, attempts: u32) -> RetryPolicy {
+        self.max_attempts = cmp::max(1, attempts);
+        self
+    }
+
+    pub fn base_delay(mut self, delay: Duration) -> RetryPolicy {
+        self.base_delay = delay;
+        self
+    }
+
+    pub fn max_backoff(mut self, backoff: Duration) -> RetryPolicy {
+        self.max_backoff = backoff;
+        self
+    }
+
+    pub fn quota(mut self, capacity: u32) -> RetryPolicy {
+        self.quota = Arc::new(Mutex::new(RetryQuota::new(capacity)));
+        self
+    }
+
+    pub fn available_quota(&self) -> u32 {
+        self.quota.lock().unwrap().available()
+    }
+
+    pub fn start(&self) -> RetryState {
+        RetryState { attempt: 1, cost: 0 }
+    }
+
+    // Returns how long to wait before the next attempt, or None if the
+    // operation should give up and return what it has.
+    pub fn should_retry(&self, state: &mut RetryState, kind: RetryKind) -> Option<Duration> {
+        if kind == RetryKind::NotRetryable || state.attempt >= self.max_attempts {
+            return None;
+        }
+        match self.quota.lock().unwrap().acquire(kind) {
+            Some(cost) => state.cost += cost,
+            None => return None,
+        }
+        let delay = self.backoff(state.attempt, rand::random::<f64>());
+        state.attempt += 1;
+        Some(delay)
+    }
+
+    pub fn succeeded(&self, state: &RetryState) {
+        let refund = if state.cost == 0 { NO_RETRY_INCREMENT } else { state.cost };
+        self.quota.lock().unwrap().release(refund);
+    }
+
+    // Full jitter: a uniformly random delay up to base * 2^(attempt - 1),
+    // capped at max_backoff. `roll` is in [0, 1).
+    pub fn backoff(&self, attempt: u32, roll: f64) -> Duration {
+        let base = duration_millis(self.base_delay);
+        let ceiling = base.saturating_mul(1u64 << cmp::min(attempt.saturating_sub(1), 32));
+        let ceiling = cmp::min(ceiling, duration_millis(self.max_backoff));
+        Duration::from_millis((ceiling as f64 * roll) as u64)
+    }
+}
+
+impl RetryState {
+    // Counts from 1.
+    pub fn attempt(&self) -> u32 {
+        self.attempt
+    }
+}
+
+pub fn duration_millis(d: Duration) -> u64 {
+    d.as_secs() * 1000 + d.subsec_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RetryPolicy, RetryKind, classify_response, classify_error};
+    use crate::error::{Error, TimeoutKind};
+    use crate::response::HttpResponse;
+    use std::io;
+    use std::time::Duration;
+
+    fn response(status: u16, body: &str) -> HttpResponse {
+        HttpResponse {
+            status,
+            headers: Vec::new(),
+            body: body.as_bytes().to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_classify_responses() {
+        assert_eq!(classify_response(&response(200, "")), RetryKind::NotRetryable);
+        assert_eq!(classify_response(&response(503, "")), RetryKind::Transient);
+        assert_eq!(classify_response(&response(429, "")), RetryKind::Throttling);
+        assert_eq!(classify_response(&response(400, "<Response><Errors><Error><Code>RequestLimitExceeded</Code></Error></Errors></Response>")), RetryKind::Throttling);
+        assert_eq!(classify_response(&response(400, r#"{"__type":"ProvisionedThroughputExceededException"}"#)), RetryKind::Throttling);
+        assert_eq!(classify_response(&response(403, "<Error><Code>AccessDenied</Code></Error>")), RetryKind::NotRetryable)
+    }
+
+    #[test]
+    fn test_classify_io_errors() {
+        let reset = Error::Io(io::Error::new(io::ErrorKind::ConnectionReset, "reset"));
+        let timeout = Error::Io(io::Error::new(io::ErrorKind::TimedOut, "timed out"));
+        let other = Error::Io(io::Error::new(io::ErrorKind::PermissionDenied, "nope"));
+        assert_eq!(classify_error(&reset), RetryKind::Transient);
+        assert_eq!(classify_error(&timeout), RetryKind::Timeout);
+        assert_eq!(classify_error(&other), RetryKind::NotRetryable);
+        assert_eq!(classify_error(&Error::Timeout(TimeoutKind::Attempt)), RetryKind::Timeout);
+        assert_eq!(classify_error(&Error::Timeout(TimeoutKind::Operation)), RetryKind::NotRetryable)
+    }
+
+    #[test]
+    fn test_backoff() {
+        let policy = RetryPolicy::new().base_delay(Duration::from_millis(100)).max_backoff(Duration::from_secs(1));
+        assert_eq!(policy.backoff(1, 0.5), Duration::from_millis(50));
+        assert_eq!(policy.backoff(3, 0.5), Duration::from_millis(200));
+        assert_eq!(policy.backoff(10, 0.5), Duration::from_millis(500));
+        assert_eq!(policy.backoff(3, 0.0), Duration::from_millis(0));
+        assert_eq!(policy.backoff(0, 0.5), Duration::from_millis(50))
+    }
+
+    #[test]
+    fn test_max_attempts() {
+        let policy = RetryPolicy::new().max_attempts(2);
+        let mut state = policy.start();
+        assert!(policy.should_retry(&mut state, RetryKind::Transient).is_some());
+        assert!(policy.should_retry(&mut state, RetryKind::Transient).is_none())
+    }
+
+    #[test]
+    fn test_not_retryable() {
+        let policy = RetryPolicy::new();
+        let mut state = policy.start();
+        assert!(policy.should_retry(&mut state, RetryKind::NotRetryable).is_none())
+    }
+
+    #[test]
+    fn test_quota_exhaustion_and_refund() {
+        let policy = RetryPolicy::new().max_attempts(10).quota(12);
+        let mut state = policy.start();
+        assert!(policy.should_retry(&mut state, RetryKind::Transient).is_some());
+        assert!(policy.should_retry(&mut state, RetryKind::Transient).is_some());
+        assert_eq!(policy.available_quota(), 2);
+        assert!(policy.should_retry(&mut state, RetryKind::Transient).is_none());
+
+        policy.succeeded(&state);
+        assert_eq!(policy.available_quota(), 12)
+    }
+
+    #[test]
+    fn test_quota_shared_by_clones() {
+        let policy = RetryPolicy::new().quota(20);
+        let clone = policy.clone();
+        let mut state = clone.start();
+        clone.should_retry(&mut state, RetryKind::Timeout);
+        assert_eq!(policy.available_quota(), 10)
+    }
+}
@@ -0,0 +1,110 @@
+use std::future::Future;
+use std::panic;
+use std::pin::Pin;
+use std::sync::mpsc;
+use std::sync::OnceLock;
+use std::task::{Context, Poll};
+
+use tokio::runtime::{Builder, Handle, Runtime};
+use tokio::task::JoinHandle;
+use tracing::instrument::WithSubscriber;
+use tracing::Instrument;
+
+pub type BoxFuture<T> = Pin<Box<dyn Future<Output = T> + Send>>;
+
+// The runtime every request, connection and timer runs on. Owning it means
+// futures from this crate can be awaited on any executor, and the blocking
+// client can wait on them from any thread, including a runtime's own.
+pub fn handle() -> &'static Handle {
+    static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+    RUNTIME.get_or_init(|| {
+        Builder::new_multi_thread()
+            .enable_all()
+            .thread_name("aws-runtime")
+            .build()
+            .expect("could not start the aws runtime")
+    }).handle()
+}
+
+// A task on the shared runtime, keeping the caller's tracing subscriber and
+// span. Dropping it aborts the task.
+pub fn spawn<F>(future: F) -> Task<F::Output>
+    where F: Future + Send + 'static, F::Output: Send + 'static
+{
+    let future = future.in_current_span().with_current_subscriber();
+    Task { handle: handle().spawn(future) }
+}
+
+pub fn spawn_blocking<F, T>(f: F) -> Task<T>
+    where F: FnOnce() -> T + Send + 'static, T: Send + 'static
+{
+    Task { handle: handle().spawn_blocking(f) }
+}
+
+// Runs a future on the shared runtime and waits for it, re-raising a panic.
+pub fn block_on<F>(future: F) -> F::Output
+    where F: Future + Send + 'static, F::Output: Send + 'static
+{
+    let (tx, rx) = mpsc::channel();
+    let mut task = spawn(future);
+    handle().spawn(async move {
+        let _ = tx.send((&mut task.handle).await);
+    });
+    match rx.recv().expect("the aws runtime has shut down") {
+        Ok(output) => output,
+        Err(e) => panic::resume_unwind(e.into_panic()),
+    }
+}
+
+#[derive(Debug)]
+pub struct Task<T> {
+    handle: JoinHandle<T>,
+}
+
+impl<T> Future for Task<T> {
+    type Output = T;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<T> {
+        match Pin::new(&mut self.handle).poll(cx) {
+            Poll::Pending => Poll::Pending,
+            Poll::Ready(Ok(output)) => Poll::Ready(output),
+            Poll::Ready(Err(e)) => panic::resume_unwind(e.into_panic()),
+        }
+    }
+}
+
+impl<T> Drop for Task<T> {
+    fn drop(&mut self) {
+        self.handle.abort()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{block_on, spawn};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_block_on() {
+        assert_eq!(block_on(async { 1 + 1 }), 2);
+        // From inside another runtime, too.
+        let rt = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        assert_eq!(rt.block_on(async { block_on(async { 3 }) }), 3)
+    }
+
+    #[tokio::test]
+    async fn test_timers_do_not_block() {
+        let start = Instant::now();
+        let sleeps: Vec<_> = (0..50).map(|_| spawn(tokio::time::sleep(Duration::from_millis(50)))).collect();
+        for sleep in sleeps {
+            sleep.await;
+        }
+        assert!(start.elapsed() < Duration::from_millis(1000))
+    }
+
+    #[test]
+    #[should_panic(expected = "boom")]
+    fn test_panics_propagate() {
+        block_on(async { panic!("boom") })
+    }
+}
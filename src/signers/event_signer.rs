@@ -0,0 +1,165 @@
+use time::{now_utc, at_utc, Tm, Timespec};
+use serialize::hex::ToHex;
+
+use crate::error;
+use crate::event_stream::{EncodeError, Message, Header, HeaderValue};
+use crate::signers::sigv4::{hmac, to_hexdigest, SigV4};
+
+// What the request opening an event stream signs in place of a payload hash.
+pub const STREAMING_EVENTS_PAYLOAD: &str = "STREAMING-AWS4-HMAC-SHA256-EVENTS";
+
+// Signs application/vnd.amazon.eventstream frames. Each frame's signature
+// covers the previous one, starting from the signature of the request that
+// opened the stream.
+#[derive(Clone,Debug)]
+pub struct EventSigner {
+    signer: SigV4,
+    prior_signature: String,
+}
+
+impl EventSigner {
+    // The opening request must be signed by `signer()`, which signs and sends
+    // STREAMING_EVENTS_PAYLOAD as its payload hash. Fails without credentials.
+    pub fn new(signer: SigV4) -> error::Result<EventSigner> {
+        let signer = signer.payload_hash(STREAMING_EVENTS_PAYLOAD);
+        let seed = signer.seed_signature()?;
+        Ok(EventSigner {
+            signer,
+            prior_signature: seed,
+        })
+    }
+
+    pub fn signer(&self) -> &SigV4 {
+        &self.signer
+    }
+
+    pub fn prior_signature(&self) -> &str {
+        &self.prior_signature
+    }
+
+    // Wraps an encoded message in a signed frame. An empty payload signs the
+    // end-of-stream frame.
+    pub fn sign(&mut self, payload: &[u8]) -> Message {
+        self.sign_at(payload, now_utc())
+    }
+
+    pub fn sign_message(&mut self, message: &Message) -> Result<Message, EncodeError> {
+        Ok(self.sign(&message.encode()?))
+    }
+
+    pub fn sign_at(&mut self, payload: &[u8], date: Tm) -> Message {
+        let sig = self.signer.clone().timestamp(date);
+        let ts = date.to_timespec();
+        let date_header = Header::new(":date", HeaderValue::Timestamp(ts.sec * 1000 + (ts.nsec / 1000000) as i64));
+
+        let mut encoded_date = Vec::new();
+        date_header.encode(&mut encoded_date).expect(":date header always fits");
+
+        let string_to_sign = format!("AWS4-HMAC-SHA256-PAYLOAD\n{}\n{}\n{}\n{}\n{}",
+                                     sig.amz_date(),
+                                     sig.credential_scope(),
+                                     self.prior_signature,
+                                     to_hexdigest(&encoded_date),
+                                     to_hexdigest(payload));
+        let key = sig.derived_signing_key().expect("new checked the credentials");
+        let signature = hmac(&key, string_to_sign.as_bytes());
+        self.prior_signature = signature.to_hex();
+
+        Message::new(vec!(date_header,
+                          Header::new(":chunk-signature", HeaderValue::Bytes(signature))),
+                     payload.to_vec())
+    }
+
+    // Checks a frame produced by `sign`, advancing the chain if it matches.
+    pub fn verify(&mut self, frame: &Message) -> bool {
+        let millis = match frame.header(":date") {
+            Some(&HeaderValue::Timestamp(ms)) => ms,
+            _ => return false,
+        };
+        let expected = match frame.header(":chunk-signature") {
+            Some(HeaderValue::Bytes(sig)) => sig.clone(),
+            _ => return false,
+        };
+        let date = at_utc(Timespec::new(millis / 1000, ((millis % 1000) * 1000000) as i32));
+
+        let mut candidate = self.clone();
+        let signed = candidate.sign_at(&frame.payload, date);
+        if signed.header(":chunk-signature") == Some(&HeaderValue::Bytes(expected)) {
+            *self = candidate;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EventSigner, STREAMING_EVENTS_PAYLOAD};
+    use crate::event_stream::{Message, HeaderValue};
+    use crate::signers::sigv4::SigV4;
+    use crate::credentials::Credentials;
+    use time::strptime;
+    use serialize::hex::ToHex;
+
+    fn signer() -> EventSigner {
+        let cred = Credentials::new().path("fixtures/credentials.ini").profile("aws").load();
+        let sig = SigV4::new().credentials(cred).region("us-east-1").service("transcribe");
+        EventSigner {
+            signer: sig,
+            prior_signature: "e1d8e8c8815e60969f2a34765c9a15945ffc0badbaa4b7e3b163ea19131e949b".to_string(),
+        }
+    }
+
+    fn chunk_signature(frame: &Message) -> String {
+        match frame.header(":chunk-signature") {
+            Some(HeaderValue::Bytes(sig)) => sig.to_hex(),
+            _ => panic!("frame is not signed"),
+        }
+    }
+
+    #[test]
+    fn test_sign_chains_signatures() {
+        let date = strptime("20130524T000000Z", "%Y%m%dT%H%M%SZ").unwrap();
+        let mut signer = signer();
+
+        let frame = signer.sign_at(b"hello", date);
+        assert_eq!(frame.header(":date"), Some(&HeaderValue::Timestamp(1369353600000)));
+        assert_eq!(frame.payload, b"hello".to_vec());
+        assert_eq!(chunk_signature(&frame), "36185f2f3773ee1301ab18b1785596ad703b983d52bd32afbb72e04a2de06e52");
+        assert_eq!(signer.prior_signature(), "36185f2f3773ee1301ab18b1785596ad703b983d52bd32afbb72e04a2de06e52");
+
+        let end = signer.sign_at(b"", date);
+        assert_eq!(chunk_signature(&end), "f3ee971dd67fed160ce59267312d2a994e8f557a80e66317bb3f1231ed297058")
+    }
+
+    #[test]
+    fn test_verify() {
+        let date = strptime("20130524T000000Z", "%Y%m%dT%H%M%SZ").unwrap();
+        let frame = signer().sign_at(b"hello", date);
+
+        let mut verifier = signer();
+        assert!(verifier.verify(&frame));
+        assert!(!verifier.verify(&frame))
+    }
+
+    #[test]
+    fn test_seed_from_request_signature() {
+        let cred = Credentials::new().path("fixtures/credentials.ini").profile("aws").load();
+        let date = strptime("20130524T000000Z", "%Y%m%dT%H%M%SZ").unwrap();
+        let sig = SigV4::new().credentials(cred).region("us-east-1").service("transcribe").timestamp(date)
+            .method("POST").path("/stream-transcription").header(("Host", "transcribestreaming.us-east-1.amazonaws.com"));
+        let signer = EventSigner::new(sig).unwrap();
+        assert_eq!(signer.prior_signature(), "218d5398e8aec3e773788eb47223ba7f08709aa60cf4867f53b488c3487f5a75");
+
+        let headers = signer.signer().clone().as_headers().unwrap();
+        assert!(headers.contains(&("x-amz-content-sha256".to_string(), STREAMING_EVENTS_PAYLOAD.to_string())));
+        let auth = &headers.iter().find(|h| h.0 == "authorization").unwrap().1;
+        assert!(auth.ends_with("Signature=218d5398e8aec3e773788eb47223ba7f08709aa60cf4867f53b488c3487f5a75"))
+    }
+
+    #[test]
+    fn test_new_without_credentials() {
+        assert!(EventSigner::new(SigV4::new().region("us-east-1").service("transcribe")).is_err())
+    }
+}
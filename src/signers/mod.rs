@@ -1,3 +1,3 @@
-pub use self::http_headers::*;
-pub mod http_headers;
+pub mod event_signer;
+pub mod post_policy;
 pub mod sigv4;
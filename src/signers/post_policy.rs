@@ -0,0 +1,189 @@
+use time::Tm;
+use serialize::hex::ToHex;
+use serialize::base64::{ToBase64, STANDARD};
+use serialize::json::Json;
+use std::collections::BTreeMap;
+
+use crate::error::Result;
+use crate::signers::sigv4::{hmac, SigV4};
+
+#[derive(Clone,Debug)]
+enum Condition {
+    Exact(String, String),
+    StartsWith(String, String),
+    ContentLengthRange(u64, u64),
+}
+
+impl Condition {
+    fn to_json(&self) -> Json {
+        match *self {
+            Condition::Exact(ref field, ref value) => {
+                let mut obj = BTreeMap::new();
+                obj.insert(field.clone(), Json::String(value.clone()));
+                Json::Object(obj)
+            },
+            Condition::StartsWith(ref field, ref prefix) => {
+                Json::Array(vec!(Json::String("starts-with".to_string()),
+                                 Json::String(format!("${}", field)),
+                                 Json::String(prefix.clone())))
+            },
+            Condition::ContentLengthRange(min, max) => {
+                Json::Array(vec!(Json::String("content-length-range".to_string()),
+                                 Json::U64(min), Json::U64(max)))
+            },
+        }
+    }
+}
+
+#[derive(Clone,Debug)]
+pub struct PostPolicy {
+    expiration: Tm,
+    conditions: Vec<Condition>,
+    key: Option<String>,
+    content_type: Option<String>,
+}
+
+impl PostPolicy {
+    // S3 rejects a policy without an expiration, so one is always needed.
+    pub fn new(expiration: Tm) -> PostPolicy {
+        PostPolicy {
+            expiration,
+            conditions: Vec::new(),
+            key: None,
+            content_type: None,
+        }
+    }
+
+    pub fn bucket(mut self, bucket: &str) -> PostPolicy {
+        self.conditions.push(Condition::Exact("bucket".to_string(), bucket.to_string()));
+        self
+    }
+
+    pub fn key(mut self, key: &str) -> PostPolicy {
+        self.conditions.push(Condition::Exact("key".to_string(), key.to_string()));
+        self.key = Some(key.to_string());
+        self
+    }
+
+    // The browser fills in the rest of the key, so the form field becomes
+    // `prefix${filename}`.
+    pub fn key_prefix(mut self, prefix: &str) -> PostPolicy {
+        self.conditions.push(Condition::StartsWith("key".to_string(), prefix.to_string()));
+        self.key = Some(format!("{}${{filename}}", prefix));
+        self
+    }
+
+    pub fn content_length_range(mut self, min: u64, max: u64) -> PostPolicy {
+        self.conditions.push(Condition::ContentLengthRange(min, max));
+        self
+    }
+
+    pub fn content_type(mut self, content_type: &str) -> PostPolicy {
+        self.conditions.push(Condition::Exact("Content-Type".to_string(), content_type.to_string()));
+        self.content_type = Some(content_type.to_string());
+        self
+    }
+
+    pub fn content_type_prefix(mut self, prefix: &str) -> PostPolicy {
+        self.conditions.push(Condition::StartsWith("Content-Type".to_string(), prefix.to_string()));
+        self
+    }
+
+    pub fn condition(mut self, field: &str, value: &str) -> PostPolicy {
+        self.conditions.push(Condition::Exact(field.to_string(), value.to_string()));
+        self
+    }
+
+    fn document(&self, sig: &SigV4) -> Result<String> {
+        let mut conditions: Vec<Json> = self.conditions.iter().map(|c| c.to_json()).collect();
+        for &(k, ref v) in [("x-amz-algorithm", "AWS4-HMAC-SHA256".to_string()),
+                            ("x-amz-credential", sig.credential()?),
+                            ("x-amz-date", sig.amz_date())].iter() {
+            conditions.push(Condition::Exact(k.to_string(), v.clone()).to_json());
+        }
+
+        let mut doc = BTreeMap::new();
+        doc.insert("conditions".to_string(), Json::Array(conditions));
+        let exp = self.expiration.strftime("%Y-%m-%dT%H:%M:%S.000Z").unwrap().to_string();
+        doc.insert("expiration".to_string(), Json::String(exp));
+        Ok(Json::Object(doc).to_string())
+    }
+
+    // Returns the hidden form fields for the upload form. `sig` needs
+    // credentials, region and the `s3` service set; without credentials it
+    // fails.
+    pub fn sign(&self, sig: &SigV4) -> Result<BTreeMap<String, String>> {
+        let policy = self.document(sig)?.as_bytes().to_base64(STANDARD);
+        let signature = hmac(&sig.derived_signing_key()?, policy.as_bytes()).to_hex();
+
+        let mut fields = BTreeMap::new();
+        if let Some(ref key) = self.key {
+            fields.insert("key".to_string(), key.clone());
+        }
+        if let Some(ref content_type) = self.content_type {
+            fields.insert("Content-Type".to_string(), content_type.clone());
+        }
+        fields.insert("x-amz-algorithm".to_string(), "AWS4-HMAC-SHA256".to_string());
+        fields.insert("x-amz-credential".to_string(), sig.credential()?);
+        fields.insert("x-amz-date".to_string(), sig.amz_date());
+        fields.insert("policy".to_string(), policy);
+        fields.insert("x-amz-signature".to_string(), signature);
+        Ok(fields)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PostPolicy;
+    use crate::error::Error;
+    use crate::signers::sigv4::SigV4;
+    use crate::credentials::Credentials;
+    use time::strptime;
+
+    fn signer() -> SigV4 {
+        let cred = Credentials::new().path("fixtures/credentials.ini").profile("aws").load();
+        SigV4::new().credentials(cred).region("us-east-1").service("s3")
+            .timestamp(strptime("20151229T000000Z", "%Y%m%dT%H%M%SZ").unwrap())
+    }
+
+    fn policy() -> PostPolicy {
+        PostPolicy::new(strptime("20151230T120000Z", "%Y%m%dT%H%M%SZ").unwrap())
+            .bucket("sigv4examplebucket")
+            .key_prefix("user/user1/")
+            .content_type_prefix("image/")
+            .content_length_range(0, 1048576)
+    }
+
+    #[test]
+    fn test_policy_document() {
+        assert_eq!(policy().document(&signer()).unwrap(), r#"{"conditions":[{"bucket":"sigv4examplebucket"},["starts-with","$key","user/user1/"],["starts-with","$Content-Type","image/"],["content-length-range",0,1048576],{"x-amz-algorithm":"AWS4-HMAC-SHA256"},{"x-amz-credential":"akid/20151229/us-east-1/s3/aws4_request"},{"x-amz-date":"20151229T000000Z"}],"expiration":"2015-12-30T12:00:00.000Z"}"#)
+    }
+
+    #[test]
+    fn test_form_fields() {
+        let fields = policy().sign(&signer()).unwrap();
+        assert_eq!(fields.get("key").unwrap(), "user/user1/${filename}");
+        assert_eq!(fields.get("x-amz-algorithm").unwrap(), "AWS4-HMAC-SHA256");
+        assert_eq!(fields.get("x-amz-credential").unwrap(), "akid/20151229/us-east-1/s3/aws4_request");
+        assert_eq!(fields.get("x-amz-date").unwrap(), "20151229T000000Z");
+        assert!(fields.get("policy").unwrap().starts_with("eyJjb25kaXRpb25zIjpb"));
+        assert_eq!(fields.get("x-amz-signature").unwrap(), "caca804ecdcc83de0dcad26593c169ae90c2cbc3aef74fbaf21daf2d6d9accc3")
+    }
+
+    #[test]
+    fn test_exact_content_type_field() {
+        let expiration = strptime("20151230T120000Z", "%Y%m%dT%H%M%SZ").unwrap();
+        let fields = PostPolicy::new(expiration).bucket("b").key("a.jpg").content_type("image/jpeg").sign(&signer()).unwrap();
+        assert_eq!(fields.get("key").unwrap(), "a.jpg");
+        assert_eq!(fields.get("Content-Type").unwrap(), "image/jpeg")
+    }
+
+    #[test]
+    fn test_sign_without_credentials() {
+        let sig = SigV4::new().region("us-east-1").service("s3");
+        match policy().sign(&sig) {
+            Err(Error::Config(_)) => {},
+            other => panic!("expected a config error, got {:?}", other),
+        }
+    }
+}
@@ -1,52 +1,173 @@
 use time::now_utc;
 use time::Tm;
-use std::ascii::AsciiExt;
-use openssl::crypto::hash::hash;
-use openssl::crypto::hmac::hmac;
-use openssl::crypto::hash::Type::SHA256;
+use openssl::hash::{hash, MessageDigest};
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
 use serialize::hex::ToHex;
 use std::collections::BTreeMap;
 use std::collections::btree_map::Entry;
-use url::percent_encoding::{percent_encode_to, FORM_URLENCODED_ENCODE_SET};
-use hyper::header::Headers;
+use http::header::{HeaderName, HeaderValue};
+use http::uri::{Uri, InvalidUri};
 use std::str;
 
-use credentials::Credentials;
+use crate::credentials::Credentials;
+use crate::error::{self, Error};
+
+// S3 signs the object key exactly as it was encoded on the wire; every other
+// service normalises the path and encodes each segment a second time.
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum PathMode {
+    Normalized,
+    S3,
+}
+
+// Headers matching an `always` pattern are signed even if they also match a
+// `never` pattern; anything matching neither list is signed. A trailing `*`
+// matches by prefix.
+#[derive(Clone,Debug)]
+pub struct HeaderPolicy {
+    always: Vec<String>,
+    never: Vec<String>,
+}
+
+impl Default for HeaderPolicy {
+    fn default() -> HeaderPolicy {
+        HeaderPolicy::new()
+    }
+}
+
+impl HeaderPolicy {
+    pub fn new() -> HeaderPolicy {
+        let always = ["host", "content-type", "content-md5", "x-amz-*"];
+        // authorization is the signature itself; the rest are hop-by-hop or
+        // liable to be rewritten by proxies and load balancers.
+        let never = ["authorization", "content-length", "user-agent", "connection",
+                     "keep-alive", "proxy-authenticate", "proxy-authorization",
+                     "proxy-connection", "te", "trailer", "transfer-encoding", "upgrade",
+                     "expect", "via", "x-forwarded-*", "x-amzn-trace-id"];
+        HeaderPolicy {
+            always: always.iter().map(|h| h.to_string()).collect(),
+            never: never.iter().map(|h| h.to_string()).collect(),
+        }
+    }
+
+    pub fn empty() -> HeaderPolicy {
+        HeaderPolicy {
+            always: Vec::new(),
+            never: Vec::new(),
+        }
+    }
+
+    pub fn always(mut self, pattern: &str) -> HeaderPolicy {
+        self.always.push(pattern.to_ascii_lowercase());
+        self
+    }
+
+    pub fn never(mut self, pattern: &str) -> HeaderPolicy {
+        self.never.push(pattern.to_ascii_lowercase());
+        self
+    }
+
+    pub fn signs(&self, header: &str) -> bool {
+        let header = header.to_ascii_lowercase();
+        if self.always.iter().any(|p| pattern_matches(p, &header)) {
+            return true;
+        }
+        !self.never.iter().any(|p| pattern_matches(p, &header))
+    }
+}
 
 #[derive(Clone,Debug)]
 pub struct SigV4 {
     credentials: Option<Credentials>,
     date: Tm,
+    header_policy: HeaderPolicy,
     headers: BTreeMap<String, Vec<Vec<u8>>>,
     method: Option<String>,
     path: Option<String>,
-    payload: Option<String>,
-    query: Option<String>,
+    path_mode: PathMode,
+    payload: Option<Vec<u8>>,
+    // Signed in place of the payload's digest.
+    payload_hash: Option<String>,
+    query: Option<Vec<(String, String)>>,
+    // The path as the uri encoded it, which S3 signs without decoding.
+    raw_path: Option<String>,
     region: Option<String>,
     service: Option<String>,
 }
 
-impl<'a> SigV4 {
+impl Default for SigV4 {
+    fn default() -> SigV4 {
+        SigV4::new()
+    }
+}
+
+impl SigV4 {
     pub fn new() -> SigV4{
         let dt = now_utc();
         SigV4 {
             credentials: None,
             date: dt,
+            header_policy: HeaderPolicy::new(),
             headers: BTreeMap::new(),
             method: None,
             path: None,
+            path_mode: PathMode::Normalized,
             payload: None,
+            payload_hash: None,
             query: None,
+            raw_path: None,
             region: None,
             service: None,
         }
     }
 
+    pub fn from_url(method: &str, url: &str) -> Result<SigV4, InvalidUri> {
+        let uri = url.parse::<Uri>()?;
+        Ok(SigV4::new().method(method).uri(&uri))
+    }
+
+    // The request's own headers come first, so its Host wins over the uri's.
+    // Fails on a header value that isn't UTF-8, which can't be signed as sent.
+    pub fn from_request<B: AsRef<[u8]>>(req: &http::Request<B>) -> error::Result<SigV4> {
+        let mut sig = SigV4::new().method(req.method().as_str());
+        for (key, value) in req.headers().iter() {
+            match str::from_utf8(value.as_bytes()) {
+                Ok(v) => append_header(&mut sig.headers, key.as_str(), v),
+                Err(_) => return Err(Error::Unsupported(format!("{} header is not UTF-8", key))),
+            }
+        }
+        Ok(sig.uri(req.uri()).payload_bytes(req.body().as_ref()))
+    }
+
+    // Host (with any non-default port) unless one is set, path and query are
+    // all taken from the uri. In the query, + is a space and %2B a plus.
+    fn uri(mut self, uri: &Uri) -> SigV4 {
+        if let Some(host) = host_header(uri) {
+            if !self.headers.contains_key("host") {
+                append_header(&mut self.headers, "host", &host);
+            }
+        }
+        self.path = Some(percent_decode(uri.path()));
+        self.raw_path = Some(uri.path().to_string());
+        self.query = uri.query().map(|q| {
+            parse_query(q).iter()
+                .map(|&(k, v)| (percent_decode(&k.replace('+', "%20")), percent_decode(&v.replace('+', "%20"))))
+                .collect()
+        });
+        self
+    }
+
     pub fn header(mut self, header: (&str, &str)) -> SigV4 {
         append_header(&mut self.headers, header.0, header.1);
         self
     }
 
+    pub fn header_policy(mut self, policy: HeaderPolicy) -> SigV4 {
+        self.header_policy = policy;
+        self
+    }
+
     pub fn credentials(mut self, credentials: Credentials) -> SigV4 {
         self.credentials = Some(credentials);
         self
@@ -55,6 +176,12 @@ impl<'a> SigV4 {
     pub fn path(mut self, path: &str) -> SigV4 {
         let path = String::from(path);
         self.path = Some(path);
+        self.raw_path = None;
+        self
+    }
+
+    pub fn path_mode(mut self, mode: PathMode) -> SigV4 {
+        self.path_mode = mode;
         self
     }
 
@@ -65,14 +192,36 @@ impl<'a> SigV4 {
     }
 
     pub fn query(mut self, query: &str) -> SigV4 {
-        let query = String::from(query);
+        let query = parse_query(query).iter()
+            .map(|&(k, v)| (k.to_string(), v.to_string()))
+            .collect();
         self.query = Some(query);
         self
     }
 
-    pub fn payload(mut self, payload: &str) -> SigV4 {
-        let payload = String::from(payload);
-        self.payload = Some(payload);
+    pub fn query_param(mut self, key: &str, value: &str) -> SigV4 {
+        let mut query = self.query.take().unwrap_or_default();
+        query.push((key.to_string(), value.to_string()));
+        self.query = Some(query);
+        self
+    }
+
+    pub fn payload(self, payload: &str) -> SigV4 {
+        self.payload_bytes(payload.as_bytes())
+    }
+
+    pub fn payload_bytes(mut self, payload: &[u8]) -> SigV4 {
+        self.payload = Some(payload.to_vec());
+        self
+    }
+
+    // Signs `hash` instead of the payload's digest and sends it as
+    // x-amz-content-sha256, e.g. STREAMING-AWS4-HMAC-SHA256-EVENTS for a
+    // request opening an event stream.
+    pub fn payload_hash(mut self, hash: &str) -> SigV4 {
+        self.payload_hash = Some(hash.to_string());
+        self.headers.remove("x-amz-content-sha256");
+        append_header(&mut self.headers, "x-amz-content-sha256", hash);
         self
     }
 
@@ -88,49 +237,112 @@ impl<'a> SigV4 {
         self
     }
 
+    pub fn timestamp(mut self, date: Tm) -> SigV4 {
+        self.date = date;
+        self
+    }
+
+    pub fn amz_date(&self) -> String {
+        self.date.strftime("%Y%m%dT%H%M%SZ").unwrap().to_string()
+    }
+
+    // The access key and credential scope, as used in x-amz-credential. Fails
+    // unless both the key and secret are set, as signing with it needs them.
+    pub fn credential(&self) -> error::Result<String> {
+        match self.credentials {
+            Some(Credentials { key: Some(ref key), secret: Some(_), .. }) => Ok(format!("{}/{}", key, self.credential_scope())),
+            _ => Err(Error::Config("signing needs an access key and secret".to_string())),
+        }
+    }
+
     fn date(mut self) -> SigV4 {
         append_header(&mut self.headers, "x-amz-date",
                       self.date.strftime("%Y%m%dT%H%M%SZ").unwrap().to_string().as_ref());
         self
     }
 
-    fn authorization(mut self) -> SigV4 {
-        let cs = self.credential_scope();
+    fn authorization(mut self) -> error::Result<SigV4> {
+        let credential = self.credential()?;
         let h = self.signed_headers();
-        let s = self.clone().signature();
+        let s = self.clone().signature()?;
 
-        let auth = format!("AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
-               self.clone().credentials.unwrap().key.unwrap(),
-               cs, h, s);
+        let auth = format!("AWS4-HMAC-SHA256 Credential={}, SignedHeaders={}, Signature={}",
+               credential, h, s);
 
-        debug!("Authorization header: {:?}", auth);
         append_header(&mut self.headers, "authorization", &auth);
-        self
+        Ok(self)
+    }
+
+    // Adds x-amz-date, authorization and, if missing, host to the request.
+    // Fails without credentials, or if http won't take a header, in which
+    // case the request is left as it was.
+    pub fn sign_request<B>(self, req: &mut http::Request<B>) -> error::Result<()> {
+        let fin = self.date().authorization()?;
+        let mut signed = Vec::new();
+        for (key, values) in fin.headers {
+            let name = HeaderName::from_bytes(key.as_bytes()).map_err(|e| Error::Http(Box::new(e)))?;
+            let values = values.iter()
+                .map(|v| HeaderValue::from_bytes(v))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| Error::Http(Box::new(e)))?;
+            signed.push((name, values));
+        }
+
+        let headers = req.headers_mut();
+        for (name, values) in signed {
+            headers.remove(&name);
+            for v in values {
+                headers.append(name.clone(), v);
+            }
+        }
+        Ok(())
     }
 
-    pub fn as_headers(self) -> Headers {
-        let fin = self.date().authorization();
-        let mut headers = Headers::new();
+    // The signed headers as (lowercase name, value) pairs, one per value.
+    pub fn as_headers(self) -> error::Result<Vec<(String, String)>> {
+        let fin = self.date().authorization()?;
+        let mut headers = Vec::new();
 
-        for h in fin.headers {
-            headers.set_raw(h.0, h.1);
+        for (key, values) in fin.headers {
+            for v in values {
+                headers.push((key.clone(), String::from_utf8_lossy(&v).into_owned()));
+            }
         }
-        headers
+        Ok(headers)
+    }
+
+    fn signature(self) -> error::Result<String> {
+        Ok(hmac(&self.derived_signing_key()?,
+                self.signing_string().as_bytes()).to_hex().to_string())
+    }
+
+    // The signature `as_headers` will put in the authorization header; event
+    // streams chain their message signatures from it.
+    pub fn seed_signature(&self) -> error::Result<String> {
+        self.clone().date().signature()
     }
 
-    fn signature(self) -> String {
-        hmac(SHA256, &self.derived_signing_key(),
-             self.signing_string().as_bytes()).to_hex().to_string()
+    // The canonical request and string to sign as as_headers will sign them,
+    // for comparing with what the service says it expected.
+    pub fn signed_canonical_request(&self) -> String {
+        self.clone().date().canonical_request()
+    }
+
+    pub fn string_to_sign(&self) -> String {
+        self.clone().date().signing_string()
     }
 
     #[allow(non_snake_case)]
-    fn derived_signing_key(&self) -> Vec<u8> {
-        let kSecret = self.clone().credentials.unwrap().secret.unwrap();
-        let kDate = hmac(SHA256, format!("AWS4{}", kSecret).as_bytes(),
+    pub fn derived_signing_key(&self) -> error::Result<Vec<u8>> {
+        let kSecret = match self.credentials {
+            Some(Credentials { secret: Some(ref secret), .. }) => secret,
+            _ => return Err(Error::Config("signing needs an access key and secret".to_string())),
+        };
+        let kDate = hmac(format!("AWS4{}", kSecret).as_bytes(),
                 self.date.strftime("%Y%m%d").unwrap().to_string().as_bytes());
-        let kRegion = hmac(SHA256, &kDate, expand_string(&self.region).as_bytes());
-        let kService = hmac(SHA256, &kRegion, expand_string(&self.service).as_bytes());
-        hmac(SHA256, &kService, "aws4_request".as_bytes())
+        let kRegion = hmac(&kDate, expand_string(&self.region).as_bytes());
+        let kService = hmac(&kRegion, expand_string(&self.service).as_bytes());
+        Ok(hmac(&kService, "aws4_request".as_bytes()))
     }
 
     fn signing_string(&self) -> String {
@@ -140,7 +352,7 @@ impl<'a> SigV4 {
                 self.hashed_canonical_request())
     }
 
-    fn credential_scope(&self) -> String {
+    pub fn credential_scope(&self) -> String {
         format!("{}/{}/{}/aws4_request",
                 self.date.strftime("%Y%m%d").unwrap(),
                 expand_string(&self.region),
@@ -148,28 +360,30 @@ impl<'a> SigV4 {
     }
 
     fn hashed_canonical_request(&self) -> String {
-        to_hexdigest(self.canonical_request())
+        to_hexdigest(self.canonical_request().as_bytes())
     }
 
-    fn hashed_payload(&self) -> String {
-        let val = match self.payload {
-            Some(ref x) => x.to_string(),
-            None => "".to_string(),
-        };
-        to_hexdigest(val)
+    pub fn hashed_payload(&self) -> String {
+        if let Some(ref hash) = self.payload_hash {
+            return hash.clone();
+        }
+        match self.payload {
+            Some(ref x) => to_hexdigest(x),
+            None => to_hexdigest(b""),
+        }
     }
 
     fn signed_headers(&self) -> String {
         let mut h = String::new();
 
         for (key,_) in self.headers.iter() {
-            if h.len() > 0 {
-                h.push(';')
-            }
-            if skipped_headers(&key) {
+            if !self.header_policy.signs(key) {
                 continue;
             }
-            h.push_str(&key);
+            if !h.is_empty() {
+                h.push(';')
+            }
+            h.push_str(key);
         }
         h
     }
@@ -178,7 +392,7 @@ impl<'a> SigV4 {
         let mut h = String::new();
 
         for (key,value) in self.headers.iter() {
-            if skipped_headers(&key) {
+            if !self.header_policy.signs(key) {
                 continue;
             }
             h.push_str(format!("{}:{}\n", key, canonical_value(value)).as_ref());
@@ -190,23 +404,49 @@ impl<'a> SigV4 {
         match self.query {
             None => String::new(),
             Some(ref x) => {
-                let mut h: Vec<(&str, &str)> = Vec::new();
-                for q in x.split('&') {
-                    if q.contains('=') {
-                        let n: Vec<&str> = q.splitn(2, '=').collect();
-                        h.push((n[0], n[1]))
-                    } else {
-                        h.push((q, ""))
-                    }
-                };
+                let h: Vec<(&str, &str)> = x.iter().map(|(k, v)| (k.as_ref(), v.as_ref())).collect();
                 sort_query_string(h)
             }
         }
     }
 
+    // The canonical query string is sorted and encoded, so it can also go on the wire.
+    pub fn encoded_query(&self) -> String {
+        self.canonical_query_string()
+    }
+
+    // The path as it should appear on the request line, percent-encoded once.
+    pub fn encoded_path(&self) -> String {
+        match self.path {
+            None => String::new(),
+            Some(ref x) => match self.path_mode {
+                PathMode::S3 => self.s3_path(x),
+                PathMode::Normalized => encode_segments(&normalize_path(x), 1),
+            }
+        }
+    }
+
+    fn canonical_uri(&self) -> String {
+        match self.path {
+            None => String::new(),
+            Some(ref x) => match self.path_mode {
+                PathMode::S3 => self.s3_path(x),
+                PathMode::Normalized => encode_segments(&normalize_path(x), 2),
+            }
+        }
+    }
+
+    // A key taken from a uri keeps its escapes, so an encoded slash stays one.
+    fn s3_path(&self, path: &str) -> String {
+        match self.raw_path {
+            Some(ref raw) => absolute_path(reencode(raw)),
+            None => absolute_path(uri_encode(path, false)),
+        }
+    }
+
     fn canonical_request(&self) -> String {
         format!("{}\n{}\n{}\n{}\n{}\n{}", expand_string(&self.method),
-                expand_string(&self.path),
+                self.canonical_uri(),
                 self.canonical_query_string(),
                 self.canonical_headers(),
                 self.signed_headers(),
@@ -216,31 +456,151 @@ impl<'a> SigV4 {
 
 }
 
-fn sort_query_string(mut query: Vec<(&str, &str)>) -> String {
-    #[inline]
-    fn byte_serialize(input: &str, output: &mut String) {
-        for &byte in input.as_bytes().iter() {
-            percent_encode_to(&[byte], FORM_URLENCODED_ENCODE_SET, output)
+// The Host header for a uri: the host, plus the port if it isn't the scheme's default.
+pub fn host_header(uri: &Uri) -> Option<String> {
+    uri.host().map(|host| {
+        let default_port = match uri.scheme_str() {
+            Some("http") => Some(80),
+            Some("https") => Some(443),
+            _ => None,
+        };
+        match uri.port_u16() {
+            Some(port) if Some(port) != default_port => format!("{}:{}", host, port),
+            _ => host.to_string(),
+        }
+    })
+}
+
+fn parse_query(query: &str) -> Vec<(&str, &str)> {
+    let mut h: Vec<(&str, &str)> = Vec::new();
+    for q in query.split('&') {
+        if q.contains('=') {
+            let n: Vec<&str> = q.splitn(2, '=').collect();
+            h.push((n[0], n[1]))
+        } else {
+            h.push((q, ""))
+        }
+    };
+    h
+}
+
+fn percent_decode(input: &str) -> String {
+    fn hex_value(b: u8) -> Option<u8> {
+        match b {
+            b'0'..=b'9' => Some(b - b'0'),
+            b'a'..=b'f' => Some(b - b'a' + 10),
+            b'A'..=b'F' => Some(b - b'A' + 10),
+            _ => None,
+        }
+    }
+
+    let bytes = input.as_bytes();
+    let mut output: Vec<u8> = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            if let (Some(h), Some(l)) = (hex_value(bytes[i + 1]), hex_value(bytes[i + 2])) {
+                output.push(h * 16 + l);
+                i += 3;
+                continue;
+            }
         }
+        output.push(bytes[i]);
+        i += 1;
     }
+    String::from_utf8_lossy(&output).into_owned()
+}
 
+// SigV4 wants spaces as %20 rather than form encoding's +, so this
+// encodes each key and value itself.
+fn sort_query_string(mut query: Vec<(&str, &str)>) -> String {
     let mut output = String::new();
 
     query.sort_by( |a, b| a.0.cmp(b.0));
     for item in query.iter() {
-        if output.len() > 0 {
-            output.push_str("&");
+        if !output.is_empty() {
+            output.push('&');
+        }
+        output.push_str(&uri_encode(item.0, true));
+        output.push('=');
+        output.push_str(&uri_encode(item.1, true));
+    }
+
+    output
+}
+
+// RFC 3986 section 5.2.4: drop empty and "." segments and resolve "..".
+fn normalize_path(path: &str) -> Vec<&str> {
+    let mut segments: Vec<&str> = Vec::new();
+    for seg in path.split('/') {
+        match seg {
+            "" | "." => {},
+            ".." => { segments.pop(); },
+            _ => segments.push(seg),
+        }
+    }
+    let trailing = path.ends_with('/') || path.ends_with("/.") || path.ends_with("/..");
+    if trailing && !segments.is_empty() {
+        segments.push("");
+    }
+    segments
+}
+
+fn encode_segments(segments: &Vec<&str>, times: usize) -> String {
+    let mut output = String::new();
+    for seg in segments {
+        let mut encoded = seg.to_string();
+        for _ in 0..times {
+            encoded = uri_encode(&encoded, true);
+        }
+        output.push('/');
+        output.push_str(&encoded);
+    }
+    if output.is_empty() {
+        output.push('/');
+    }
+    output
+}
+
+fn absolute_path(path: String) -> String {
+    if path.starts_with("/") {
+        path
+    } else {
+        format!("/{}", path)
+    }
+}
+
+fn uri_encode(input: &str, encode_slash: bool) -> String {
+    let mut output = String::new();
+    for &byte in input.as_bytes().iter() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                output.push(byte as char)
+            },
+            b'/' if !encode_slash => output.push('/'),
+            _ => output.push_str(&format!("%{:02X}", byte)),
         }
-        byte_serialize(item.0, &mut output);
-        output.push_str("=");
-        byte_serialize(item.1, &mut output);
     }
+    output
+}
 
+// Encodes as uri_encode does, keeping slashes and any escapes already there.
+fn reencode(input: &str) -> String {
+    let bytes = input.as_bytes();
+    let mut output = String::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let escape = bytes[i] == b'%' && i + 2 < bytes.len() &&
+            bytes[i + 1].is_ascii_hexdigit() && bytes[i + 2].is_ascii_hexdigit();
+        if escape {
+            output.push_str(&input[i..i + 3].to_ascii_uppercase());
+            i += 3;
+        } else {
+            output.push_str(&uri_encode(&input[i..i + 1], false));
+            i += 1;
+        }
+    }
     output
-    // it would be marvelous to use the below, but the AWS SigV4 spec says space must be %20, and
-    // rust-url gives me back a +. So, roll our own for now.
-    // let qs: Vec<(String, String)> = query.iter().map(|n| (n.k.to_string(), n.v.to_string())).collect();
-    // form_urlencoded::serialize_owned(qs.as_slice())
 }
 
 fn append_header(map: &mut BTreeMap<String, Vec<Vec<u8>>>, key: &str, value: &str) {
@@ -248,9 +608,7 @@ fn append_header(map: &mut BTreeMap<String, Vec<Vec<u8>>>, key: &str, value: &st
 
     match map.entry(k) {
         Entry::Vacant(entry) => {
-            let mut values = Vec::new();
-            values.push(value.as_bytes().to_vec());
-            entry.insert(values);
+            entry.insert(vec!(value.as_bytes().to_vec()));
         },
         Entry::Occupied(entry) => {
             entry.into_mut().push(value.as_bytes().to_vec());
@@ -258,9 +616,16 @@ fn append_header(map: &mut BTreeMap<String, Vec<Vec<u8>>>, key: &str, value: &st
     };
 }
 
-fn to_hexdigest(val: String) -> String {
-    let h = hash(SHA256, val.as_bytes());
-    h.as_slice().to_hex().to_string()
+pub fn to_hexdigest(val: &[u8]) -> String {
+    let h = hash(MessageDigest::sha256(), val).unwrap();
+    h.to_hex()
+}
+
+// HMAC-SHA256, as every step of signing uses it.
+pub fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let key = PKey::hmac(key).unwrap();
+    let mut signer = Signer::new(MessageDigest::sha256(), &key).unwrap();
+    signer.sign_oneshot_to_vec(data).unwrap()
 }
 
 fn expand_string(val: &Option<String>) -> String {
@@ -274,11 +639,11 @@ fn canonical_value(val: &Vec<Vec<u8>>) -> String {
     let mut st = String::new();
     for v in val {
         let s = str::from_utf8(v).unwrap();
-        if st.len() > 0 {
+        if !st.is_empty() {
             st.push(',')
         }
         if s.starts_with("\""){
-            st.push_str(&s);
+            st.push_str(s);
         } else {
             st.push_str(s.replace("  ", " ").trim());
         }
@@ -286,15 +651,17 @@ fn canonical_value(val: &Vec<Vec<u8>>) -> String {
     st
 }
 
-fn skipped_headers(header: &str) -> bool {
-    ["authorization", "content-length", "user-agent" ].contains(&header)
+fn pattern_matches(pattern: &str, header: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => header.starts_with(prefix),
+        None => pattern == header,
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::SigV4;
-    use signers::http_headers::*;
-    use credentials::Credentials;
+    use super::{SigV4, PathMode, HeaderPolicy};
+    use crate::credentials::Credentials;
     use time::strptime;
     use std::collections::BTreeMap;
     use serialize::hex::ToHex;
@@ -355,6 +722,12 @@ mod tests {
         assert_eq!(sig.canonical_query_string(), "other=&test=&x-amz-header=foo")
     }
 
+    #[test]
+    fn test_query_param() {
+        let sig = SigV4::new().query_param("Filter.1.Value", "a&b=c").query_param("Action", "Describe");
+        assert_eq!(sig.canonical_query_string(), "Action=Describe&Filter.1.Value=a%26b%3Dc")
+    }
+
     #[test]
     fn test_canonical_query_sorted() {
         let sig = SigV4::new().query("foo=&bar=&baz=");
@@ -368,6 +741,137 @@ mod tests {
         assert_eq!(sig.canonical_query_string(), "q=xyz&q=mno&q.options=abc")
     }
 
+    #[test]
+    fn test_canonical_uri_s3() {
+        let sig = SigV4::new().path_mode(PathMode::S3).path("photos/2015 summer/ä.jpg");
+        assert_eq!(sig.canonical_uri(), "/photos/2015%20summer/%C3%A4.jpg")
+    }
+
+    #[test]
+    fn test_canonical_uri_s3_not_normalized() {
+        let sig = SigV4::new().path_mode(PathMode::S3).path("/photos//2015/../ä.jpg");
+        assert_eq!(sig.canonical_uri(), "/photos//2015/../%C3%A4.jpg")
+    }
+
+    #[test]
+    fn test_canonical_uri_double_encoded() {
+        let sig = SigV4::new().path("/photos/2015 summer/ä.jpg");
+        assert_eq!(sig.canonical_uri(), "/photos/2015%2520summer/%25C3%25A4.jpg")
+    }
+
+    #[test]
+    fn test_encoded_path_single_encoded() {
+        let sig = SigV4::new().path("/photos/2015 summer/ä.jpg");
+        assert_eq!(sig.encoded_path(), "/photos/2015%20summer/%C3%A4.jpg")
+    }
+
+    #[test]
+    fn test_canonical_uri_dot_segments() {
+        let sig = SigV4::new().path("/example1/example2/../..");
+        assert_eq!(sig.canonical_uri(), "/");
+
+        let sig = SigV4::new().path("/./a/./b/../c");
+        assert_eq!(sig.canonical_uri(), "/a/c")
+    }
+
+    #[test]
+    fn test_canonical_uri_slashes() {
+        let sig = SigV4::new().path("//example//");
+        assert_eq!(sig.canonical_uri(), "/example/")
+    }
+
+    #[test]
+    fn test_canonical_uri_empty() {
+        let sig = SigV4::new().path("");
+        assert_eq!(sig.canonical_uri(), "/")
+    }
+
+    #[test]
+    fn test_from_url() {
+        let sig = SigV4::from_url("GET", "https://examplebucket.s3.amazonaws.com/photos/2015%20summer/a.jpg?list-type=2&prefix=a%20b").unwrap();
+        assert_eq!(sig.headers.get("host"), wrap_header!("examplebucket.s3.amazonaws.com"));
+        assert_eq!(sig.path, Some("/photos/2015 summer/a.jpg".to_string()));
+        assert_eq!(sig.canonical_query_string(), "list-type=2&prefix=a%20b")
+    }
+
+    #[test]
+    fn test_from_url_non_default_port() {
+        let sig = SigV4::from_url("GET", "http://localhost:4566/").unwrap();
+        assert_eq!(sig.headers.get("host"), wrap_header!("localhost:4566"));
+
+        let sig = SigV4::from_url("GET", "https://iam.amazonaws.com:443/").unwrap();
+        assert_eq!(sig.headers.get("host"), wrap_header!("iam.amazonaws.com"))
+    }
+
+    #[test]
+    fn test_sign_request() {
+        let cred = Credentials::new().path("fixtures/credentials.ini").profile("aws").load();
+        let mut req = http::Request::builder()
+            .method("POST")
+            .uri("https://iam.amazonaws.com/")
+            .header("Content-Type", "application/x-www-form-urlencoded; charset=utf-8")
+            .body(b"Action=ListUsers&Version=2010-05-08".to_vec())
+            .unwrap();
+
+        let mut sig = SigV4::from_request(&req).unwrap().credentials(cred).region("us-east-1").service("iam");
+        sig.date = strptime("20110909T233600Z", "%Y%m%dT%H%M%SZ").unwrap();
+        sig.sign_request(&mut req).unwrap();
+
+        assert_eq!(req.headers().get("host").unwrap(), "iam.amazonaws.com");
+        assert_eq!(req.headers().get("x-amz-date").unwrap(), "20110909T233600Z");
+        assert_eq!(req.headers().get("authorization").unwrap(), "AWS4-HMAC-SHA256 Credential=akid/20110909/us-east-1/iam/aws4_request, SignedHeaders=content-type;host;x-amz-date, Signature=ced6826de92d2bdeed8f846f0bf508e8559e98e4b0199114b84c54174deb456c")
+    }
+
+    #[test]
+    fn test_sign_request_with_host_header() {
+        let cred = Credentials::new().path("fixtures/credentials.ini").profile("aws").load();
+        let mut req = http::Request::builder()
+            .method("POST")
+            .uri("https://iam.amazonaws.com/")
+            .header("Host", "iam.amazonaws.com")
+            .header("Content-Type", "application/x-www-form-urlencoded; charset=utf-8")
+            .body(b"Action=ListUsers&Version=2010-05-08".to_vec())
+            .unwrap();
+
+        let mut sig = SigV4::from_request(&req).unwrap().credentials(cred).region("us-east-1").service("iam");
+        sig.date = strptime("20110909T233600Z", "%Y%m%dT%H%M%SZ").unwrap();
+        assert_eq!(sig.headers.get("host"), wrap_header!("iam.amazonaws.com"));
+        sig.sign_request(&mut req).unwrap();
+
+        assert_eq!(req.headers().get_all("host").iter().count(), 1);
+        assert!(req.headers().get("authorization").unwrap().to_str().unwrap().ends_with("Signature=ced6826de92d2bdeed8f846f0bf508e8559e98e4b0199114b84c54174deb456c"))
+    }
+
+    #[test]
+    fn test_sign_request_failures() {
+        let mut req = http::Request::builder().uri("https://iam.amazonaws.com/").body(Vec::new()).unwrap();
+        assert!(SigV4::from_request(&req).unwrap().region("us-east-1").service("iam").sign_request(&mut req).is_err());
+        assert!(req.headers().is_empty());
+
+        let cred = Credentials::new().path("fixtures/credentials.ini").profile("aws").load();
+        let sig = SigV4::from_request(&req).unwrap().credentials(cred).header(("bad header", "v"));
+        assert!(sig.sign_request(&mut req).is_err());
+        assert!(req.headers().is_empty())
+    }
+
+    #[test]
+    fn test_from_request_non_utf8_header() {
+        let req = http::Request::builder()
+            .uri("https://iam.amazonaws.com/")
+            .header("x-amz-meta-name", http::HeaderValue::from_bytes(b"caf\xe9").unwrap())
+            .body(Vec::new())
+            .unwrap();
+        assert!(SigV4::from_request(&req).is_err())
+    }
+
+    #[test]
+    fn test_from_url_keeps_escapes() {
+        let sig = SigV4::from_url("GET", "https://examplebucket.s3.amazonaws.com/a%2fb/c%20d?q=a+b&r=c%2Bd").unwrap().path_mode(PathMode::S3);
+        assert_eq!(sig.canonical_uri(), "/a%2Fb/c%20d");
+        assert_eq!(sig.encoded_path(), "/a%2Fb/c%20d");
+        assert_eq!(sig.canonical_query_string(), "q=a%20b&r=c%2Bd")
+    }
+
     #[test]
     fn test_signing_string() {
         let h = ("Content-Type", "application/x-www-form-urlencoded; charset=utf-8");
@@ -375,11 +879,15 @@ mod tests {
 
         let sig = SigV4 {
             credentials: None,
+            header_policy: HeaderPolicy::new(),
             headers: BTreeMap::new(),
             path: Some("/".to_string()),
+            path_mode: PathMode::Normalized,
             method: Some("POST".to_string()),
             query: None,
-            payload: Some("Action=ListUsers&Version=2010-05-08".to_string()),
+            raw_path: None,
+            payload: Some(b"Action=ListUsers&Version=2010-05-08".to_vec()),
+            payload_hash: None,
             date: strptime("20110909T233600Z", "%Y%m%dT%H%M%SZ").unwrap(),
             region: Some("us-east-1".to_string()),
             service: Some("iam".to_string()),
@@ -397,11 +905,15 @@ mod tests {
         let h2 = ("Host", "iam.amazonaws.com" );
 
         let sig = SigV4 {
+            header_policy: HeaderPolicy::new(),
             headers: BTreeMap::new(),
             path: Some("/".to_string()),
+            path_mode: PathMode::Normalized,
             method: Some("POST".to_string()),
             query: None,
-            payload: Some("Action=ListUsers&Version=2010-05-08".to_string()),
+            raw_path: None,
+            payload: Some(b"Action=ListUsers&Version=2010-05-08".to_vec()),
+            payload_hash: None,
             credentials: None,
             date: strptime("20110909T233600Z", "%Y%m%dT%H%M%SZ").unwrap(),
             region: None,
@@ -421,6 +933,66 @@ mod tests {
         assert_eq!(sig.signed_headers(), "content-type;test;x-amz-date")
     }
 
+    #[test]
+    fn test_signed_headers_skipped_first() {
+        let sig = SigV4::new().header(("Authorization", "none")).header(("b", "1")).header(("c", "2"));
+        assert_eq!(sig.signed_headers(), "b;c")
+    }
+
+    #[test]
+    fn test_signed_headers_skipped_middle() {
+        let sig = SigV4::new().header(("a", "1")).header(("Authorization", "none")).header(("b", "2"));
+        assert_eq!(sig.signed_headers(), "a;b")
+    }
+
+    #[test]
+    fn test_signed_headers_skipped_last() {
+        let sig = SigV4::new().header(("a", "1")).header(("b", "2")).header(("Via", "proxy"));
+        assert_eq!(sig.signed_headers(), "a;b")
+    }
+
+    #[test]
+    fn test_signed_headers_skipped_adjacent() {
+        let sig = SigV4::new().header(("a", "1")).header(("Connection", "close"))
+            .header(("Content-Length", "5")).header(("Host", "iam.amazonaws.com"))
+            .header(("Transfer-Encoding", "chunked")).header(("User-Agent", "aws-rs"));
+        assert_eq!(sig.signed_headers(), "a;host");
+        assert_eq!(sig.canonical_headers(), "a:1\nhost:iam.amazonaws.com\n")
+    }
+
+    #[test]
+    fn test_signed_headers_only_skipped() {
+        let sig = SigV4::new().header(("Authorization", "none")).header(("User-Agent", "aws-rs"));
+        assert_eq!(sig.signed_headers(), "")
+    }
+
+    #[test]
+    fn test_header_policy_defaults() {
+        let policy = HeaderPolicy::new();
+        assert!(policy.signs("Host"));
+        assert!(policy.signs("x-amz-security-token"));
+        assert!(policy.signs("content-type"));
+        assert!(policy.signs("x-custom"));
+        assert!(!policy.signs("X-Forwarded-For"));
+        assert!(!policy.signs("x-amzn-trace-id"));
+        assert!(!policy.signs("proxy-authorization"))
+    }
+
+    #[test]
+    fn test_header_policy_custom() {
+        let policy = HeaderPolicy::new().never("x-custom-*").always("user-agent");
+        let sig = SigV4::new().header_policy(policy)
+            .header(("X-Custom-Trace", "1")).header(("User-Agent", "aws-rs")).header(("x-amz-date", "2"));
+        assert_eq!(sig.signed_headers(), "user-agent;x-amz-date")
+    }
+
+    #[test]
+    fn test_header_policy_always_wins() {
+        let policy = HeaderPolicy::empty().never("x-amz-*").always("x-amz-date");
+        assert!(policy.signs("x-amz-date"));
+        assert!(!policy.signs("x-amz-target"))
+    }
+
     #[test]
     fn test_hashed_payload() {
         let sig = SigV4::new().
@@ -429,6 +1001,13 @@ mod tests {
         "b6359072c78d70ebee1e81adcbab4f01bf2c23245fa365ef83fe8f1f955085e2")
     }
 
+    #[test]
+    fn test_payload_hash_override() {
+        let sig = SigV4::new().payload("ignored").payload_hash("UNSIGNED-PAYLOAD");
+        assert_eq!(sig.hashed_payload(), "UNSIGNED-PAYLOAD");
+        assert_eq!(sig.headers.get("x-amz-content-sha256"), wrap_header!("UNSIGNED-PAYLOAD"))
+    }
+
     #[test]
     fn test_empty_payload() {
         let sig = SigV4::new();
@@ -463,11 +1042,15 @@ mod tests {
     #[test]
     fn test_specific_date() {
         let sig = SigV4 {
+            header_policy: HeaderPolicy::new(),
             headers: BTreeMap::new(),
             path: None,
+            path_mode: PathMode::Normalized,
             method: None,
             query: None,
+            raw_path: None,
             payload: None,
+            payload_hash: None,
             credentials: None,
             date: strptime("20110909T233600Z", "%Y%m%dT%H%M%SZ").unwrap(),
             region: None,
@@ -479,11 +1062,15 @@ mod tests {
     #[test]
     fn test_credential_scope() {
         let sig = SigV4 {
+            header_policy: HeaderPolicy::new(),
             headers: BTreeMap::new(),
             path: None,
+            path_mode: PathMode::Normalized,
             method: None,
             query: None,
+            raw_path: None,
             payload: None,
+            payload_hash: None,
             credentials: None,
             date: strptime("20110909T233600Z", "%Y%m%dT%H%M%SZ").unwrap(),
             region: Some("eu-west-1".to_string()),
@@ -504,11 +1091,15 @@ mod tests {
         let h2 = ("Host", "iam.amazonaws.com");
 
         let sig = SigV4 {
+            header_policy: HeaderPolicy::new(),
             headers: BTreeMap::new(),
             path: Some("/".to_string()),
+            path_mode: PathMode::Normalized,
             method: Some("POST".to_string()),
             query: None,
-            payload: Some("Action=ListUsers&Version=2010-05-08".to_string()),
+            raw_path: None,
+            payload: Some(b"Action=ListUsers&Version=2010-05-08".to_vec()),
+            payload_hash: None,
             credentials: None,
             date: strptime("20110909T233600Z", "%Y%m%dT%H%M%SZ").unwrap(),
             region: None,
@@ -533,18 +1124,22 @@ b6359072c78d70ebee1e81adcbab4f01bf2c23245fa365ef83fe8f1f955085e2")
 
         let sig = SigV4 {
             credentials: Some(cred),
+            header_policy: HeaderPolicy::new(),
             headers: BTreeMap::new(),
             path: Some("/".to_string()),
+            path_mode: PathMode::Normalized,
             method: Some("POST".to_string()),
             query: None,
-            payload: Some("Action=ListUsers&Version=2010-05-08".to_string()),
+            raw_path: None,
+            payload: Some(b"Action=ListUsers&Version=2010-05-08".to_vec()),
+            payload_hash: None,
             date: strptime("20110909T233600Z", "%Y%m%dT%H%M%SZ").unwrap(),
             region: Some("us-east-1".to_string()),
             service: Some("iam".to_string()),
         }.date();
 
         let target = [152, 241, 216, 137, 254, 196, 244, 66, 26, 220, 82, 43, 171, 12, 225, 248, 46, 105, 41, 194, 98, 237, 21, 229, 169, 76, 144, 239, 209, 227, 176, 231];
-        assert_eq!(sig.derived_signing_key().to_hex(), target.to_hex())
+        assert_eq!(sig.derived_signing_key().unwrap().to_hex(), target.to_hex())
     }
 
     #[test]
@@ -556,17 +1151,21 @@ b6359072c78d70ebee1e81adcbab4f01bf2c23245fa365ef83fe8f1f955085e2")
 
         let sig = SigV4 {
             credentials: Some(cred),
+            header_policy: HeaderPolicy::new(),
             headers: BTreeMap::new(),
             path: Some("/".to_string()),
+            path_mode: PathMode::Normalized,
             method: Some("POST".to_string()),
             query: None,
-            payload: Some("Action=ListUsers&Version=2010-05-08".to_string()),
+            raw_path: None,
+            payload: Some(b"Action=ListUsers&Version=2010-05-08".to_vec()),
+            payload_hash: None,
             date: strptime("20110909T233600Z", "%Y%m%dT%H%M%SZ").unwrap(),
             region: Some("us-east-1".to_string()),
             service: Some("iam".to_string()),
         }.date().header(h).header(h2);
 
-        assert_eq!(sig.signature(), "ced6826de92d2bdeed8f846f0bf508e8559e98e4b0199114b84c54174deb456c")
+        assert_eq!(sig.signature().unwrap(), "ced6826de92d2bdeed8f846f0bf508e8559e98e4b0199114b84c54174deb456c")
     }
 
     #[test]
@@ -578,15 +1177,19 @@ b6359072c78d70ebee1e81adcbab4f01bf2c23245fa365ef83fe8f1f955085e2")
 
         let sig = SigV4 {
             credentials: Some(cred),
+            header_policy: HeaderPolicy::new(),
             headers: BTreeMap::new(),
             path: Some("/".to_string()),
+            path_mode: PathMode::Normalized,
             method: Some("POST".to_string()),
             query: None,
-            payload: Some("Action=ListUsers&Version=2010-05-08".to_string()),
+            raw_path: None,
+            payload: Some(b"Action=ListUsers&Version=2010-05-08".to_vec()),
+            payload_hash: None,
             date: strptime("20110909T233600Z", "%Y%m%dT%H%M%SZ").unwrap(),
             region: Some("us-east-1".to_string()),
             service: Some("iam".to_string()),
-        }.date().header(h).header(h2).authorization();
+        }.date().header(h).header(h2).authorization().unwrap();
 
         assert_eq!(sig.headers.get("authorization"), wrap_header!("AWS4-HMAC-SHA256 Credential=akid/20110909/us-east-1/iam/aws4_request, SignedHeaders=content-type;host;x-amz-date, Signature=ced6826de92d2bdeed8f846f0bf508e8559e98e4b0199114b84c54174deb456c"))
     }
@@ -600,18 +1203,23 @@ b6359072c78d70ebee1e81adcbab4f01bf2c23245fa365ef83fe8f1f955085e2")
 
         let sig = SigV4 {
             credentials: Some(cred),
+            header_policy: HeaderPolicy::new(),
             headers: BTreeMap::new(),
             path: Some("/".to_string()),
+            path_mode: PathMode::Normalized,
             method: Some("POST".to_string()),
             query: None,
-            payload: Some("Action=ListUsers&Version=2010-05-08".to_string()),
+            raw_path: None,
+            payload: Some(b"Action=ListUsers&Version=2010-05-08".to_vec()),
+            payload_hash: None,
             date: strptime("20110909T233600Z", "%Y%m%dT%H%M%SZ").unwrap(),
             region: Some("us-east-1".to_string()),
             service: Some("iam".to_string()),
         }.header(h).header(h2);
 
-        let headers = sig.as_headers();
+        let headers = sig.as_headers().unwrap();
 
-        assert_eq!(headers.get::<Authorization>().unwrap().to_string(), "AWS4-HMAC-SHA256 Credential=akid/20110909/us-east-1/iam/aws4_request, SignedHeaders=content-type;host;x-amz-date, Signature=ced6826de92d2bdeed8f846f0bf508e8559e98e4b0199114b84c54174deb456c".to_string())
+        let auth = headers.iter().find(|h| h.0 == "authorization").unwrap();
+        assert_eq!(auth.1, "AWS4-HMAC-SHA256 Credential=akid/20110909/us-east-1/iam/aws4_request, SignedHeaders=content-type;host;x-amz-date, Signature=ced6826de92d2bdeed8f846f0bf508e8559e98e4b0199114b84c54174deb456c")
     }
 }
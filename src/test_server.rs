@@ -0,0 +1,158 @@
+use openssl::ssl::{SslAcceptor, SslFiletype, SslMethod};
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+// A local HTTP server for tests. It answers each connection with the next
+// scripted (status, body) pair and records the raw requests it received.
+pub struct TestServer {
+    pub url: String,
+    pub requests: Arc<Mutex<Vec<String>>>,
+}
+
+impl TestServer {
+    pub fn new(responses: Vec<(u16, &str)>) -> TestServer {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let recorded = requests.clone();
+        let responses: Vec<(u16, String)> = responses.into_iter().map(|(s, b)| (s, b.to_string())).collect();
+
+        thread::spawn(move || {
+            for (status, body) in responses {
+                let mut stream = match listener.accept() {
+                    Ok((stream, _)) => stream,
+                    Err(_) => return,
+                };
+                recorded.lock().unwrap().push(read_request(&mut stream));
+                let res = format!("HTTP/1.1 {} Scripted\r\nContent-Length: {}\r\nx-amzn-RequestId: req-{}\r\nConnection: close\r\n\r\n{}",
+                                  status, body.len(), status, body);
+                let _ = stream.write_all(res.as_bytes());
+            }
+        });
+
+        TestServer {
+            url,
+            requests,
+        }
+    }
+
+    // Acts as a proxy: accepts a CONNECT, then answers the tunnelled request.
+    // Both are recorded.
+    pub fn proxy(responses: Vec<(u16, &str)>) -> TestServer {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let recorded = requests.clone();
+        let responses: Vec<(u16, String)> = responses.into_iter().map(|(s, b)| (s, b.to_string())).collect();
+
+        thread::spawn(move || {
+            for (status, body) in responses {
+                let mut stream = match listener.accept() {
+                    Ok((stream, _)) => stream,
+                    Err(_) => return,
+                };
+                recorded.lock().unwrap().push(read_request(&mut stream));
+                let _ = stream.write_all(b"HTTP/1.1 200 Connection established\r\n\r\n");
+                recorded.lock().unwrap().push(read_request(&mut stream));
+                let res = format!("HTTP/1.1 {} Scripted\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                                  status, body.len(), body);
+                let _ = stream.write_all(res.as_bytes());
+            }
+        });
+
+        TestServer {
+            url,
+            requests,
+        }
+    }
+
+    // Answers over TLS with the fixture client certificate, which names
+    // neither the host nor the address, so only an insecure client accepts it.
+    pub fn tls(responses: Vec<(u16, &str)>) -> TestServer {
+        let mut acceptor = SslAcceptor::mozilla_intermediate(SslMethod::tls()).unwrap();
+        acceptor.set_certificate_file("fixtures/tls/client.pem", SslFiletype::PEM).unwrap();
+        acceptor.set_private_key_file("fixtures/tls/client.key", SslFiletype::PEM).unwrap();
+        let acceptor = acceptor.build();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("https://{}/", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let recorded = requests.clone();
+        let responses: Vec<(u16, String)> = responses.into_iter().map(|(s, b)| (s, b.to_string())).collect();
+
+        thread::spawn(move || {
+            let mut responses = responses.into_iter();
+            for stream in listener.incoming() {
+                let mut stream = match stream.map(|s| acceptor.accept(s)) {
+                    Ok(Ok(stream)) => stream,
+                    Ok(Err(_)) => continue,
+                    Err(_) => return,
+                };
+                let (status, body) = match responses.next() {
+                    Some(res) => res,
+                    None => return,
+                };
+                recorded.lock().unwrap().push(read_request(&mut stream));
+                let res = format!("HTTP/1.1 {} Scripted\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                                  status, body.len(), body);
+                let _ = stream.write_all(res.as_bytes());
+            }
+        });
+
+        TestServer {
+            url,
+            requests,
+        }
+    }
+
+    // Accepts connections but never answers them.
+    pub fn silent() -> TestServer {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            let mut held = Vec::new();
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => held.push(stream),
+                    Err(_) => return,
+                }
+            }
+        });
+
+        TestServer {
+            url,
+            requests: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    pub fn request_count(&self) -> usize {
+        self.requests.lock().unwrap().len()
+    }
+}
+
+fn read_request<R: Read>(stream: &mut R) -> String {
+    let mut data = Vec::new();
+    let mut buf = [0u8; 4096];
+    loop {
+        let n = match stream.read(&mut buf) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => n,
+        };
+        data.extend(buf[..n].iter().cloned());
+        let text = String::from_utf8_lossy(&data).into_owned();
+        if let Some(end) = text.find("\r\n\r\n") {
+            let length = text[..end].lines()
+                .filter_map(|l| {
+                    let l = l.to_lowercase();
+                    l.strip_prefix("content-length:").and_then(|v| v.trim().parse::<usize>().ok())
+                })
+                .next()
+                .unwrap_or(0);
+            if data.len() >= end + 4 + length {
+                break;
+            }
+        }
+    }
+    String::from_utf8_lossy(&data).into_owned()
+}
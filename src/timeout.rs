@@ -0,0 +1,101 @@
+use std::time::{Duration, Instant};
+
+use crate::error::TimeoutKind;
+
+// Connect and read timeouts apply to each connection; the attempt timeout to
+// a single request and response; the operation timeout to the whole call,
+// retries and backoff included. None means no limit.
+#[derive(Clone,Copy,Debug,Default,PartialEq)]
+pub struct Timeouts {
+    pub connect: Option<Duration>,
+    pub read: Option<Duration>,
+    pub attempt: Option<Duration>,
+    pub operation: Option<Duration>,
+}
+
+impl Timeouts {
+    pub fn new() -> Timeouts {
+        Timeouts::default()
+    }
+
+    pub fn connect(mut self, timeout: Duration) -> Timeouts {
+        self.connect = Some(timeout);
+        self
+    }
+
+    pub fn read(mut self, timeout: Duration) -> Timeouts {
+        self.read = Some(timeout);
+        self
+    }
+
+    pub fn attempt(mut self, timeout: Duration) -> Timeouts {
+        self.attempt = Some(timeout);
+        self
+    }
+
+    pub fn operation(mut self, timeout: Duration) -> Timeouts {
+        self.operation = Some(timeout);
+        self
+    }
+
+    // When an operation starting now has to be finished by.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.operation.map(|t| Instant::now() + t)
+    }
+
+    // How long the next attempt may run, and which timeout it will have hit
+    // if it runs out.
+    pub fn attempt_limit(&self, deadline: Option<Instant>) -> Option<(Duration, TimeoutKind)> {
+        let remaining = deadline.map(|d| {
+            let now = Instant::now();
+            if d > now { d - now } else { Duration::from_millis(0) }
+        });
+        match (self.attempt, remaining) {
+            (Some(attempt), Some(remaining)) if remaining < attempt => Some((remaining, TimeoutKind::Operation)),
+            (Some(attempt), _) => Some((attempt, TimeoutKind::Attempt)),
+            (None, Some(remaining)) => Some((remaining, TimeoutKind::Operation)),
+            (None, None) => None,
+        }
+    }
+}
+
+// Whether waiting `delay` would take us past the deadline.
+pub fn exceeds(deadline: Option<Instant>, delay: Duration) -> bool {
+    match deadline {
+        Some(d) => Instant::now() + delay >= d,
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Timeouts, exceeds};
+    use crate::error::TimeoutKind;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_attempt_limit() {
+        let secs = Duration::from_secs;
+        assert_eq!(Timeouts::new().attempt_limit(None), None);
+        assert_eq!(Timeouts::new().attempt(secs(5)).attempt_limit(None), Some((secs(5), TimeoutKind::Attempt)));
+
+        let deadline = Some(Instant::now() + secs(60));
+        assert_eq!(Timeouts::new().attempt(secs(5)).attempt_limit(deadline), Some((secs(5), TimeoutKind::Attempt)));
+        match Timeouts::new().attempt_limit(deadline) {
+            Some((limit, TimeoutKind::Operation)) => assert!(limit <= secs(60) && limit > secs(59)),
+            other => panic!("unexpected limit {:?}", other),
+        }
+
+        let passed = Some(Instant::now());
+        assert_eq!(Timeouts::new().attempt(secs(5)).attempt_limit(passed),
+                   Some((Duration::from_millis(0), TimeoutKind::Operation)))
+    }
+
+    #[test]
+    fn test_exceeds() {
+        let deadline = Some(Instant::now() + Duration::from_secs(10));
+        assert!(!exceeds(deadline, Duration::from_secs(1)));
+        assert!(exceeds(deadline, Duration::from_secs(20)));
+        assert!(!exceeds(None, Duration::from_secs(3600)))
+    }
+}
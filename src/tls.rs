@@ -0,0 +1,207 @@
+use ini::Ini;
+use openssl::error::ErrorStack;
+use openssl::ssl::{SslConnector, SslFiletype, SslMethod, SslVerifyMode, SslVersion};
+use openssl::x509::store::X509StoreBuilder;
+use openssl::x509::X509;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::pin::Pin;
+use tokio::net::TcpStream;
+use tokio_openssl::SslStream;
+
+use crate::env::{self, Getenv};
+use crate::error::{Error, Result};
+
+#[derive(Clone,Copy,Debug,PartialEq,PartialOrd)]
+pub enum TlsVersion {
+    Tls10,
+    Tls11,
+    Tls12,
+}
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct TlsConfig {
+    ca_bundle: Option<String>,
+    client_certificate: Option<(String, String)>,
+    min_version: TlsVersion,
+    insecure: bool,
+}
+
+impl Default for TlsConfig {
+    fn default() -> TlsConfig {
+        TlsConfig::new()
+    }
+}
+
+impl TlsConfig {
+    // The system's trusted roots and TLS 1.2 or later.
+    pub fn new() -> TlsConfig {
+        TlsConfig {
+            ca_bundle: None,
+            client_certificate: None,
+            min_version: TlsVersion::Tls12,
+            insecure: false,
+        }
+    }
+
+    // AWS_CA_BUNDLE, or else ca_bundle from the current profile in the
+    // config file, as the CLI does.
+    pub fn from_env() -> TlsConfig {
+        TlsConfig::from_env_with(&env::process)
+    }
+
+    pub fn from_env_with(getenv: Getenv) -> TlsConfig {
+        let lookup = |name: &str| env::lookup(getenv, name);
+        let config = TlsConfig::new();
+        if let Some(bundle) = lookup("AWS_CA_BUNDLE") {
+            return config.ca_bundle(&bundle);
+        }
+
+        let path = env::aws_file(getenv, "AWS_CONFIG_FILE", "config");
+        let profile = env::profile(getenv);
+        let section = if profile == "default" { profile } else { format!("profile {}", profile) };
+
+        let bundle = Ini::load_from_file(&path).ok().and_then(|conf| {
+            conf.section(Some(&section[..])).and_then(|s| s.get("ca_bundle")).map(|v| v.to_string())
+        });
+        match bundle {
+            Some(bundle) => config.ca_bundle(&bundle),
+            None => config,
+        }
+    }
+
+    // PEM certificates to trust instead of the system's roots.
+    pub fn ca_bundle(mut self, path: &str) -> TlsConfig {
+        self.ca_bundle = Some(path.to_string());
+        self
+    }
+
+    // PEM certificate and private key to present to the server.
+    pub fn client_certificate(mut self, cert: &str, key: &str) -> TlsConfig {
+        self.client_certificate = Some((cert.to_string(), key.to_string()));
+        self
+    }
+
+    pub fn min_version(mut self, version: TlsVersion) -> TlsConfig {
+        self.min_version = version;
+        self
+    }
+
+    // Accepts any certificate for any host. Only for talking to local test
+    // services with self-signed certificates; never use it against AWS.
+    pub fn insecure(mut self) -> TlsConfig {
+        self.insecure = true;
+        self
+    }
+
+    pub fn is_insecure(&self) -> bool {
+        self.insecure
+    }
+
+    pub fn connector(&self) -> Result<TlsConnector> {
+        let invalid = |what: &str, e: &dyn fmt::Display| Error::Config(format!("tls {}: {}", what, e));
+
+        let mut builder = SslConnector::builder(SslMethod::tls_client()).map_err(|e| invalid("context", &e))?;
+        let min_version = match self.min_version {
+            TlsVersion::Tls10 => SslVersion::TLS1,
+            TlsVersion::Tls11 => SslVersion::TLS1_1,
+            TlsVersion::Tls12 => SslVersion::TLS1_2,
+        };
+        builder.set_min_proto_version(Some(min_version)).map_err(|e| invalid("version", &e))?;
+
+        // The builder starts out trusting the system's roots.
+        if let Some(ref path) = self.ca_bundle {
+            let pem = fs::read(path).map_err(|e| invalid(path, &e))?;
+            let mut store = X509StoreBuilder::new().map_err(|e| invalid(path, &e))?;
+            for cert in X509::stack_from_pem(&pem).map_err(|e| invalid(path, &e))? {
+                store.add_cert(cert).map_err(|e| invalid(path, &e))?;
+            }
+            builder.set_cert_store(store.build());
+        }
+        if let Some((ref cert, ref key)) = self.client_certificate {
+            builder.set_certificate_file(cert, SslFiletype::PEM).map_err(|e| invalid(cert, &e))?;
+            builder.set_private_key_file(key, SslFiletype::PEM).map_err(|e| invalid(key, &e))?;
+            builder.check_private_key().map_err(|e| invalid(key, &e))?;
+        }
+        if self.insecure {
+            warn!("TLS certificate verification is disabled");
+            builder.set_verify(SslVerifyMode::NONE);
+        }
+
+        Ok(TlsConnector {
+            connector: builder.build(),
+            insecure: self.insecure,
+        })
+    }
+}
+
+// Always checks the certificate against the host, unless insecure.
+#[derive(Clone)]
+pub struct TlsConnector {
+    connector: SslConnector,
+    insecure: bool,
+}
+
+impl TlsConnector {
+    pub async fn connect(&self, host: &str, stream: TcpStream) -> io::Result<SslStream<TcpStream>> {
+        let tls_error = |e: ErrorStack| io::Error::other(e);
+        let ssl = self.connector.configure().map_err(tls_error)?
+            .verify_hostname(!self.insecure)
+            .into_ssl(host).map_err(tls_error)?;
+        let mut stream = SslStream::new(ssl, stream).map_err(tls_error)?;
+        Pin::new(&mut stream).connect().await.map_err(|e| {
+            e.into_io_error().unwrap_or_else(io::Error::other)
+        })?;
+        Ok(stream)
+    }
+}
+
+impl fmt::Debug for TlsConnector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "TlsConnector {{ insecure: {} }}", self.insecure)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TlsConfig, TlsVersion};
+    use crate::env::fixed;
+    use crate::error::Error;
+
+    fn env(vars: &'static [(&'static str, &'static str)]) -> TlsConfig {
+        TlsConfig::from_env_with(&fixed(vars))
+    }
+
+    #[test]
+    fn test_ca_bundle_from_env() {
+        assert_eq!(env(&[("AWS_CA_BUNDLE", "/etc/ca.pem"), ("AWS_CONFIG_FILE", "fixtures/config.ini")]),
+                   TlsConfig::new().ca_bundle("/etc/ca.pem"));
+        assert_eq!(env(&[("AWS_CONFIG_FILE", "fixtures/config.ini")]),
+                   TlsConfig::new().ca_bundle("fixtures/tls/ca.pem"));
+        assert_eq!(env(&[("AWS_CONFIG_FILE", "fixtures/config.ini"), ("AWS_PROFILE", "dev")]),
+                   TlsConfig::new().ca_bundle("/etc/pki/dev-ca.pem"));
+        assert_eq!(env(&[("AWS_CONFIG_FILE", "fixtures/missing.ini")]), TlsConfig::new())
+    }
+
+    #[test]
+    fn test_connector() {
+        assert!(TlsConfig::new().connector().is_ok());
+        assert!(TlsConfig::new().ca_bundle("fixtures/tls/ca.pem")
+                .client_certificate("fixtures/tls/client.pem", "fixtures/tls/client.key")
+                .min_version(TlsVersion::Tls11)
+                .connector().is_ok());
+        assert!(!TlsConfig::new().connector().unwrap().insecure);
+        assert!(TlsConfig::new().insecure().connector().unwrap().insecure)
+    }
+
+    #[test]
+    fn test_connector_errors() {
+        match TlsConfig::new().ca_bundle("fixtures/tls/missing.pem").connector() {
+            Err(Error::Config(msg)) => assert!(msg.contains("fixtures/tls/missing.pem")),
+            other => panic!("expected a config error, got {:?}", other),
+        }
+        // The key doesn't belong to the CA certificate.
+        assert!(TlsConfig::new().client_certificate("fixtures/tls/ca.pem", "fixtures/tls/client.key").connector().is_err())
+    }
+}
@@ -0,0 +1,115 @@
+use std::collections::VecDeque;
+use std::future;
+use std::sync::{Arc, Mutex};
+
+use crate::error::{Error, Result};
+use crate::response::HttpResponse;
+use crate::runtime::BoxFuture;
+use crate::transport::{HttpRequest, HttpTransport};
+
+// Records every request and answers with canned responses, in order. Clones
+// share the script and the recording, so a test can keep one and give the
+// other to the client.
+#[derive(Clone,Debug)]
+pub struct MockTransport {
+    responses: Arc<Mutex<VecDeque<Result<HttpResponse>>>>,
+    requests: Arc<Mutex<Vec<HttpRequest>>>,
+}
+
+impl Default for MockTransport {
+    fn default() -> MockTransport {
+        MockTransport::new()
+    }
+}
+
+impl MockTransport {
+    pub fn new() -> MockTransport {
+        MockTransport {
+            responses: Arc::new(Mutex::new(VecDeque::new())),
+            requests: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    pub fn respond(self, status: u16, body: &str) -> MockTransport {
+        self.respond_with(HttpResponse {
+            status,
+            headers: vec!(("x-amzn-RequestId".to_string(), format!("mock-{}", status))),
+            body: body.as_bytes().to_vec(),
+        })
+    }
+
+    pub fn respond_with(self, res: HttpResponse) -> MockTransport {
+        self.responses.lock().unwrap().push_back(Ok(res));
+        self
+    }
+
+    // Fails the next request with `err` instead, e.g. to simulate a timeout.
+    pub fn fail(self, err: Error) -> MockTransport {
+        self.responses.lock().unwrap().push_back(Err(err));
+        self
+    }
+
+    pub fn requests(&self) -> Vec<HttpRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    pub fn request_count(&self) -> usize {
+        self.requests.lock().unwrap().len()
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.responses.lock().unwrap().len()
+    }
+}
+
+impl HttpTransport for MockTransport {
+    fn send(&self, req: HttpRequest) -> BoxFuture<Result<HttpResponse>> {
+        let res = match self.responses.lock().unwrap().pop_front() {
+            Some(res) => res,
+            None => Err(Error::NoResponse(format!("mock transport has nothing scripted for {} {}", req.method, req.url))),
+        };
+        self.requests.lock().unwrap().push(req);
+        Box::pin(future::ready(res))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MockTransport;
+    use crate::error::{Error, TimeoutKind};
+    use crate::runtime::block_on;
+    use crate::transport::{HttpRequest, HttpTransport};
+
+    fn request() -> HttpRequest {
+        HttpRequest {
+            method: "GET".to_string(),
+            url: "https://example.com/".to_string(),
+            headers: vec!(("Host".to_string(), "example.com".to_string())),
+            body: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_scripted_responses() {
+        let mock = MockTransport::new().respond(500, "").respond(200, "ok");
+        let transport = mock.clone();
+        assert_eq!(block_on(transport.send(request())).unwrap().status, 500);
+        assert_eq!(block_on(transport.send(request())).unwrap().body, b"ok".to_vec());
+        match block_on(transport.send(request())) {
+            Err(Error::NoResponse(_)) => {},
+            other => panic!("expected no response, got {:?}", other),
+        }
+        assert_eq!(mock.request_count(), 3);
+        assert_eq!(mock.requests()[0].header("host"), Some("example.com"))
+    }
+
+    #[test]
+    fn test_failure() {
+        let mock = MockTransport::new().fail(Error::Timeout(TimeoutKind::Read)).respond(200, "");
+        match block_on(mock.send(request())) {
+            Err(Error::Timeout(TimeoutKind::Read)) => {},
+            other => panic!("expected a timeout, got {:?}", other),
+        }
+        assert_eq!(mock.remaining(), 1)
+    }
+}
@@ -0,0 +1,33 @@
+use std::fmt;
+
+use crate::error::Result;
+use crate::response::HttpResponse;
+use crate::runtime::BoxFuture;
+
+pub mod mock;
+pub mod vcr;
+
+// A signed request, exactly as it will go on the wire.
+#[derive(Clone,Debug,PartialEq)]
+pub struct HttpRequest {
+    pub method: String,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl HttpRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|&(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| &v[..])
+    }
+}
+
+// Whatever carries requests for an ApiClient. Signing, retries and rate
+// limiting all happen before a transport sees the request, so a transport
+// sends exactly once and returns whatever came back, whatever its status.
+// Dropping the future abandons the request.
+pub trait HttpTransport: fmt::Debug + Send + Sync {
+    fn send(&self, req: HttpRequest) -> BoxFuture<Result<HttpResponse>>;
+}
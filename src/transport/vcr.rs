@@ -0,0 +1,456 @@
+use http::uri::Uri;
+use serialize::base64::{FromBase64, ToBase64, STANDARD};
+use serialize::json::{Json, ToJson};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::future;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::str;
+use std::sync::{Arc, Mutex};
+
+use crate::error::{Error, Result};
+use crate::response::HttpResponse;
+use crate::runtime::BoxFuture;
+use crate::transport::{HttpRequest, HttpTransport};
+
+const SCRUBBED: &str = "<scrubbed>";
+
+// Never written to a fixture, whether sent or received.
+const SCRUBBED_HEADERS: &[&str] = &[
+    "authorization", "proxy-authorization", "set-cookie", "x-amz-security-token",
+];
+
+// Credentials in response bodies, such as STS's AssumeRole or SSO's
+// GetRoleCredentials. JSON keys are matched ignoring case.
+const SCRUBBED_FIELDS: &[&str] = &["SecretAccessKey", "SecretKey", "SessionToken", "Token"];
+
+// Presigned URL parameters: secrets, or signature inputs that change on every
+// request and so would stop a replay from matching.
+const SCRUBBED_PARAMS: &[&str] = &[
+    "X-Amz-Credential", "X-Amz-Date", "X-Amz-Security-Token", "X-Amz-Signature",
+];
+
+// A request as stored in a fixture: scrubbed, with the query in canonical
+// order. Replays match on method, path, query and body.
+#[derive(Clone,Debug,PartialEq)]
+pub struct RecordedRequest {
+    pub method: String,
+    pub path: String,
+    pub query: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl RecordedRequest {
+    pub fn new(req: &HttpRequest) -> RecordedRequest {
+        let (path, query) = match req.url.parse::<Uri>() {
+            Ok(uri) => (uri.path().to_string(), uri.query().unwrap_or("").to_string()),
+            Err(_) => (req.url.clone(), String::new()),
+        };
+        RecordedRequest {
+            method: req.method.to_uppercase(),
+            path,
+            query: canonical_query(&query),
+            headers: scrub_headers(&req.headers),
+            body: req.body.clone(),
+        }
+    }
+
+    pub fn matches(&self, other: &RecordedRequest) -> bool {
+        self.method == other.method && self.path == other.path &&
+            self.query == other.query && self.body == other.body
+    }
+}
+
+fn scrub_headers(headers: &[(String, String)]) -> Vec<(String, String)> {
+    headers.iter().map(|(k, v)| {
+        let scrub = SCRUBBED_HEADERS.iter().any(|h| k.eq_ignore_ascii_case(h));
+        (k.clone(), if scrub { SCRUBBED.to_string() } else { v.clone() })
+    }).collect()
+}
+
+// A response as stored in a fixture, without headers or body fields that
+// carry credentials.
+pub fn scrub_response(res: &HttpResponse) -> HttpResponse {
+    HttpResponse {
+        status: res.status,
+        headers: scrub_headers(&res.headers),
+        body: scrub_body(&res.body),
+    }
+}
+
+fn scrub_body(body: &[u8]) -> Vec<u8> {
+    let text = match str::from_utf8(body) {
+        Ok(text) => text,
+        Err(_) => return body.to_vec(),
+    };
+    if text.trim_start().starts_with('{') {
+        match Json::from_str(text) {
+            Ok(mut json) => if scrub_json(&mut json) { json.to_string().into_bytes() } else { body.to_vec() },
+            Err(_) => body.to_vec(),
+        }
+    } else {
+        scrub_xml(text).into_bytes()
+    }
+}
+
+fn scrub_json(json: &mut Json) -> bool {
+    match *json {
+        Json::Object(ref mut obj) => {
+            let mut scrubbed = false;
+            for (key, value) in obj.iter_mut() {
+                if value.is_string() && SCRUBBED_FIELDS.iter().any(|f| key.eq_ignore_ascii_case(f)) {
+                    *value = SCRUBBED.to_json();
+                    scrubbed = true;
+                } else {
+                    scrubbed |= scrub_json(value);
+                }
+            }
+            scrubbed
+        },
+        Json::Array(ref mut list) => list.iter_mut().fold(false, |scrubbed, v| scrub_json(v) | scrubbed),
+        _ => false,
+    }
+}
+
+// The marker is escaped so the element still parses on replay.
+fn scrub_xml(text: &str) -> String {
+    let marker = "&lt;scrubbed&gt;";
+    let mut text = text.to_string();
+    for field in SCRUBBED_FIELDS.iter() {
+        let (open, close) = (format!("<{}>", field), format!("</{}>", field));
+        let mut from = 0;
+        while let Some(start) = text[from..].find(&open).map(|i| from + i + open.len()) {
+            match text[start..].find(&close) {
+                Some(len) => {
+                    text.replace_range(start..start + len, marker);
+                    from = start + marker.len() + close.len();
+                },
+                None => break,
+            }
+        }
+    }
+    text
+}
+
+pub fn canonical_query(query: &str) -> String {
+    let mut params: Vec<String> = query.split('&')
+        .filter(|p| !p.is_empty())
+        .map(|p| {
+            let key = p.split('=').next().unwrap_or("");
+            if SCRUBBED_PARAMS.iter().any(|s| s.eq_ignore_ascii_case(key)) { format!("{}={}", key, SCRUBBED) } else { p.to_string() }
+        })
+        .collect();
+    params.sort();
+    params.join("&")
+}
+
+#[derive(Clone,Debug)]
+pub struct Interaction {
+    pub request: RecordedRequest,
+    pub response: HttpResponse,
+}
+
+fn headers_json(headers: &[(String, String)]) -> Json {
+    Json::Array(headers.iter().map(|(k, v)| Json::Array(vec!(k.to_json(), v.to_json()))).collect())
+}
+
+// Bodies are stored as text where possible, so fixtures stay readable.
+fn body_json(body: &[u8], obj: &mut BTreeMap<String, Json>) {
+    match str::from_utf8(body) {
+        Ok(text) => {
+            obj.insert("body".to_string(), text.to_json());
+        },
+        Err(_) => {
+            obj.insert("body".to_string(), body.to_base64(STANDARD).to_json());
+            obj.insert("body_encoding".to_string(), "base64".to_json());
+        },
+    }
+}
+
+impl ToJson for Interaction {
+    fn to_json(&self) -> Json {
+        let mut req = BTreeMap::new();
+        req.insert("method".to_string(), self.request.method.to_json());
+        req.insert("path".to_string(), self.request.path.to_json());
+        req.insert("query".to_string(), self.request.query.to_json());
+        req.insert("headers".to_string(), headers_json(&self.request.headers));
+        body_json(&self.request.body, &mut req);
+
+        let mut res = BTreeMap::new();
+        res.insert("status".to_string(), (self.response.status as u64).to_json());
+        res.insert("headers".to_string(), headers_json(&self.response.headers));
+        body_json(&self.response.body, &mut res);
+
+        let mut obj = BTreeMap::new();
+        obj.insert("request".to_string(), Json::Object(req));
+        obj.insert("response".to_string(), Json::Object(res));
+        Json::Object(obj)
+    }
+}
+
+fn invalid(path: &Path, reason: &str) -> Error {
+    Error::Parse(format!("fixture {}: {}", path.display(), reason))
+}
+
+impl Interaction {
+    fn from_json(json: &Json, path: &Path) -> Result<Interaction> {
+        let string = |obj: &Json, key: &str| -> Result<String> {
+            obj.find(key).and_then(|v| v.as_string()).map(|v| v.to_string())
+                .ok_or_else(|| invalid(path, &format!("missing {}", key)))
+        };
+        let headers = |obj: &Json| -> Result<Vec<(String, String)>> {
+            let list = obj.find("headers").and_then(|v| v.as_array()).ok_or_else(|| invalid(path, "missing headers"))?;
+            let mut headers = Vec::new();
+            for pair in list.iter() {
+                match pair.as_array().map(|p| (p.first().and_then(|v| v.as_string()), p.get(1).and_then(|v| v.as_string()))) {
+                    Some((Some(k), Some(v))) => headers.push((k.to_string(), v.to_string())),
+                    _ => return Err(invalid(path, "headers must be [name, value] pairs")),
+                }
+            }
+            Ok(headers)
+        };
+        let body = |obj: &Json| -> Result<Vec<u8>> {
+            let text = string(obj, "body")?;
+            match obj.find("body_encoding").and_then(|v| v.as_string()) {
+                Some("base64") => text.from_base64().map_err(|e| invalid(path, &e.to_string())),
+                _ => Ok(text.into_bytes()),
+            }
+        };
+
+        let req = json.find("request").ok_or_else(|| invalid(path, "missing request"))?;
+        let res = json.find("response").ok_or_else(|| invalid(path, "missing response"))?;
+        let status = res.find("status").and_then(|v| v.as_u64()).ok_or_else(|| invalid(path, "missing status"))?;
+        Ok(Interaction {
+            request: RecordedRequest {
+                method: string(req, "method")?,
+                path: string(req, "path")?,
+                query: string(req, "query")?,
+                headers: headers(req)?,
+                body: body(req)?,
+            },
+            response: HttpResponse {
+                status: status as u16,
+                headers: headers(res)?,
+                body: body(res)?,
+            },
+        })
+    }
+}
+
+pub fn load(path: &Path) -> Result<Vec<Interaction>> {
+    let mut text = String::new();
+    File::open(path)?.read_to_string(&mut text)?;
+    let json = Json::from_str(&text).map_err(|e| invalid(path, &e.to_string()))?;
+    let list = json.find("interactions").and_then(|v| v.as_array()).ok_or_else(|| invalid(path, "missing interactions"))?;
+    list.iter().map(|i| Interaction::from_json(i, path)).collect()
+}
+
+pub fn save(path: &Path, interactions: &[Interaction]) -> Result<()> {
+    let mut obj = BTreeMap::new();
+    obj.insert("interactions".to_string(), Json::Array(interactions.iter().map(|i| i.to_json()).collect()));
+    let mut file = File::create(path)?;
+    writeln!(file, "{}", Json::Object(obj).pretty())?;
+    Ok(())
+}
+
+#[derive(Debug)]
+enum Mode {
+    // The lock is held while the fixture is written, so writes happen one at
+    // a time and the last always has every interaction.
+    Record(Arc<dyn HttpTransport>, Mutex<()>),
+    Replay(Mutex<Vec<bool>>),
+}
+
+// Records real traffic to a fixture under fixtures/ and replays it later.
+// Recording passes every request on to another transport and rewrites the
+// fixture as it goes, scrubbing credentials from both sides; replaying
+// answers each request with the first unused recorded response that matches
+// it, so retried requests get their responses in the order they were
+// recorded.
+#[derive(Clone,Debug)]
+pub struct VcrTransport {
+    path: PathBuf,
+    interactions: Arc<Mutex<Vec<Interaction>>>,
+    mode: Arc<Mode>,
+}
+
+impl VcrTransport {
+    pub fn record<P: AsRef<Path>, T: HttpTransport + 'static>(path: P, transport: T) -> VcrTransport {
+        VcrTransport {
+            path: path.as_ref().to_path_buf(),
+            interactions: Arc::new(Mutex::new(Vec::new())),
+            mode: Arc::new(Mode::Record(Arc::new(transport), Mutex::new(()))),
+        }
+    }
+
+    pub fn replay<P: AsRef<Path>>(path: P) -> Result<VcrTransport> {
+        let interactions = load(path.as_ref())?;
+        let used = vec!(false; interactions.len());
+        Ok(VcrTransport {
+            path: path.as_ref().to_path_buf(),
+            interactions: Arc::new(Mutex::new(interactions)),
+            mode: Arc::new(Mode::Replay(Mutex::new(used))),
+        })
+    }
+
+    pub fn interactions(&self) -> Vec<Interaction> {
+        self.interactions.lock().unwrap().clone()
+    }
+}
+
+impl HttpTransport for VcrTransport {
+    fn send(&self, req: HttpRequest) -> BoxFuture<Result<HttpResponse>> {
+        let recorded = RecordedRequest::new(&req);
+        match *self.mode {
+            Mode::Record(ref transport, _) => {
+                let this = self.clone();
+                let sent = transport.send(req);
+                Box::pin(async move {
+                    let res = sent.await?;
+                    let interaction = Interaction { request: recorded, response: scrub_response(&res) };
+                    this.interactions.lock().unwrap().push(interaction);
+                    this.save()?;
+                    Ok(res)
+                })
+            },
+            Mode::Replay(ref used) => Box::pin(future::ready(self.replay_response(used, &recorded))),
+        }
+    }
+}
+
+impl VcrTransport {
+    // Writes outside the interactions lock, so replies and interactions()
+    // don't wait on the file.
+    fn save(&self) -> Result<()> {
+        if let Mode::Record(_, ref writing) = *self.mode {
+            let _writing = writing.lock().unwrap();
+            let interactions = self.interactions();
+            save(&self.path, &interactions)?;
+        }
+        Ok(())
+    }
+
+    fn replay_response(&self, used: &Mutex<Vec<bool>>, recorded: &RecordedRequest) -> Result<HttpResponse> {
+        let interactions = self.interactions.lock().unwrap();
+        let mut used = used.lock().unwrap();
+        for (i, interaction) in interactions.iter().enumerate() {
+            if !used[i] && interaction.request.matches(recorded) {
+                used[i] = true;
+                return Ok(interaction.response.clone());
+            }
+        }
+        Err(Error::NoResponse(format!("{}: nothing recorded for {} {}?{}",
+                                      self.path.display(), recorded.method, recorded.path, recorded.query)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{VcrTransport, RecordedRequest, canonical_query, load, scrub_response};
+    use crate::error::Error;
+    use crate::response::HttpResponse;
+    use std::env;
+    use std::fs;
+    use crate::runtime::block_on;
+    use crate::transport::{HttpRequest, HttpTransport};
+    use crate::transport::mock::MockTransport;
+
+    fn request(url: &str, body: &str) -> HttpRequest {
+        HttpRequest {
+            method: "POST".to_string(),
+            url: url.to_string(),
+            headers: vec!(("Host".to_string(), "sts.eu-west-1.amazonaws.com".to_string()),
+                          ("Authorization".to_string(), "AWS4-HMAC-SHA256 Credential=akid/20150101/eu-west-1/sts/aws4_request, SignedHeaders=host, Signature=abc".to_string()),
+                          ("X-Amz-Security-Token".to_string(), "token".to_string())),
+            body: body.as_bytes().to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_canonical_query() {
+        assert_eq!(canonical_query("b=2&a=1&X-Amz-Signature=abcdef"), "X-Amz-Signature=<scrubbed>&a=1&b=2");
+        assert_eq!(canonical_query("x-amz-signature=abcdef&x-amz-date=1"), "x-amz-date=<scrubbed>&x-amz-signature=<scrubbed>");
+        assert_eq!(canonical_query(""), "")
+    }
+
+    #[test]
+    fn test_scrubs_credentials() {
+        let recorded = RecordedRequest::new(&request("https://sts.eu-west-1.amazonaws.com/?X-Amz-Credential=akid", ""));
+        assert_eq!(recorded.query, "X-Amz-Credential=<scrubbed>");
+        assert_eq!(recorded.headers[1].1, "<scrubbed>");
+        assert_eq!(recorded.headers[2].1, "<scrubbed>")
+    }
+
+    #[test]
+    fn test_scrubs_json_credentials() {
+        let body = r#"{"roleCredentials":{"accessKeyId":"ASIA","secretAccessKey":"secret","sessionToken":"token","expiration":1}}"#;
+        let res = HttpResponse { status: 200, headers: vec!(("Set-Cookie".to_string(), "a=b".to_string())), body: body.as_bytes().to_vec() };
+        let scrubbed = scrub_response(&res);
+        let text = String::from_utf8(scrubbed.body).unwrap();
+        assert!(text.contains(r#""accessKeyId":"ASIA""#));
+        assert!(!text.contains("secret\"") && !text.contains("\"token\""));
+        assert_eq!(scrubbed.headers[0].1, "<scrubbed>")
+    }
+
+    #[test]
+    fn test_records_assume_role_without_secrets() {
+        let mut path = env::temp_dir();
+        path.push(format!("aws-rs-vcr-assume-role-{}.json", ::std::process::id()));
+        let body = "<AssumeRoleResponse><AssumeRoleResult><Credentials>\
+                    <AccessKeyId>ASIAEXAMPLE</AccessKeyId>\
+                    <SecretAccessKey>wJalrXUtnFEMI</SecretAccessKey>\
+                    <SessionToken>FwoGZXIvYXdzEBY</SessionToken>\
+                    <Expiration>2019-11-09T13:34:41Z</Expiration>\
+                    </Credentials></AssumeRoleResult></AssumeRoleResponse>";
+        let recorder = VcrTransport::record(&path, MockTransport::new().respond(200, body));
+        let req = request("https://sts.eu-west-1.amazonaws.com/", "Action=AssumeRole");
+        assert_eq!(block_on(recorder.send(req.clone())).unwrap().body, body.as_bytes().to_vec());
+
+        let saved = fs::read_to_string(&path).unwrap();
+        assert!(!saved.contains("wJalrXUtnFEMI"));
+        assert!(!saved.contains("FwoGZXIvYXdzEBY"));
+        assert!(saved.contains("ASIAEXAMPLE"));
+
+        let replayed = String::from_utf8(block_on(VcrTransport::replay(&path).unwrap().send(req)).unwrap().body).unwrap();
+        assert!(replayed.contains("<SecretAccessKey>&lt;scrubbed&gt;</SecretAccessKey>"));
+        fs::remove_file(&path).unwrap()
+    }
+
+    #[test]
+    fn test_record_then_replay() {
+        let mut path = env::temp_dir();
+        path.push(format!("aws-rs-vcr-{}.json", ::std::process::id()));
+        let mock = MockTransport::new().respond(500, "oops").respond(200, "first").respond(200, "second");
+
+        let recorder = VcrTransport::record(&path, mock);
+        let one = request("https://sts.eu-west-1.amazonaws.com/?b=2&a=1", "Action=One");
+        let two = request("https://sts.eu-west-1.amazonaws.com/", "Action=Two");
+        assert_eq!(block_on(recorder.send(one.clone())).unwrap().status, 500);
+        assert_eq!(block_on(recorder.send(one.clone())).unwrap().body, b"first".to_vec());
+        assert_eq!(block_on(recorder.send(two.clone())).unwrap().body, b"second".to_vec());
+
+        let saved = fs::read_to_string(&path).unwrap();
+        assert!(!saved.contains("Signature=abc"));
+        assert!(!saved.contains("\"token\""));
+
+        let player = VcrTransport::replay(&path).unwrap();
+        assert_eq!(block_on(player.send(two.clone())).unwrap().body, b"second".to_vec());
+        assert_eq!(block_on(player.send(request("https://sts.eu-west-1.amazonaws.com/?a=1&b=2", "Action=One"))).unwrap().status, 500);
+        assert_eq!(block_on(player.send(one.clone())).unwrap().body, b"first".to_vec());
+        match block_on(player.send(one.clone())) {
+            Err(Error::NoResponse(_)) => {},
+            other => panic!("expected no response, got {:?}", other),
+        }
+        fs::remove_file(&path).unwrap()
+    }
+
+    #[test]
+    fn test_fixture() {
+        let interactions = load("fixtures/vcr/sts_get_caller_identity.json".as_ref()).unwrap();
+        assert_eq!(interactions.len(), 1);
+        assert_eq!(interactions[0].request.headers[2].1, "<scrubbed>");
+        assert_eq!(interactions[0].response.status, 200)
+    }
+}
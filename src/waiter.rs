@@ -0,0 +1,333 @@
+use std::cmp;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::error::{Error, Result};
+use crate::xml::Element;
+
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum WaiterState {
+    Success,
+    Failure,
+    Retry,
+}
+
+// Paths are slash separated like xml::Element::find, but fan out over every
+// matching child at each step, so "reservationSet/item/instancesSet/item/instanceState/name"
+// yields the state of every instance.
+#[derive(Clone,Debug,PartialEq)]
+pub enum Matcher {
+    Path(String, String),
+    PathAll(String, String),
+    PathAny(String, String),
+    Status(u16),
+    Error(String),
+}
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Acceptor {
+    pub state: WaiterState,
+    pub matcher: Matcher,
+}
+
+// What one poll of the operation produced, when it didn't fail outright.
+#[derive(Clone,Debug)]
+pub struct Observation {
+    pub status: u16,
+    pub body: Option<Element>,
+}
+
+#[derive(Clone,Debug)]
+pub struct Waiter {
+    name: String,
+    acceptors: Vec<Acceptor>,
+    min_delay: Duration,
+    max_delay: Duration,
+    timeout: Duration,
+}
+
+pub fn values(root: &Element, path: &str) -> Vec<String> {
+    let mut current = vec!(root);
+    for step in path.split('/').filter(|s| !s.is_empty()) {
+        current = current.iter().flat_map(|e| e.get_all(step).into_iter()).collect();
+    }
+    current.iter().map(|e| e.text.clone()).collect()
+}
+
+impl Matcher {
+    fn matches(&self, outcome: &Result<Observation>) -> bool {
+        match (self, outcome) {
+            (Matcher::Status(code), Ok(o)) => o.status == *code,
+            (Matcher::Status(code), Err(Error::Service(e))) => e.status == *code,
+            (Matcher::Error(code), Err(Error::Service(e))) => &e.code == code,
+            (Matcher::Path(path, expected), Ok(Observation { body: Some(body), .. })) => {
+                values(body, path).first() == Some(expected)
+            },
+            (Matcher::PathAll(path, expected), Ok(Observation { body: Some(body), .. })) => {
+                let vals = values(body, path);
+                !vals.is_empty() && vals.iter().all(|v| v == expected)
+            },
+            (Matcher::PathAny(path, expected), Ok(Observation { body: Some(body), .. })) => {
+                values(body, path).iter().any(|v| v == expected)
+            },
+            _ => false,
+        }
+    }
+}
+
+impl Waiter {
+    pub fn new(name: &str) -> Waiter {
+        Waiter {
+            name: name.to_string(),
+            acceptors: Vec::new(),
+            min_delay: Duration::from_secs(5),
+            max_delay: Duration::from_secs(120),
+            timeout: Duration::from_secs(600),
+        }
+    }
+
+    pub fn acceptor(mut self, state: WaiterState, matcher: Matcher) -> Waiter {
+        self.acceptors.push(Acceptor { state, matcher });
+        self
+    }
+
+    pub fn min_delay(mut self, delay: Duration) -> Waiter {
+        self.min_delay = delay;
+        self
+    }
+
+    pub fn max_delay(mut self, delay: Duration) -> Waiter {
+        self.max_delay = delay;
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Waiter {
+        self.timeout = timeout;
+        self
+    }
+
+    // The first matching acceptor wins. With none matching, an error ends the
+    // wait and anything else is polled again.
+    pub fn state(&self, outcome: &Result<Observation>) -> WaiterState {
+        for a in self.acceptors.iter() {
+            if a.matcher.matches(outcome) {
+                return a.state;
+            }
+        }
+        if outcome.is_err() { WaiterState::Failure } else { WaiterState::Retry }
+    }
+
+    // Exponential from min_delay, capped at max_delay, with jitter between the
+    // two. `roll` is in [0, 1).
+    pub fn delay(&self, attempt: u32, roll: f64) -> Duration {
+        let min = millis(self.min_delay);
+        let ceiling = cmp::min(millis(self.max_delay), min.saturating_mul(1u64 << cmp::min(attempt.saturating_sub(1), 32)));
+        let ceiling = cmp::max(ceiling, min);
+        Duration::from_millis(min + ((ceiling - min) as f64 * roll) as u64)
+    }
+
+    pub fn wait<F>(&self, mut operation: F) -> Result<()> where F: FnMut() -> Result<Observation> {
+        let start = Instant::now();
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let outcome = operation();
+            match self.state(&outcome) {
+                WaiterState::Success => return Ok(()),
+                WaiterState::Failure => {
+                    let reason = match outcome {
+                        Err(e) => e.to_string(),
+                        Ok(_) => "resource reached a failure state".to_string(),
+                    };
+                    return Err(Error::WaiterFailure(format!("{}: {}", self.name, reason)));
+                },
+                WaiterState::Retry => {},
+            }
+
+            let elapsed = start.elapsed();
+            if elapsed >= self.timeout {
+                return Err(Error::WaiterTimeout(self.name.clone()));
+            }
+            let remaining = self.timeout - elapsed;
+            let delay = cmp::min(self.delay(attempt, rand::random::<f64>()), remaining);
+            thread::sleep(delay);
+        }
+    }
+}
+
+fn millis(d: Duration) -> u64 {
+    d.as_secs() * 1000 + d.subsec_millis() as u64
+}
+
+fn path_all(path: &str, expected: &str) -> Matcher {
+    Matcher::PathAll(path.to_string(), expected.to_string())
+}
+
+fn path_any(path: &str, expected: &str) -> Matcher {
+    Matcher::PathAny(path.to_string(), expected.to_string())
+}
+
+const INSTANCE_STATE: &str = "reservationSet/item/instancesSet/item/instanceState/name";
+const STACK_STATUS: &str = "Stacks/member/StackStatus";
+
+// For DescribeInstances.
+pub fn instance_running() -> Waiter {
+    Waiter::new("InstanceRunning")
+        .min_delay(Duration::from_secs(15))
+        .acceptor(WaiterState::Success, path_all(INSTANCE_STATE, "running"))
+        .acceptor(WaiterState::Failure, path_any(INSTANCE_STATE, "shutting-down"))
+        .acceptor(WaiterState::Failure, path_any(INSTANCE_STATE, "terminated"))
+        .acceptor(WaiterState::Failure, path_any(INSTANCE_STATE, "stopping"))
+        .acceptor(WaiterState::Retry, Matcher::Error("InvalidInstanceID.NotFound".to_string()))
+}
+
+pub fn instance_stopped() -> Waiter {
+    Waiter::new("InstanceStopped")
+        .min_delay(Duration::from_secs(15))
+        .acceptor(WaiterState::Success, path_all(INSTANCE_STATE, "stopped"))
+        .acceptor(WaiterState::Failure, path_any(INSTANCE_STATE, "pending"))
+        .acceptor(WaiterState::Failure, path_any(INSTANCE_STATE, "terminated"))
+}
+
+pub fn instance_terminated() -> Waiter {
+    Waiter::new("InstanceTerminated")
+        .min_delay(Duration::from_secs(15))
+        .acceptor(WaiterState::Success, path_all(INSTANCE_STATE, "terminated"))
+        .acceptor(WaiterState::Failure, path_any(INSTANCE_STATE, "pending"))
+        .acceptor(WaiterState::Failure, path_any(INSTANCE_STATE, "stopping"))
+}
+
+// For HeadBucket.
+pub fn bucket_exists() -> Waiter {
+    Waiter::new("BucketExists")
+        .min_delay(Duration::from_secs(5))
+        .timeout(Duration::from_secs(100))
+        .acceptor(WaiterState::Success, Matcher::Status(200))
+        .acceptor(WaiterState::Success, Matcher::Status(301))
+        .acceptor(WaiterState::Success, Matcher::Status(403))
+        .acceptor(WaiterState::Retry, Matcher::Status(404))
+}
+
+pub fn bucket_not_exists() -> Waiter {
+    Waiter::new("BucketNotExists")
+        .min_delay(Duration::from_secs(5))
+        .timeout(Duration::from_secs(100))
+        .acceptor(WaiterState::Success, Matcher::Status(404))
+}
+
+// For DescribeStacks.
+pub fn stack_create_complete() -> Waiter {
+    let mut waiter = Waiter::new("StackCreateComplete")
+        .min_delay(Duration::from_secs(30))
+        .timeout(Duration::from_secs(3600))
+        .acceptor(WaiterState::Success, path_all(STACK_STATUS, "CREATE_COMPLETE"));
+    for status in ["CREATE_FAILED", "DELETE_COMPLETE", "DELETE_FAILED", "ROLLBACK_FAILED", "ROLLBACK_COMPLETE"].iter() {
+        waiter = waiter.acceptor(WaiterState::Failure, path_any(STACK_STATUS, status));
+    }
+    waiter.acceptor(WaiterState::Failure, Matcher::Error("ValidationError".to_string()))
+}
+
+pub fn stack_delete_complete() -> Waiter {
+    let mut waiter = Waiter::new("StackDeleteComplete")
+        .min_delay(Duration::from_secs(30))
+        .timeout(Duration::from_secs(3600))
+        .acceptor(WaiterState::Success, path_all(STACK_STATUS, "DELETE_COMPLETE"))
+        .acceptor(WaiterState::Success, Matcher::Error("ValidationError".to_string()));
+    for status in ["DELETE_FAILED", "CREATE_FAILED", "ROLLBACK_FAILED", "UPDATE_ROLLBACK_FAILED"].iter() {
+        waiter = waiter.acceptor(WaiterState::Failure, path_any(STACK_STATUS, status));
+    }
+    waiter
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Waiter, WaiterState, Matcher, Observation, instance_running, bucket_exists, values};
+    use crate::error::{Error, ServiceError};
+    use std::time::Duration;
+    use crate::xml::Element;
+
+    fn instances(states: &[&str]) -> Element {
+        let mut set = Element::new("instancesSet");
+        for s in states {
+            set = set.child(Element::new("item").child(Element::new("instanceState").text_child("name", s)));
+        }
+        Element::new("DescribeInstancesResponse").child(Element::new("reservationSet").child(Element::new("item").child(set)))
+    }
+
+    fn observed(body: Element) -> Result<Observation, Error> {
+        Ok(Observation { status: 200, body: Some(body) })
+    }
+
+    fn service_error(code: &str, status: u16) -> Result<Observation, Error> {
+        Err(Error::Service(ServiceError {
+            code: code.to_string(),
+            message: String::new(),
+            request_id: None,
+            status,
+        }))
+    }
+
+    #[test]
+    fn test_values_fan_out() {
+        assert_eq!(values(&instances(&["running", "pending"]), "reservationSet/item/instancesSet/item/instanceState/name"),
+                   vec!("running".to_string(), "pending".to_string()))
+    }
+
+    #[test]
+    fn test_instance_running_states() {
+        let waiter = instance_running();
+        assert_eq!(waiter.state(&observed(instances(&["running", "running"]))), WaiterState::Success);
+        assert_eq!(waiter.state(&observed(instances(&["running", "pending"]))), WaiterState::Retry);
+        assert_eq!(waiter.state(&observed(instances(&["pending", "terminated"]))), WaiterState::Failure);
+        assert_eq!(waiter.state(&service_error("InvalidInstanceID.NotFound", 400)), WaiterState::Retry);
+        assert_eq!(waiter.state(&service_error("AccessDenied", 403)), WaiterState::Failure)
+    }
+
+    #[test]
+    fn test_bucket_exists_states() {
+        let waiter = bucket_exists();
+        assert_eq!(waiter.state(&Ok(Observation { status: 200, body: None })), WaiterState::Success);
+        assert_eq!(waiter.state(&service_error("Http404", 404)), WaiterState::Retry);
+        assert_eq!(waiter.state(&service_error("AccessDenied", 403)), WaiterState::Success)
+    }
+
+    #[test]
+    fn test_delay() {
+        let waiter = Waiter::new("test").min_delay(Duration::from_secs(2)).max_delay(Duration::from_secs(10));
+        assert_eq!(waiter.delay(1, 0.5), Duration::from_secs(2));
+        assert_eq!(waiter.delay(2, 0.5), Duration::from_secs(3));
+        assert_eq!(waiter.delay(10, 0.0), Duration::from_secs(2));
+        assert_eq!(waiter.delay(10, 0.5), Duration::from_secs(6));
+        assert_eq!(waiter.delay(0, 0.5), Duration::from_secs(2))
+    }
+
+    #[test]
+    fn test_wait_polls_until_success() {
+        let waiter = instance_running().min_delay(Duration::from_millis(1)).max_delay(Duration::from_millis(2));
+        let mut polls = vec!(instances(&["running"]), instances(&["pending"]), instances(&["pending"]));
+        let result = waiter.wait(|| observed(polls.pop().unwrap()));
+        assert!(result.is_ok());
+        assert_eq!(polls.len(), 0)
+    }
+
+    #[test]
+    fn test_wait_failure() {
+        let waiter = instance_running().min_delay(Duration::from_millis(1));
+        match waiter.wait(|| observed(instances(&["terminated"]))) {
+            Err(Error::WaiterFailure(_)) => {},
+            other => panic!("expected failure, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_wait_timeout() {
+        let waiter = Waiter::new("never")
+            .acceptor(WaiterState::Success, Matcher::Status(204))
+            .min_delay(Duration::from_millis(5))
+            .timeout(Duration::from_millis(20));
+        match waiter.wait(|| Ok(Observation { status: 200, body: None })) {
+            Err(Error::WaiterTimeout(ref name)) => assert_eq!(name, "never"),
+            other => panic!("expected timeout, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,115 @@
+use crate::signers::sigv4::SigV4;
+use crate::transport::HttpRequest;
+
+// Opt-in logging of what's signed and sent, for chasing signature mismatches.
+// Secrets are redacted; the string to sign holds none.
+
+const REDACTED: &str = "<redacted>";
+
+const SECRET_HEADERS: &[&str] = &["authorization", "proxy-authorization", "x-amz-security-token"];
+
+const SECRET_PARAMS: &[&str] = &["X-Amz-Credential", "X-Amz-Security-Token", "X-Amz-Signature"];
+
+pub fn redact_query(query: &str) -> String {
+    query.split('&').map(|param| {
+        let key = param.split('=').next().unwrap_or("");
+        if SECRET_PARAMS.iter().any(|p| p.eq_ignore_ascii_case(key)) {
+            format!("{}={}", key, REDACTED)
+        } else {
+            param.to_string()
+        }
+    }).collect::<Vec<String>>().join("&")
+}
+
+pub fn redact_url(url: &str) -> String {
+    match url.find('?') {
+        Some(i) => format!("{}?{}", &url[..i], redact_query(&url[i + 1..])),
+        None => url.to_string(),
+    }
+}
+
+// Keeps the scope and signed headers of an Authorization header, which are
+// what's useful when comparing with the service's canonical request.
+pub fn redact_header(name: &str, value: &str) -> String {
+    if name.eq_ignore_ascii_case("authorization") && value.starts_with("AWS4-HMAC-SHA256 ") {
+        value.split(", ").map(|part| {
+            if let Some(i) = part.find("Credential=") {
+                let scope = part[i..].find('/').map(|j| &part[i + j..]).unwrap_or("");
+                format!("{}Credential={}{}", &part[..i], REDACTED, scope)
+            } else if part.starts_with("Signature=") {
+                format!("Signature={}", REDACTED)
+            } else {
+                part.to_string()
+            }
+        }).collect::<Vec<String>>().join(", ")
+    } else if SECRET_HEADERS.iter().any(|h| name.eq_ignore_ascii_case(h)) {
+        REDACTED.to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+// The third line of a canonical request is the query, followed by one
+// "name:value" line per signed header.
+pub fn redact_canonical_request(canonical: &str) -> String {
+    let mut in_headers = true;
+    canonical.split('\n').enumerate().map(|(i, line)| {
+        if i == 2 {
+            return redact_query(line);
+        }
+        if i < 3 || !in_headers {
+            return line.to_string();
+        }
+        if line.is_empty() {
+            in_headers = false;
+            return String::new();
+        }
+        let name = line.split(':').next().unwrap_or("");
+        if SECRET_HEADERS.contains(&name) {
+            format!("{}:{}", name, REDACTED)
+        } else {
+            line.to_string()
+        }
+    }).collect::<Vec<String>>().join("\n")
+}
+
+pub fn log_signing(sig: &SigV4) {
+    debug!(target: "aws::wire",
+           canonical_request = %redact_canonical_request(&sig.signed_canonical_request()),
+           string_to_sign = %sig.string_to_sign(),
+           "signing");
+}
+
+pub fn log_request(req: &HttpRequest) {
+    let headers: Vec<String> = req.headers.iter()
+        .map(|(k, v)| format!("{}: {}", k, redact_header(k, v)))
+        .collect();
+    debug!(target: "aws::wire", method = %req.method, url = %redact_url(&req.url), headers = ?headers, "sending");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{redact_query, redact_header, redact_canonical_request};
+
+    #[test]
+    fn test_redact_query() {
+        assert_eq!(redact_query("X-Amz-Algorithm=AWS4-HMAC-SHA256&X-Amz-Credential=AKID%2F20150830&X-Amz-Signature=abc"),
+                   "X-Amz-Algorithm=AWS4-HMAC-SHA256&X-Amz-Credential=<redacted>&X-Amz-Signature=<redacted>")
+    }
+
+    #[test]
+    fn test_redact_authorization() {
+        let auth = "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/iam/aws4_request, SignedHeaders=host;x-amz-date, Signature=5d672d79c15b13162d9279b0855cfba6789a8edb4c82c400e06b5924a6f2b5d7";
+        assert_eq!(redact_header("Authorization", auth),
+                   "AWS4-HMAC-SHA256 Credential=<redacted>/20150830/us-east-1/iam/aws4_request, SignedHeaders=host;x-amz-date, Signature=<redacted>");
+        assert_eq!(redact_header("X-Amz-Security-Token", "FQoGZXIvYXdzE"), "<redacted>");
+        assert_eq!(redact_header("Host", "iam.amazonaws.com"), "iam.amazonaws.com")
+    }
+
+    #[test]
+    fn test_redact_canonical_request() {
+        let canonical = "GET\n/\nAction=ListUsers&X-Amz-Signature=abc\nhost:iam.amazonaws.com\nx-amz-security-token:FQoGZXIvYXdzE\n\nhost;x-amz-security-token\ne3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+        assert_eq!(redact_canonical_request(canonical),
+                   "GET\n/\nAction=ListUsers&X-Amz-Signature=<redacted>\nhost:iam.amazonaws.com\nx-amz-security-token:<redacted>\n\nhost;x-amz-security-token\ne3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855")
+    }
+}
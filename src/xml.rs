@@ -0,0 +1,420 @@
+use std::char;
+use std::fmt;
+
+// A minimal element tree; enough for the documents AWS sends and expects.
+// Namespace prefixes are dropped from element names.
+#[derive(Clone,Debug,PartialEq)]
+pub struct Element {
+    pub name: String,
+    pub attributes: Vec<(String, String)>,
+    pub children: Vec<Element>,
+    pub text: String,
+}
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct ParseError {
+    pub position: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at byte {}", self.message, self.position)
+    }
+}
+
+impl Element {
+    pub fn new(name: &str) -> Element {
+        Element {
+            name: name.to_string(),
+            attributes: Vec::new(),
+            children: Vec::new(),
+            text: String::new(),
+        }
+    }
+
+    pub fn attribute(mut self, name: &str, value: &str) -> Element {
+        self.attributes.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn child(mut self, child: Element) -> Element {
+        self.children.push(child);
+        self
+    }
+
+    pub fn text(mut self, text: &str) -> Element {
+        self.text = text.to_string();
+        self
+    }
+
+    // Shorthand for a child holding only text, e.g. <Key>a.jpg</Key>.
+    pub fn text_child(self, name: &str, text: &str) -> Element {
+        self.child(Element::new(name).text(text))
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Element> {
+        self.children.iter().find(|c| c.name == name)
+    }
+
+    pub fn get_all(&self, name: &str) -> Vec<&Element> {
+        self.children.iter().filter(|c| c.name == name).collect()
+    }
+
+    // Follows a slash separated path of child names, e.g. "Error/Code".
+    pub fn find(&self, path: &str) -> Option<&Element> {
+        let mut current = self;
+        for name in path.split('/').filter(|n| !n.is_empty()) {
+            match current.get(name) {
+                Some(next) => current = next,
+                None => return None,
+            }
+        }
+        Some(current)
+    }
+
+    // All the elements matching the last step of the path, e.g. "Users/member".
+    pub fn find_all(&self, path: &str) -> Vec<&Element> {
+        match path.rfind('/') {
+            Some(i) => match self.find(&path[..i]) {
+                Some(parent) => parent.get_all(&path[i + 1..]),
+                None => Vec::new(),
+            },
+            None => self.get_all(path),
+        }
+    }
+
+    pub fn find_text(&self, path: &str) -> Option<String> {
+        self.find(path).map(|e| e.text.clone())
+    }
+
+    pub fn parse(input: &str) -> Result<Element, ParseError> {
+        let mut parser = Parser { input: input.as_bytes(), pos: 0 };
+        parser.skip_prolog();
+        let root = parser.element(1)?;
+        parser.skip_misc();
+        if parser.pos < parser.input.len() {
+            return Err(parser.error("trailing content after root element"));
+        }
+        Ok(root)
+    }
+
+    pub fn to_xml(&self) -> String {
+        let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>");
+        self.write(&mut out);
+        out
+    }
+
+    fn write(&self, out: &mut String) {
+        out.push('<');
+        out.push_str(&self.name);
+        for (k, v) in self.attributes.iter() {
+            out.push_str(&format!(" {}=\"{}\"", k, escape(v)));
+        }
+        if self.children.is_empty() && self.text.is_empty() {
+            out.push_str("/>");
+            return;
+        }
+        out.push('>');
+        out.push_str(&escape(&self.text));
+        for c in self.children.iter() {
+            c.write(out);
+        }
+        out.push_str(&format!("</{}>", self.name));
+    }
+}
+
+pub fn escape(input: &str) -> String {
+    let mut out = String::with_capacity(input.len());
+    for c in input.chars() {
+        match c {
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '&' => out.push_str("&amp;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            '\r' => out.push_str("&#xD;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+// Deeper than any AWS response, and shallow enough that a hostile document
+// can't exhaust the stack.
+const MAX_DEPTH: usize = 100;
+
+struct Parser<'a> {
+    input: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, message: &str) -> ParseError {
+        ParseError {
+            position: self.pos,
+            message: message.to_string(),
+        }
+    }
+
+    fn starts_with(&self, s: &str) -> bool {
+        self.input[self.pos..].starts_with(s.as_bytes())
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.pos < self.input.len() && (self.input[self.pos] as char).is_whitespace() {
+            self.pos += 1;
+        }
+    }
+
+    fn skip_past(&mut self, end: &str) -> Result<(), ParseError> {
+        while self.pos < self.input.len() {
+            if self.starts_with(end) {
+                self.pos += end.len();
+                return Ok(());
+            }
+            self.pos += 1;
+        }
+        Err(self.error(&format!("expected {}", end)))
+    }
+
+    // declarations, comments and doctypes before the root element
+    fn skip_prolog(&mut self) {
+        self.skip_misc();
+        if self.starts_with("<!DOCTYPE") {
+            let _ = self.skip_past(">");
+            self.skip_misc();
+        }
+    }
+
+    fn skip_misc(&mut self) {
+        loop {
+            self.skip_whitespace();
+            if self.starts_with("<?") {
+                if self.skip_past("?>").is_err() { return; }
+            } else if self.starts_with("<!--") {
+                if self.skip_past("-->").is_err() { return; }
+            } else {
+                return;
+            }
+        }
+    }
+
+    fn name(&mut self) -> Result<String, ParseError> {
+        let start = self.pos;
+        while self.pos < self.input.len() {
+            match self.input[self.pos] {
+                b' ' | b'\t' | b'\r' | b'\n' | b'/' | b'>' | b'=' => break,
+                _ => self.pos += 1,
+            }
+        }
+        if start == self.pos {
+            return Err(self.error("expected a name"));
+        }
+        Ok(String::from_utf8_lossy(&self.input[start..self.pos]).into_owned())
+    }
+
+    fn element(&mut self, depth: usize) -> Result<Element, ParseError> {
+        if !self.starts_with("<") {
+            return Err(self.error("expected an element"));
+        }
+        self.pos += 1;
+        let qname = self.name()?;
+        let mut element = Element::new(local_name(&qname));
+
+        loop {
+            self.skip_whitespace();
+            if self.starts_with("/>") {
+                self.pos += 2;
+                return Ok(element);
+            }
+            if self.starts_with(">") {
+                self.pos += 1;
+                break;
+            }
+            let attr = self.name()?;
+            self.skip_whitespace();
+            if !self.starts_with("=") {
+                return Err(self.error("expected '=' after attribute name"));
+            }
+            self.pos += 1;
+            self.skip_whitespace();
+            let value = self.quoted()?;
+            element.attributes.push((attr, value));
+        }
+
+        loop {
+            if self.pos >= self.input.len() {
+                return Err(self.error(&format!("unclosed element {}", qname)));
+            }
+            if self.starts_with("</") {
+                self.pos += 2;
+                let close = self.name()?;
+                if close != qname {
+                    return Err(self.error(&format!("expected </{}>, found </{}>", qname, close)));
+                }
+                self.skip_whitespace();
+                if !self.starts_with(">") {
+                    return Err(self.error("expected '>'"));
+                }
+                self.pos += 1;
+                // whitespace between child elements isn't content
+                if !element.children.is_empty() && element.text.trim().is_empty() {
+                    element.text = String::new();
+                }
+                return Ok(element);
+            } else if self.starts_with("<!--") {
+                self.skip_past("-->")?;
+            } else if self.starts_with("<![CDATA[") {
+                self.pos += 9;
+                let start = self.pos;
+                self.skip_past("]]>")?;
+                let cdata = String::from_utf8_lossy(&self.input[start..self.pos - 3]).into_owned();
+                element.text.push_str(&cdata);
+            } else if self.starts_with("<?") {
+                self.skip_past("?>")?;
+            } else if self.starts_with("<") {
+                if depth == MAX_DEPTH {
+                    return Err(self.error("elements nested too deeply"));
+                }
+                let child = self.element(depth + 1)?;
+                element.children.push(child);
+            } else {
+                let text = self.text_until(b'<')?;
+                element.text.push_str(&text);
+            }
+        }
+    }
+
+    fn quoted(&mut self) -> Result<String, ParseError> {
+        let quote = match self.input.get(self.pos) {
+            Some(&b'"') => b'"',
+            Some(&b'\'') => b'\'',
+            _ => return Err(self.error("expected a quoted value")),
+        };
+        self.pos += 1;
+        let value = self.text_until(quote)?;
+        if self.pos >= self.input.len() {
+            return Err(self.error("unterminated attribute value"));
+        }
+        self.pos += 1;
+        Ok(value)
+    }
+
+    fn text_until(&mut self, end: u8) -> Result<String, ParseError> {
+        let start = self.pos;
+        while self.pos < self.input.len() && self.input[self.pos] != end {
+            self.pos += 1;
+        }
+        let raw = String::from_utf8_lossy(&self.input[start..self.pos]).into_owned();
+        unescape(&raw).map_err(|e| ParseError { position: start, message: e })
+    }
+}
+
+fn local_name(name: &str) -> &str {
+    match name.rfind(':') {
+        Some(i) => &name[i + 1..],
+        None => name,
+    }
+}
+
+fn unescape(input: &str) -> Result<String, String> {
+    let mut out = String::with_capacity(input.len());
+    let mut rest = input;
+    while let Some(i) = rest.find('&') {
+        out.push_str(&rest[..i]);
+        rest = &rest[i..];
+        let end = match rest.find(';') {
+            Some(end) => end,
+            None => return Err("unterminated entity".to_string()),
+        };
+        let entity = &rest[1..end];
+        match entity {
+            "lt" => out.push('<'),
+            "gt" => out.push('>'),
+            "amp" => out.push('&'),
+            "quot" => out.push('"'),
+            "apos" => out.push('\''),
+            _ => {
+                let code = if let Some(hex) = entity.strip_prefix("#x") {
+                    u32::from_str_radix(hex, 16).ok()
+                } else if let Some(dec) = entity.strip_prefix('#') {
+                    dec.parse::<u32>().ok()
+                } else {
+                    None
+                };
+                match code.and_then(char::from_u32) {
+                    Some(c) => out.push(c),
+                    None => return Err(format!("unknown entity &{};", entity)),
+                }
+            },
+        }
+        rest = &rest[end + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Element, MAX_DEPTH};
+
+    #[test]
+    fn test_parse() {
+        let doc = Element::parse(r#"<?xml version="1.0" encoding="UTF-8"?>
+<ListBucketResult xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+    <Name>bucket</Name>
+    <!-- a comment -->
+    <Contents><Key>a &amp; b.jpg</Key><Size>10</Size></Contents>
+    <Contents><Key><![CDATA[<c>.jpg]]></Key></Contents>
+    <IsTruncated/>
+</ListBucketResult>"#).unwrap();
+
+        assert_eq!(doc.name, "ListBucketResult");
+        assert_eq!(doc.attributes[0].1, "http://s3.amazonaws.com/doc/2006-03-01/");
+        assert_eq!(doc.text, "");
+        assert_eq!(doc.find_text("Name"), Some("bucket".to_string()));
+        assert_eq!(doc.find_text("Contents/Key"), Some("a & b.jpg".to_string()));
+        assert_eq!(doc.get_all("Contents")[1].find_text("Key"), Some("<c>.jpg".to_string()));
+        assert_eq!(doc.find_all("Contents").len(), 2);
+        assert_eq!(doc.find_all("Contents/Key").len(), 1);
+        assert!(doc.get("IsTruncated").is_some())
+    }
+
+    #[test]
+    fn test_parse_strips_prefixes() {
+        let doc = Element::parse("<s:Envelope><s:Body>&#65;&#x42;</s:Body></s:Envelope>").unwrap();
+        assert_eq!(doc.find_text("Body"), Some("AB".to_string()))
+    }
+
+    #[test]
+    fn test_parse_mismatched() {
+        assert!(Element::parse("<a><b></a>").is_err());
+        assert!(Element::parse("<a>").is_err());
+        assert!(Element::parse("<a></a><b/>").is_err())
+    }
+
+    #[test]
+    fn test_parse_depth_limit() {
+        let nested = |depth: usize| format!("{}{}", "<a>".repeat(depth), "</a>".repeat(depth));
+        assert!(Element::parse(&nested(MAX_DEPTH)).is_ok());
+        assert!(Element::parse(&nested(MAX_DEPTH + 1)).is_err());
+        assert!(Element::parse(&"<a>".repeat(1000000)).is_err())
+    }
+
+    #[test]
+    fn test_write() {
+        let doc = Element::new("CreateBucketConfiguration")
+            .attribute("xmlns", "http://s3.amazonaws.com/doc/2006-03-01/")
+            .text_child("LocationConstraint", "eu-west-1")
+            .child(Element::new("Empty"))
+            .text_child("Escaped", "a<b & \"c\"");
+        assert_eq!(doc.to_xml(), "<?xml version=\"1.0\" encoding=\"UTF-8\"?><CreateBucketConfiguration xmlns=\"http://s3.amazonaws.com/doc/2006-03-01/\"><LocationConstraint>eu-west-1</LocationConstraint><Empty/><Escaped>a&lt;b &amp; &quot;c&quot;</Escaped></CreateBucketConfiguration>")
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let doc = Element::new("a").child(Element::new("b").text("1")).text_child("c", "x&y");
+        assert_eq!(Element::parse(&doc.to_xml()).unwrap(), doc)
+    }
+}